@@ -0,0 +1,6 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use ppm_prototype::aggregate::AggregateResp;
+use ppm_prototype_fuzz::check_round_trip;
+
+fuzz_target!(|data: &[u8]| check_round_trip::<AggregateResp>(data));
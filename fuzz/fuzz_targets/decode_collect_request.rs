@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use ppm_prototype::collect::CollectRequest;
+use ppm_prototype_fuzz::check_round_trip;
+use prio::vdaf::prio3::Prio3Aes128Count;
+
+fuzz_target!(|data: &[u8]| check_round_trip::<CollectRequest<Prio3Aes128Count>>(data));
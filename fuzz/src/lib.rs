@@ -0,0 +1,16 @@
+//! Harness shared by the fuzz targets
+
+use prio::codec::{Decode, Encode};
+
+/// Decode `data` as a `T` and, if it is a valid encoding, check that encoding
+/// the decoded value yields exactly `data`. Decoding must never panic, whatever
+/// `data` is.
+pub fn check_round_trip<T: Decode + Encode>(data: &[u8]) {
+    if let Ok(decoded) = T::get_decoded(data) {
+        assert_eq!(
+            decoded.get_encoded(),
+            data,
+            "re-encoding decoded value changed its encoding"
+        );
+    }
+}
@@ -1,11 +1,16 @@
 //! The aggregate portion of the PPM protocol, per §4.3 of RFCXXXX
 
 use crate::{
+    dp::NoisyAggregateShare,
     error::{IntoHttpApiProblem, ProblemDocumentType},
     hpke,
-    parameters::{Parameters, TaskId},
-    report::{self, Report},
-    Interval, Nonce, Role,
+    metrics::Metrics,
+    parameters::{Parameters, QueryType, TaskId},
+    report::{self, ExtensionRegistry, Report},
+    status::{
+        BatchFailures, BatchStatus, CollectAuditEntry, TaskCollectAudit, TaskFailures, TaskStatus,
+    },
+    BatchId, BatchSelector, Interval, Nonce, Role, Time,
 };
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use prio::{
@@ -13,12 +18,13 @@ use prio::{
     vdaf::{self, Aggregatable, PrepareTransition},
 };
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, VecDeque},
     convert::TryFrom,
     fmt::Debug,
     io::{Cursor, Read},
+    sync::Arc,
 };
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -28,7 +34,7 @@ pub enum Error {
     Encryption(#[from] crate::hpke::Error),
     #[error("I/O error")]
     Io(#[from] std::io::Error),
-    #[error("Report error")]
+    #[error("Report error: {0}")]
     Report(#[from] crate::report::Error),
     #[error("VDAF error {0}")]
     Vdaf(#[from] prio::vdaf::VdafError),
@@ -36,14 +42,24 @@ pub enum Error {
     InvalidBatchInterval(Interval),
     #[error("insufficient batch size {0}")]
     InsufficientBatchSize(u64),
-    #[error("request exceeds the batch's privacy budget")]
-    PrivacyBudgetExceeded,
+    #[error("request exceeds the privacy budget of {0}")]
+    PrivacyBudgetExceeded(BatchSelector),
+    #[error("query type does not match the task's query type")]
+    QueryMismatch,
+    #[error("aggregation parameter does not match the task's aggregation parameter")]
+    AggregationParameterMismatch,
     #[error("Codec error {0}")]
     Codec(String),
     #[error("Parameters error")]
     Parameters(#[from] crate::parameters::Error),
     #[error("Stale report: {0}")]
     StaleReport(Nonce),
+    #[error("expired report: {0}")]
+    ExpiredReport(Nonce),
+    #[error("batch {0} has already been collected")]
+    BatchCollected(BatchSelector),
+    #[error("report time not aligned to task's time precision: {0}")]
+    UnalignedReportTime(Nonce),
     #[error("unknown HPKE config ID {0:?}")]
     UnknownHpkeConfig(hpke::ConfigId),
     #[error("unrecognized task ID")]
@@ -52,6 +68,10 @@ pub enum Error {
     CodecError(#[from] prio::codec::CodecError),
     #[error("unexpected prepare state transition: {0}")]
     UnexpectedStateTransition(String),
+    #[error("differential privacy error {0}")]
+    DifferentialPrivacy(#[from] crate::dp::Error),
+    #[error("metrics error {0}")]
+    Metrics(#[from] crate::metrics::Error),
 }
 
 impl IntoHttpApiProblem for Error {
@@ -61,10 +81,16 @@ impl IntoHttpApiProblem for Error {
             Self::Encryption(_) => Some(ProblemDocumentType::UnrecognizedMessage),
             Self::InvalidBatchInterval(_) => Some(ProblemDocumentType::InvalidBatchInterval),
             Self::InsufficientBatchSize(_) => Some(ProblemDocumentType::InsufficientBatchSize),
-            Self::PrivacyBudgetExceeded => Some(ProblemDocumentType::PrivacyBudgetExceeded),
+            Self::PrivacyBudgetExceeded(_) => Some(ProblemDocumentType::PrivacyBudgetExceeded),
+            Self::QueryMismatch => Some(ProblemDocumentType::QueryMismatch),
+            Self::AggregationParameterMismatch => Some(ProblemDocumentType::UnrecognizedMessage),
             Self::StaleReport(_) => Some(ProblemDocumentType::StaleReport),
+            Self::ExpiredReport(_) => Some(ProblemDocumentType::StaleReport),
+            Self::BatchCollected(_) => Some(ProblemDocumentType::StaleReport),
+            Self::UnalignedReportTime(_) => Some(ProblemDocumentType::UnrecognizedMessage),
             Self::UnknownHpkeConfig(_) => Some(ProblemDocumentType::OutdatedConfig),
             Self::UnrecognizedTask(_) => Some(ProblemDocumentType::UnrecognizedTask),
+            Self::Report(e) => e.problem_document_type(),
             _ => None,
         }
     }
@@ -74,9 +100,18 @@ impl From<Error> for TransitionError {
     fn from(e: Error) -> Self {
         match e {
             Error::StaleReport(_) => TransitionError::BatchCollected,
+            Error::ExpiredReport(_) => TransitionError::ReportDropped,
+            Error::BatchCollected(_) => TransitionError::BatchCollected,
+            Error::UnalignedReportTime(_) => TransitionError::ReportDropped,
             Error::UnknownHpkeConfig(_) => TransitionError::HpkeUnknownConfigId,
             Error::Encryption(_) => TransitionError::HpkeDecryptError,
             Error::Vdaf(_) => TransitionError::VdafPrepError,
+            Error::Report(report::Error::UnrecognizedExtension(_)) => {
+                TransitionError::UnrecognizedExtension
+            }
+            Error::Report(
+                report::Error::InvalidExtension(_, _) | report::Error::DuplicateExtension(_),
+            ) => TransitionError::ReportDropped,
             unhandled_error => {
                 warn!(?unhandled_error, "unhandled error!");
                 TransitionError::ReportDropped
@@ -170,7 +205,7 @@ impl Decode for Transition {
 // There are fewer than 255 possible values of `TransitionError`, and so per RFC
 // 8446 a value occupies one byte on the wire.
 // https://datatracker.ietf.org/doc/html/rfc8446#section-3.5
-#[derive(Clone, Copy, Debug, IntoPrimitive, TryFromPrimitive)]
+#[derive(Clone, Copy, Debug, Eq, Hash, IntoPrimitive, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum TransitionError {
     BatchCollected = 0,
@@ -180,6 +215,7 @@ pub enum TransitionError {
     HpkeDecryptError = 4,
     VdafPrepError = 5,
     UnrecognizedNonce = 6,
+    UnrecognizedExtension = 7,
 }
 
 /// A state transition message exchanged between leader and helper
@@ -266,10 +302,43 @@ impl Decode for Aggregate {
     }
 }
 
+/// Tells the helper which batch the reports in an aggregation job belong to.
+/// In time interval tasks this follows from each report's timestamp, so only
+/// fixed size tasks carry a batch ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialBatchSelector {
+    TimeInterval,
+    FixedSize(BatchId),
+}
+
+impl Encode for PartialBatchSelector {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        // The discriminant is the query type, as in `collect::Query`
+        match self {
+            Self::TimeInterval => 1u8.encode(bytes),
+            Self::FixedSize(batch_id) => {
+                2u8.encode(bytes);
+                batch_id.encode(bytes);
+            }
+        }
+    }
+}
+
+impl Decode for PartialBatchSelector {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        match u8::decode(bytes)? {
+            1 => Ok(Self::TimeInterval),
+            2 => Ok(Self::FixedSize(BatchId::decode(bytes)?)),
+            _ => Err(CodecError::UnexpectedValue),
+        }
+    }
+}
+
 /// AggregateInitReq message
 #[derive(Clone, Debug)]
 pub struct AggregateInitReq {
     pub task_id: TaskId,
+    pub partial_batch_selector: PartialBatchSelector,
     pub aggregation_parameter: Vec<u8>,
     pub report_shares: Vec<ReportShare>,
 }
@@ -277,6 +346,7 @@ pub struct AggregateInitReq {
 impl Encode for AggregateInitReq {
     fn encode(&self, bytes: &mut Vec<u8>) {
         self.task_id.encode(bytes);
+        self.partial_batch_selector.encode(bytes);
         encode_u16_items(bytes, &(), &self.aggregation_parameter);
         encode_u16_items(bytes, &(), &self.report_shares);
     }
@@ -285,11 +355,13 @@ impl Encode for AggregateInitReq {
 impl Decode for AggregateInitReq {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
         let task_id = TaskId::decode(bytes)?;
+        let partial_batch_selector = PartialBatchSelector::decode(bytes)?;
         let aggregation_parameter = decode_u16_items(&(), bytes)?;
         let report_shares = decode_u16_items(&(), bytes)?;
 
         Ok(Self {
             task_id,
+            partial_batch_selector,
             aggregation_parameter,
             report_shares,
         })
@@ -356,24 +428,24 @@ impl Decode for AggregateResp {
 #[derive(Clone, Debug)]
 pub struct AggregateShareReq {
     pub task_id: TaskId,
-    pub batch_interval: Interval,
+    pub batch_selector: BatchSelector,
 }
 
 impl Encode for AggregateShareReq {
     fn encode(&self, bytes: &mut Vec<u8>) {
         self.task_id.encode(bytes);
-        self.batch_interval.encode(bytes);
+        self.batch_selector.encode(bytes);
     }
 }
 
 impl Decode for AggregateShareReq {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
         let task_id = TaskId::decode(bytes)?;
-        let batch_interval = Interval::decode(bytes)?;
+        let batch_selector = BatchSelector::decode(bytes)?;
 
         Ok(Self {
             task_id,
-            batch_interval,
+            batch_selector,
         })
     }
 }
@@ -394,6 +466,18 @@ impl Encode for AggregateMessage {
     }
 }
 
+impl AggregateMessage {
+    /// The task that the message concerns, if it is a request
+    pub fn task_id(&self) -> Option<TaskId> {
+        match &self.aggregate {
+            Aggregate::Initialize(req) => Some(req.task_id),
+            Aggregate::Request(req) => Some(req.task_id),
+            Aggregate::ShareRequest(req) => Some(req.task_id),
+            Aggregate::Response(_) | Aggregate::ShareResponse(_) => None,
+        }
+    }
+}
+
 impl Decode for AggregateMessage {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
         let aggregate = Aggregate::decode(bytes)?;
@@ -404,27 +488,81 @@ impl Decode for AggregateMessage {
     }
 }
 
-/// Accumulator for some batch interval
+/// Accumulator for an interval of `min_batch_duration`, or for a fixed size
+/// batch
 #[derive(Clone, Debug)]
 pub(crate) struct Accumulator<S> {
     /// The value accumulated thus far. S will be some VDAF's AggregateShare type.
     pub(crate) accumulated: S,
     /// How many contributions are included
     pub(crate) contributions: u64,
-    /// Consumed privacy budget for the aggregation interval. Measured in number
+    /// Consumed privacy budget for the interval or batch. Measured in number
     /// of queries.
     pub(crate) consumed_privacy_budget: u64,
+    /// When the first contribution was accumulated
+    pub(crate) created: Time,
 }
 
-pub(crate) fn dump_accumulators<S: Debug>(accumulators: &HashMap<Interval, Accumulator<S>>) {
+/// Reports in a batch that failed to be prepared by either aggregator
+#[derive(Clone, Debug)]
+struct ReportFailures {
+    /// How many reports failed for each reason
+    reasons: HashMap<TransitionError, u64>,
+    /// When the first report failed
+    created: Time,
+}
+
+pub(crate) fn dump_accumulators<S: Debug>(accumulators: &HashMap<BatchSelector, Accumulator<S>>) {
     if accumulators.is_empty() {
-        info!("accumulators are empty");
+        debug!("accumulators are empty");
     }
-    for (interval, accumulated) in accumulators {
-        info!(?interval, ?accumulated, "accumulated value for interval");
+    for (batch, accumulated) in accumulators {
+        debug!(%batch, ?accumulated, "accumulated value for batch");
     }
 }
 
+/// Outcome of a query against an aggregator's aggregate shares
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CollectOutcome {
+    /// The aggregate share was released, consuming privacy budget
+    Collected { report_count: u64 },
+    /// The query was refused or failed, and no privacy budget was consumed
+    Rejected { reason: String },
+}
+
+/// Most queries kept in an aggregator's audit log. The oldest are dropped once
+/// there are more, even if their batches remain.
+const MAX_COLLECT_AUDIT_RECORDS: usize = 10000;
+
+/// Record of a query against an aggregator's aggregate shares, kept so that
+/// operators can tell how each batch interval's privacy budget was spent
+#[derive(Clone, Debug)]
+pub struct CollectAuditRecord {
+    pub batch: BatchSelector,
+    /// Encoded aggregation parameter
+    pub aggregation_parameter: Vec<u8>,
+    /// When the aggregator received the query
+    pub timestamp: Time,
+    pub outcome: CollectOutcome,
+}
+
+/// An aggregate share that has been extracted from the accumulators but whose
+/// privacy budget has not yet been consumed
+#[derive(Debug)]
+pub(crate) struct PendingAggregateShare {
+    /// The aggregate share, encrypted to the collector
+    pub(crate) ciphertext: hpke::Ciphertext,
+    /// How many reports are included in the aggregate share
+    pub(crate) report_count: u64,
+    pub(crate) batch: BatchSelector,
+    /// Encoded aggregation parameter the query asked for
+    aggregation_parameter: Vec<u8>,
+    timestamp: Time,
+    /// The accumulators making up the batch: intervals of `min_batch_duration`
+    /// for a time interval batch or the batch itself for a fixed size one
+    accumulators: Vec<BatchSelector>,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct Aggregator<A: vdaf::Aggregator> {
     role: Role,
@@ -432,13 +570,25 @@ pub(crate) struct Aggregator<A: vdaf::Aggregator> {
     pub aggregator: A,
     pub verify_parameter: A::VerifyParam,
     aggregation_parameter: A::AggregationParam,
-    /// The batch intervals for which this aggregator has received either a
-    /// collect request or an aggregate share request, depending on the role.
-    collected_batch_intervals: HashSet<Interval>,
+    /// The intervals of `min_batch_duration` or fixed size batches for which
+    /// this aggregator has received either a collect request or an aggregate
+    /// share request, depending on the role, and when it first did so.
+    collected_batches: HashMap<BatchSelector, Time>,
     task_parameters: Parameters,
     /// Accumulated sums over inputs that have been verified in conjunction with
-    /// the helper. The key is the batch interval.
-    accumulators: HashMap<Interval, Accumulator<A::AggregateShare>>,
+    /// the helper. The key is the interval of `min_batch_duration` or the fixed
+    /// size batch the inputs belong to.
+    accumulators: HashMap<BatchSelector, Accumulator<A::AggregateShare>>,
+    /// Every query made against this aggregator's aggregate shares
+    collect_audit_log: VecDeque<CollectAuditRecord>,
+    /// Report extensions this aggregator recognizes
+    extension_registry: Arc<ExtensionRegistry>,
+    metrics: Metrics,
+    /// How many reports failed to be prepared by either aggregator
+    failed_reports: u64,
+    /// Failed reports in each interval of `min_batch_duration` or fixed size
+    /// batch, or under `None` if the report's batch isn't known
+    report_failures: HashMap<Option<BatchSelector>, ReportFailures>,
 }
 
 impl<A: vdaf::Aggregator> Aggregator<A> {
@@ -449,46 +599,209 @@ impl<A: vdaf::Aggregator> Aggregator<A> {
         verify_parameter: &A::VerifyParam,
         aggregation_parameter: &A::AggregationParam,
         task_parameters: &Parameters,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         // TODO: construct accumulators here once we stop storing them in
         // state blob
         // TODO: construct aggregator here from task_parameters
-        Self {
+        Ok(Self {
             role,
             hpke_config: hpke_config.clone(),
             aggregator: aggregator.clone(),
-            collected_batch_intervals: HashSet::new(),
+            collected_batches: HashMap::new(),
             verify_parameter: verify_parameter.clone(),
             task_parameters: task_parameters.clone(),
             aggregation_parameter: aggregation_parameter.clone(),
             accumulators: HashMap::new(),
+            collect_audit_log: VecDeque::new(),
+            extension_registry: Arc::new(ExtensionRegistry::default()),
+            metrics: Metrics::new(role, task_parameters.task_id)?,
+            failed_reports: 0,
+            report_failures: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub(crate) fn aggregation_parameter(&self) -> &A::AggregationParam {
+        &self.aggregation_parameter
+    }
+
+    /// Record that a report in `batch` failed to be prepared. `batch` is `None`
+    /// when it can't be determined, e.g. for a nonce the helper doesn't
+    /// recognize.
+    pub(crate) fn report_failed(&mut self, batch: Option<BatchSelector>, error: TransitionError) {
+        self.failed_reports += 1;
+        self.metrics.report_failed(error);
+        *self
+            .report_failures
+            .entry(batch)
+            .or_insert_with(|| ReportFailures {
+                reasons: HashMap::new(),
+                created: Time::now(),
+            })
+            .reasons
+            .entry(error)
+            .or_default() += 1;
+    }
+
+    /// Count the task's failed reports by reason in each batch, for the
+    /// failures endpoint
+    pub(crate) fn task_failures(&self) -> TaskFailures {
+        let mut batches: Vec<_> = self
+            .report_failures
+            .iter()
+            .map(|(batch, failures)| {
+                (
+                    failures.created,
+                    BatchFailures {
+                        batch: batch.map(|batch| batch.to_string()),
+                        reasons: failures
+                            .reasons
+                            .iter()
+                            .map(|(reason, count)| (format!("{:?}", reason), *count))
+                            .collect(),
+                    },
+                )
+            })
+            .collect();
+        batches.sort_by(|(a_created, a), (b_created, b)| {
+            (a_created, &a.batch).cmp(&(b_created, &b.batch))
+        });
+
+        TaskFailures {
+            task_id: self.task_parameters.task_id,
+            batches: batches.into_iter().map(|(_, batch)| batch).collect(),
         }
     }
 
-    #[tracing::instrument(skip(self, extensions, report_share), err)]
-    pub(crate) fn prepare_message(
+    /// Describe the aggregator's accumulators and failed reports, for the
+    /// status endpoint
+    pub(crate) fn task_status(&self, pending_reports: u64, aggregated_reports: u64) -> TaskStatus {
+        let publish_counts = self.task_parameters.differential_privacy.is_none();
+        let mut batches: Vec<_> = self
+            .accumulators
+            .iter()
+            .map(|(batch, accumulator)| BatchStatus {
+                batch: batch.to_string(),
+                contributions: Some(accumulator.contributions).filter(|_| publish_counts),
+                consumed_privacy_budget: accumulator.consumed_privacy_budget,
+                collected: self.is_collected(batch),
+                created: accumulator.created.0,
+            })
+            .collect();
+        batches.sort_by(|a, b| (a.created, &a.batch).cmp(&(b.created, &b.batch)));
+
+        TaskStatus {
+            task_id: self.task_parameters.task_id,
+            pending_reports,
+            aggregated_reports,
+            failed_reports: self.failed_reports,
+            batches,
+        }
+    }
+
+    /// Replace the task's parameters
+    pub(crate) fn set_task_parameters(&mut self, task_parameters: &Parameters) {
+        self.task_parameters = task_parameters.clone();
+    }
+
+    pub(crate) fn set_extension_registry(&mut self, extension_registry: ExtensionRegistry) {
+        self.extension_registry = Arc::new(extension_registry);
+    }
+
+    /// Determine which batch a report belongs to, given the batch that its
+    /// aggregation job is for.
+    pub(crate) fn report_batch(
+        &self,
+        nonce: Nonce,
+        partial_batch_selector: &PartialBatchSelector,
+    ) -> Result<BatchSelector, Error> {
+        match (self.task_parameters.query_type, partial_batch_selector) {
+            (QueryType::TimeInterval, PartialBatchSelector::TimeInterval) => {
+                Ok(BatchSelector::TimeInterval(
+                    nonce
+                        .time
+                        .batch_interval(self.task_parameters.min_batch_duration),
+                ))
+            }
+            (QueryType::FixedSize { .. }, PartialBatchSelector::FixedSize(batch_id)) => {
+                Ok(BatchSelector::FixedSize(*batch_id))
+            }
+            _ => Err(Error::QueryMismatch),
+        }
+    }
+
+    /// Returns true if the interval of `min_batch_duration` or fixed size
+    /// batch has been collected.
+    pub(crate) fn is_collected(&self, batch: &BatchSelector) -> bool {
+        self.collected_batches.contains_key(batch)
+    }
+
+    /// Number of reports accumulated into the interval of `min_batch_duration`
+    /// or fixed size batch
+    pub(crate) fn report_count(&self, batch: &BatchSelector) -> u64 {
+        self.accumulators
+            .get(batch)
+            .map(|accumulator| accumulator.contributions)
+            .unwrap_or(0)
+    }
+
+    /// Check that a report may be aggregated, without decrypting its input
+    /// share
+    pub(crate) fn validate_report(
         &self,
         report_task_id: TaskId,
         nonce: Nonce,
+        batch: &BatchSelector,
         extensions: &[report::Extension],
-        report_share: &hpke::Ciphertext,
-    ) -> Result<(A::PrepareStep, A::PrepareMessage), Error> {
+    ) -> Result<(), Error> {
         if self.task_parameters.task_id != report_task_id {
             return Err(Error::UnrecognizedTask(report_task_id));
         }
 
-        if self.collected_batch_intervals.contains(
-            &nonce
-                .time
-                .batch_interval(self.task_parameters.min_batch_duration),
-        ) {
+        if !self.task_parameters.validate_report_time(nonce.time) {
+            return Err(Error::UnalignedReportTime(nonce));
+        }
+
+        if self.is_expired(nonce.time, Time::now()) {
+            return Err(Error::ExpiredReport(nonce));
+        }
+
+        if self.is_collected(batch) {
             return Err(Error::StaleReport(nonce));
         }
 
+        self.extension_registry.validate(extensions)?;
+
+        Ok(())
+    }
+
+    /// Check that an input share was encrypted to this aggregator's HPKE
+    /// config, without decrypting it
+    pub(crate) fn check_hpke_config(&self, report_share: &hpke::Ciphertext) -> Result<(), Error> {
         if report_share.config_id != self.hpke_config.id {
             return Err(Error::UnknownHpkeConfig(report_share.config_id));
         }
 
+        Ok(())
+    }
+
+    /// Validate the report, decrypt its input share and initialize VDAF
+    /// preparation of it
+    #[tracing::instrument(skip(self, extensions, report_share), err)]
+    pub(crate) fn prepare_message(
+        &self,
+        report_task_id: TaskId,
+        nonce: Nonce,
+        batch: &BatchSelector,
+        extensions: &[report::Extension],
+        report_share: &hpke::Ciphertext,
+    ) -> Result<(A::PrepareStep, A::PrepareMessage), Error> {
+        self.validate_report(report_task_id, nonce, batch, extensions)?;
+        self.check_hpke_config(report_share)?;
+
         let hpke_recipient = self.hpke_config.recipient(
             &self.task_parameters.task_id,
             hpke::Label::InputShare,
@@ -520,31 +833,36 @@ impl<A: vdaf::Aggregator> Aggregator<A> {
         }
     }
 
+    /// Accumulate a prepared report's output share into the accumulator for
+    /// its batch. Reports still being aggregated when their batch was
+    /// collected are refused, since the batch's accumulator may since have
+    /// been deleted and a new one would come with a fresh privacy budget.
     pub(crate) fn accumulate_report(
         &mut self,
-        timestamp: Nonce,
+        batch: BatchSelector,
         output_share: A::OutputShare,
     ) -> Result<(), Error> {
-        // Proof checked out. Now accumulate the output share into the accumulator
-        // for the batch interval corresponding to the report timestamp.
-        let interval = timestamp
-            .time
-            .batch_interval(self.task_parameters.min_batch_duration);
+        if self.is_collected(&batch) {
+            return Err(Error::BatchCollected(batch));
+        }
 
-        if let Some(accumulator) = self.accumulators.get_mut(&interval) {
+        // Proof checked out. Now accumulate the output share into the accumulator
+        // for the report's batch.
+        if let Some(accumulator) = self.accumulators.get_mut(&batch) {
             accumulator.accumulated.accumulate(&output_share)?;
             accumulator.contributions += 1;
         } else {
-            // This is the first input we have seen for this batch interval.
+            // This is the first input we have seen for this batch.
             // Initialize the accumulator.
             self.accumulators.insert(
-                interval,
+                batch,
                 Accumulator {
                     accumulated: self
                         .aggregator
                         .aggregate(&self.aggregation_parameter, [output_share])?,
                     contributions: 1,
                     consumed_privacy_budget: 0,
+                    created: Time::now(),
                 },
             );
         }
@@ -552,51 +870,128 @@ impl<A: vdaf::Aggregator> Aggregator<A> {
         Ok(())
     }
 
+    /// Extract this aggregator's aggregate share over the batch, encrypted to
+    /// the collector, along with the number of reports included in it. The
+    /// query's aggregation parameter must be the one reports were prepared
+    /// with. No privacy budget is consumed until the returned share is passed
+    /// to [`Self::commit_aggregate_share`], so that a query that fails, here or
+    /// later in the collect flow, leaves the budget untouched. Rejected queries
+    /// are recorded in the audit log.
     pub(crate) fn extract_aggregate_share(
         &mut self,
         requested_task_id: TaskId,
-        batch_interval: Interval,
-    ) -> Result<hpke::Ciphertext, Error> {
+        batch: BatchSelector,
+        aggregation_parameter: &A::AggregationParam,
+    ) -> Result<PendingAggregateShare, Error>
+    where
+        A::AggregateShare: NoisyAggregateShare,
+    {
+        let timestamp = Time::now();
+        let aggregation_parameter = aggregation_parameter.get_encoded();
+        let result = self.prepare_aggregate_share(
+            requested_task_id,
+            batch,
+            &aggregation_parameter,
+            timestamp,
+        );
+        if let Err(error) = &result {
+            self.audit(CollectAuditRecord {
+                batch,
+                aggregation_parameter,
+                timestamp,
+                outcome: CollectOutcome::Rejected {
+                    reason: error.to_string(),
+                },
+            });
+        }
+
+        result
+    }
+
+    /// Determine which accumulators make up the batch
+    fn batch_accumulators(&self, batch: BatchSelector) -> Result<Vec<BatchSelector>, Error> {
+        match (self.task_parameters.query_type, batch) {
+            (QueryType::TimeInterval, BatchSelector::TimeInterval(batch_interval)) => {
+                if !self.task_parameters.validate_batch_interval(batch_interval) {
+                    return Err(Error::InvalidBatchInterval(batch_interval));
+                }
+
+                let num_intervals_in_request =
+                    batch_interval.intervals_in_interval(self.task_parameters.min_batch_duration);
+
+                let first_interval = batch_interval
+                    .start
+                    .interval_start(self.task_parameters.min_batch_duration);
+
+                (0..num_intervals_in_request)
+                    .map(|i| {
+                        let offset = self
+                            .task_parameters
+                            .min_batch_duration
+                            .multiple(i)
+                            .ok_or(Error::InvalidBatchInterval(batch_interval))?;
+                        Ok(BatchSelector::TimeInterval(
+                            first_interval
+                                .add(offset)
+                                .batch_interval(self.task_parameters.min_batch_duration),
+                        ))
+                    })
+                    .collect()
+            }
+            (QueryType::FixedSize { .. }, BatchSelector::FixedSize(_)) => Ok(vec![batch]),
+            _ => Err(Error::QueryMismatch),
+        }
+    }
+
+    fn prepare_aggregate_share(
+        &self,
+        requested_task_id: TaskId,
+        batch: BatchSelector,
+        aggregation_parameter: &[u8],
+        timestamp: Time,
+    ) -> Result<PendingAggregateShare, Error>
+    where
+        A::AggregateShare: NoisyAggregateShare,
+    {
         if self.task_parameters.task_id != requested_task_id {
             return Err(Error::UnrecognizedTask(requested_task_id));
         }
 
-        if !self.task_parameters.validate_batch_interval(batch_interval) {
-            return Err(Error::InvalidBatchInterval(batch_interval));
+        if aggregation_parameter != self.aggregation_parameter.get_encoded() {
+            return Err(Error::AggregationParameterMismatch);
         }
 
-        let num_intervals_in_request =
-            batch_interval.intervals_in_interval(self.task_parameters.min_batch_duration);
-
-        let first_interval = batch_interval
-            .start
-            .interval_start(self.task_parameters.min_batch_duration);
+        let accumulators = self.batch_accumulators(batch)?;
 
         let mut aggregate_shares = vec![];
         let mut total_contributions = 0;
 
-        for i in 0..num_intervals_in_request {
-            let current_interval = first_interval
-                .add(self.task_parameters.min_batch_duration.multiple(i))
-                .batch_interval(self.task_parameters.min_batch_duration);
-
-            self.collected_batch_intervals.insert(current_interval);
-
-            match self.accumulators.get_mut(&current_interval) {
+        // Check every accumulator's budget before anything is consumed
+        for accumulator_batch in &accumulators {
+            match self.accumulators.get(accumulator_batch) {
                 Some(accumulator) => {
                     if accumulator.consumed_privacy_budget
-                        == self.task_parameters.max_batch_lifetime
+                        >= self.task_parameters.max_batch_lifetime
                     {
-                        return Err(Error::PrivacyBudgetExceeded);
+                        warn!(
+                            batch = %accumulator_batch,
+                            queries = ?self.queries_consuming_budget(accumulator_batch).collect::<Vec<_>>(),
+                            "privacy budget exhausted"
+                        );
+                        return Err(Error::PrivacyBudgetExceeded(*accumulator_batch));
                     }
                     aggregate_shares.push(accumulator.accumulated.clone());
-
-                    accumulator.consumed_privacy_budget += 1;
                     total_contributions += accumulator.contributions;
                 }
+                None if self.is_collected(accumulator_batch) => {
+                    // The accumulator was collected before being garbage
+                    // collected, so whatever budget it had left is gone with it
+                    warn!(batch = %accumulator_batch, "accumulator deleted");
+                    return Err(Error::PrivacyBudgetExceeded(*accumulator_batch));
+                }
                 None => {
-                    // Most likely there are no contributions for this batch interval yet
-                    warn!("no accumulator found for interval {:?}", current_interval);
+                    // Most likely there are no contributions for this batch yet
+                    warn!("no accumulator found for {}", accumulator_batch);
                     continue;
                 }
             }
@@ -607,9 +1002,18 @@ impl<A: vdaf::Aggregator> Aggregator<A> {
         }
 
         // Merge aggregate shares into a single aggregate share
-        let remaining_shares = aggregate_shares.split_off(1);
-        for aggregate_share in remaining_shares.into_iter() {
-            aggregate_shares[0].merge(&aggregate_share)?;
+        let mut aggregate_shares = aggregate_shares.into_iter();
+        let mut aggregate_share = aggregate_shares
+            .next()
+            .ok_or(Error::InsufficientBatchSize(total_contributions))?;
+        for other_share in aggregate_shares {
+            aggregate_share.merge(&other_share)?;
+        }
+
+        // Noise must be added before the share leaves this aggregator, so that
+        // neither the collector nor the other aggregator ever sees it unnoised
+        if let Some(differential_privacy) = &self.task_parameters.differential_privacy {
+            differential_privacy.add_noise(&mut aggregate_share)?;
         }
 
         let hpke_sender = self.task_parameters.collector_config.sender(
@@ -619,13 +1023,277 @@ impl<A: vdaf::Aggregator> Aggregator<A> {
             Role::Collector,
         )?;
 
-        Ok(hpke_sender.seal(
-            &aggregate_shares[0].get_encoded(),
-            &batch_interval.associated_data(),
-        )?)
+        let ciphertext =
+            hpke_sender.seal(&aggregate_share.get_encoded(), &batch.associated_data())?;
+
+        Ok(PendingAggregateShare {
+            ciphertext,
+            report_count: total_contributions,
+            batch,
+            aggregation_parameter: aggregation_parameter.to_vec(),
+            timestamp,
+            accumulators,
+        })
+    }
+
+    /// The number of reports in the aggregate share to release to the collector,
+    /// which is noised if the task has differential privacy
+    pub(crate) fn released_report_count(
+        &self,
+        aggregate_share: &PendingAggregateShare,
+    ) -> Result<u64, Error> {
+        Ok(match &self.task_parameters.differential_privacy {
+            Some(differential_privacy) => {
+                differential_privacy.noise_count(aggregate_share.report_count)?
+            }
+            None => aggregate_share.report_count,
+        })
+    }
+
+    /// Consume one unit of privacy budget from each accumulator in the
+    /// aggregate share and record the query in the audit log.
+    pub(crate) fn commit_aggregate_share(&mut self, aggregate_share: &PendingAggregateShare) {
+        for accumulator_batch in &aggregate_share.accumulators {
+            self.collected_batches
+                .entry(*accumulator_batch)
+                .or_insert(aggregate_share.timestamp);
+            if let Some(accumulator) = self.accumulators.get_mut(accumulator_batch) {
+                accumulator.consumed_privacy_budget += 1;
+                self.metrics.privacy_budget_consumed(
+                    accumulator_batch,
+                    accumulator.consumed_privacy_budget,
+                );
+            }
+        }
+
+        self.audit(CollectAuditRecord {
+            batch: aggregate_share.batch,
+            aggregation_parameter: aggregate_share.aggregation_parameter.clone(),
+            timestamp: aggregate_share.timestamp,
+            outcome: CollectOutcome::Collected {
+                report_count: aggregate_share.report_count,
+            },
+        });
+    }
+
+    /// Record that an extracted aggregate share was never released, e.g.
+    /// because the other aggregator failed to provide its share. No privacy
+    /// budget is consumed.
+    pub(crate) fn abandon_aggregate_share(
+        &mut self,
+        aggregate_share: PendingAggregateShare,
+        reason: String,
+    ) {
+        self.audit(CollectAuditRecord {
+            batch: aggregate_share.batch,
+            aggregation_parameter: aggregate_share.aggregation_parameter.clone(),
+            timestamp: aggregate_share.timestamp,
+            outcome: CollectOutcome::Rejected { reason },
+        });
+    }
+
+    fn audit(&mut self, record: CollectAuditRecord) {
+        info!(?record, "collect query");
+        self.metrics
+            .collect_request(matches!(record.outcome, CollectOutcome::Collected { .. }));
+        if self.collect_audit_log.len() >= MAX_COLLECT_AUDIT_RECORDS {
+            self.collect_audit_log.pop_front();
+        }
+        self.collect_audit_log.push_back(record);
+    }
+
+    /// Queries that consumed privacy budget from the accumulator
+    fn queries_consuming_budget<'a>(
+        &'a self,
+        accumulator_batch: &'a BatchSelector,
+    ) -> impl Iterator<Item = &'a CollectAuditRecord> {
+        self.collect_audit_log.iter().filter(move |record| {
+            matches!(record.outcome, CollectOutcome::Collected { .. })
+                && record.batch.contains(accumulator_batch)
+        })
+    }
+
+    /// The queries made against this aggregator's aggregate shares that are
+    /// still relevant, oldest first
+    pub(crate) fn collect_audit_log(&self) -> &VecDeque<CollectAuditRecord> {
+        &self.collect_audit_log
+    }
+
+    /// List the queries in the audit log, for the collect audit endpoint
+    pub(crate) fn task_collect_audit(&self) -> TaskCollectAudit {
+        let publish_counts = self.task_parameters.differential_privacy.is_none();
+        TaskCollectAudit {
+            task_id: self.task_parameters.task_id,
+            queries: self
+                .collect_audit_log
+                .iter()
+                .map(|record| {
+                    let (collected, report_count, rejection_reason) = match &record.outcome {
+                        CollectOutcome::Collected { report_count } => {
+                            (true, Some(*report_count).filter(|_| publish_counts), None)
+                        }
+                        CollectOutcome::Rejected { reason } => (false, None, Some(reason.clone())),
+                    };
+                    CollectAuditEntry {
+                        batch: record.batch.to_string(),
+                        aggregation_parameter: hex::encode(&record.aggregation_parameter),
+                        timestamp: record.timestamp.0,
+                        collected,
+                        report_count,
+                        rejection_reason,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns true if a report with the timestamp has outlived the task's
+    /// retention policy
+    pub(crate) fn is_expired(&self, report_time: Time, now: Time) -> bool {
+        match self.task_parameters.report_expiry(now) {
+            Some(expiry) => report_time < expiry,
+            None => false,
+        }
+    }
+
+    /// Delete accumulators that can no longer be collected, either because
+    /// their privacy budget is used up or because they are older than the
+    /// task's `accumulator_max_age`, and forget about collected batches to
+    /// which no more reports could belong. Returns the batches whose
+    /// accumulators were deleted.
+    pub(crate) fn collect_garbage(&mut self, now: Time) -> Vec<BatchSelector> {
+        let retention = match &self.task_parameters.retention {
+            Some(retention) => retention.clone(),
+            None => return vec![],
+        };
+        let max_batch_lifetime = self.task_parameters.max_batch_lifetime;
+        let outlived_max_age = |created: Time| match retention.accumulator_max_age {
+            Some(max_age) => now.0.saturating_sub(created.0) > max_age.0,
+            None => false,
+        };
+
+        let deleted: Vec<_> = self
+            .accumulators
+            .iter()
+            .filter(|(_, accumulator)| {
+                accumulator.consumed_privacy_budget >= max_batch_lifetime
+                    || outlived_max_age(accumulator.created)
+            })
+            .map(|(batch, _)| *batch)
+            .collect();
+        for batch in &deleted {
+            self.accumulators.remove(batch);
+            self.metrics.remove_batch(batch);
+        }
+
+        // Reports for an interval that has expired are rejected anyway, so
+        // there's no need to remember that it was collected. We can't tell when
+        // a fixed size batch stops getting reports, so those are remembered for
+        // as long as their accumulators would be.
+        let report_expiry = self.task_parameters.report_expiry(now);
+        let interval_expired = |interval: &Interval| match report_expiry {
+            Some(expiry) => interval.end() <= expiry,
+            None => false,
+        };
+        let accumulators = &self.accumulators;
+        self.collected_batches
+            .retain(|batch, collected| match batch {
+                BatchSelector::TimeInterval(interval) => !interval_expired(interval),
+                BatchSelector::FixedSize(_) => {
+                    accumulators.contains_key(batch) || !outlived_max_age(*collected)
+                }
+            });
+
+        // Failure counts go along with the batch's accumulator, or once the
+        // batch could no longer have had one
+        self.report_failures.retain(|batch, failures| match batch {
+            Some(batch) if deleted.contains(batch) => false,
+            Some(BatchSelector::TimeInterval(interval)) => !interval_expired(interval),
+            _ => !outlived_max_age(failures.created),
+        });
+
+        // Queries are forgotten once nothing is left of the batches they were
+        // made against
+        let collected_batches = &self.collected_batches;
+        self.collect_audit_log.retain(|record| {
+            accumulators
+                .keys()
+                .chain(collected_batches.keys())
+                .any(|batch| record.batch.contains(batch))
+        });
+
+        deleted
     }
 
     pub(crate) fn dump_accumulators(&self) {
         dump_accumulators(&self.accumulators)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parameters::RetentionPolicy, Duration};
+    use prio::vdaf::prio3::Prio3Aes128Sum;
+
+    fn aggregator_with_retention() -> Aggregator<Prio3Aes128Sum> {
+        let mut parameters =
+            Parameters::from_json_reader(&include_bytes!("../sample-config/parameters.json")[..])
+                .unwrap();
+        parameters.retention = Some(RetentionPolicy {
+            gc_interval: Duration(1),
+            report_max_age: Some(Duration(1000)),
+            accumulator_max_age: Some(Duration(2000)),
+        });
+        let hpke_config =
+            hpke::ConfigFile::from_json_reader(&include_bytes!("../sample-config/hpke.json")[..])
+                .unwrap();
+        let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+        let verify_parameter = parameters
+            .decode_vdaf_verification_parameter(Role::Leader, &vdaf)
+            .unwrap();
+
+        Aggregator::new(
+            Role::Leader,
+            &hpke_config.leader,
+            &vdaf,
+            &verify_parameter,
+            &(),
+            &parameters,
+        )
+        .unwrap()
+    }
+
+    /// A batch interval whose end is past the largest representable time
+    fn last_interval() -> BatchSelector {
+        BatchSelector::TimeInterval(Interval {
+            start: Time(u64::MAX - 10),
+            duration: Duration(50),
+        })
+    }
+
+    #[test]
+    fn collect_garbage_near_max_time() {
+        let mut aggregator = aggregator_with_retention();
+        aggregator
+            .collected_batches
+            .insert(last_interval(), Time(u64::MAX - 10));
+
+        aggregator.collect_garbage(Time(u64::MAX));
+
+        // Reports could still arrive for the interval, so it's remembered
+        assert!(aggregator.is_collected(&last_interval()));
+    }
+
+    #[test]
+    fn collect_garbage_keeps_failures_near_max_time() {
+        let mut aggregator = aggregator_with_retention();
+        aggregator.report_failed(Some(last_interval()), TransitionError::VdafPrepError);
+
+        aggregator.collect_garbage(Time(u64::MAX));
+
+        assert!(aggregator
+            .report_failures
+            .contains_key(&Some(last_interval())));
+    }
+}
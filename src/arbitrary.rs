@@ -0,0 +1,321 @@
+//! proptest `Arbitrary` implementations for protocol messages, and tests that
+//! every message survives a round trip through its encoding.
+
+use crate::{
+    aggregate::{
+        Aggregate, AggregateInitReq, AggregateMessage, AggregateReq, AggregateResp,
+        AggregateShareReq, PartialBatchSelector, ReportShare, Transition, TransitionError,
+        TransitionMessage,
+    },
+    collect::{CollectRequest, CollectResponse, Query},
+    hpke::{
+        AuthenticatedEncryptionWithAssociatedData, Ciphertext, Config, ConfigId,
+        KeyDerivationFunction, KeyEncapsulationMechanism, PublicKey,
+    },
+    parameters::TaskId,
+    report::{DeviceClass, Extension, ExtensionType, Report},
+    BatchId, BatchSelector, Duration, Interval, Nonce, Time,
+};
+use prio::vdaf::prio3::Prio3Aes128Count;
+use proptest::{collection::vec, prelude::*};
+use std::convert::TryFrom;
+
+/// Most items generated in any vector in a message. Kept small so that
+/// messages stay well within the 2^16 - 1 byte limit on vectors.
+const MAX_ITEMS: usize = 4;
+
+/// Longest opaque byte string generated
+const MAX_BYTES: usize = 64;
+
+macro_rules! impl_arbitrary {
+    ($type:ty, $strategy:expr) => {
+        impl Arbitrary for $type {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+
+            fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+                $strategy.boxed()
+            }
+        }
+    };
+}
+
+fn bytes() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..MAX_BYTES)
+}
+
+fn items<T: Arbitrary>() -> impl Strategy<Value = Vec<T>> {
+    vec(any::<T>(), 0..MAX_ITEMS)
+}
+
+impl_arbitrary!(Time, any::<u64>().prop_map(Time));
+impl_arbitrary!(Duration, any::<u64>().prop_map(Duration));
+impl_arbitrary!(
+    Nonce,
+    (any::<Time>(), any::<u64>()).prop_map(|(time, rand)| Nonce { time, rand })
+);
+impl_arbitrary!(
+    Interval,
+    (any::<Time>(), any::<Duration>()).prop_map(|(start, duration)| Interval { start, duration })
+);
+impl_arbitrary!(BatchId, any::<[u8; 32]>().prop_map(BatchId));
+impl_arbitrary!(
+    BatchSelector,
+    prop_oneof![
+        any::<Interval>().prop_map(BatchSelector::TimeInterval),
+        any::<BatchId>().prop_map(BatchSelector::FixedSize),
+    ]
+);
+impl_arbitrary!(
+    TaskId,
+    any::<[u8; 32]>().prop_map(|id| TaskId::try_from(id.to_vec()).unwrap())
+);
+
+impl_arbitrary!(
+    Ciphertext,
+    (any::<u8>(), bytes(), bytes()).prop_map(|(config_id, encapsulated_context, payload)| {
+        Ciphertext {
+            config_id: ConfigId(config_id),
+            encapsulated_context,
+            payload,
+        }
+    })
+);
+impl_arbitrary!(
+    Config,
+    (
+        any::<u8>(),
+        prop_oneof![
+            Just(KeyEncapsulationMechanism::P256HkdfSha256),
+            Just(KeyEncapsulationMechanism::X25519HkdfSha256),
+        ],
+        prop_oneof![
+            Just(KeyDerivationFunction::HkdfSha256),
+            Just(KeyDerivationFunction::HkdfSha384),
+            Just(KeyDerivationFunction::HkdfSha512),
+        ],
+        prop_oneof![
+            Just(AuthenticatedEncryptionWithAssociatedData::AesGcm128),
+            Just(AuthenticatedEncryptionWithAssociatedData::AesGcm256),
+            Just(AuthenticatedEncryptionWithAssociatedData::ChaCha20Poly1305),
+        ],
+        bytes(),
+    )
+        .prop_map(|(id, kem_id, kdf_id, aead_id, public_key)| Config {
+            id: ConfigId(id),
+            kem_id,
+            kdf_id,
+            aead_id,
+            public_key: PublicKey(public_key),
+            // Private keys are never encoded
+            private_key: None,
+        })
+);
+
+impl_arbitrary!(
+    Extension,
+    (any::<u16>(), bytes()).prop_map(|(extension_type, extension_data)| Extension::new(
+        ExtensionType::from(extension_type),
+        extension_data
+    ))
+);
+impl_arbitrary!(DeviceClass, ".{0,32}".prop_map(DeviceClass));
+impl_arbitrary!(
+    Report,
+    (
+        any::<TaskId>(),
+        any::<Nonce>(),
+        items::<Extension>(),
+        items::<Ciphertext>(),
+    )
+        .prop_map(
+            |(task_id, nonce, extensions, encrypted_input_shares)| Report {
+                task_id,
+                nonce,
+                extensions,
+                encrypted_input_shares,
+            }
+        )
+);
+
+impl_arbitrary!(
+    ReportShare,
+    (any::<Nonce>(), items::<Extension>(), any::<Ciphertext>()).prop_map(
+        |(nonce, extensions, encrypted_input_share)| ReportShare {
+            nonce,
+            extensions,
+            encrypted_input_share,
+        }
+    )
+);
+impl_arbitrary!(
+    TransitionError,
+    (0u8..=7).prop_map(|error| TransitionError::try_from(error).unwrap())
+);
+impl_arbitrary!(
+    Transition,
+    prop_oneof![
+        bytes().prop_map(|payload| Transition::Continued { payload }),
+        Just(Transition::Finished),
+        any::<TransitionError>().prop_map(|error| Transition::Failed { error }),
+    ]
+);
+impl_arbitrary!(
+    TransitionMessage,
+    (any::<Nonce>(), any::<Transition>())
+        .prop_map(|(nonce, transition)| TransitionMessage { nonce, transition })
+);
+impl_arbitrary!(
+    PartialBatchSelector,
+    prop_oneof![
+        Just(PartialBatchSelector::TimeInterval),
+        any::<BatchId>().prop_map(PartialBatchSelector::FixedSize),
+    ]
+);
+impl_arbitrary!(
+    AggregateInitReq,
+    (
+        any::<TaskId>(),
+        any::<PartialBatchSelector>(),
+        bytes(),
+        items::<ReportShare>(),
+    )
+        .prop_map(
+            |(task_id, partial_batch_selector, aggregation_parameter, report_shares)| {
+                AggregateInitReq {
+                    task_id,
+                    partial_batch_selector,
+                    aggregation_parameter,
+                    report_shares,
+                }
+            }
+        )
+);
+impl_arbitrary!(
+    AggregateReq,
+    (any::<TaskId>(), bytes(), items::<TransitionMessage>()).prop_map(
+        |(task_id, helper_state, transitions)| AggregateReq {
+            task_id,
+            helper_state,
+            transitions,
+        }
+    )
+);
+impl_arbitrary!(
+    AggregateResp,
+    (bytes(), items::<TransitionMessage>()).prop_map(|(helper_state, transitions)| {
+        AggregateResp {
+            helper_state,
+            transitions,
+        }
+    })
+);
+impl_arbitrary!(
+    AggregateShareReq,
+    (any::<TaskId>(), any::<BatchSelector>()).prop_map(|(task_id, batch_selector)| {
+        AggregateShareReq {
+            task_id,
+            batch_selector,
+        }
+    })
+);
+impl_arbitrary!(
+    Aggregate,
+    prop_oneof![
+        any::<AggregateInitReq>().prop_map(Aggregate::Initialize),
+        any::<AggregateReq>().prop_map(Aggregate::Request),
+        any::<AggregateResp>().prop_map(Aggregate::Response),
+        any::<AggregateShareReq>().prop_map(Aggregate::ShareRequest),
+        any::<Ciphertext>().prop_map(Aggregate::ShareResponse),
+    ]
+);
+impl_arbitrary!(
+    AggregateMessage,
+    (any::<Aggregate>(), any::<[u8; 32]>())
+        .prop_map(|(aggregate, tag)| AggregateMessage { aggregate, tag })
+);
+
+impl_arbitrary!(
+    Query,
+    prop_oneof![
+        any::<Interval>().prop_map(|batch_interval| Query::TimeInterval { batch_interval }),
+        any::<Option<BatchId>>().prop_map(|batch_id| Query::FixedSize { batch_id }),
+    ]
+);
+impl_arbitrary!(
+    CollectRequest<Prio3Aes128Count>,
+    (any::<TaskId>(), any::<Query>()).prop_map(|(task_id, query)| CollectRequest {
+        task_id,
+        query,
+        aggregation_parameter: (),
+    })
+);
+impl_arbitrary!(
+    CollectResponse,
+    (any::<BatchSelector>(), any::<u64>(), items::<Ciphertext>()).prop_map(
+        |(batch_selector, report_count, encrypted_agg_shares)| CollectResponse {
+            batch_selector,
+            report_count,
+            encrypted_agg_shares,
+        }
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prio::codec::{Decode, Encode};
+    use std::fmt::Debug;
+
+    /// Check that `value` decodes from its encoding, and that the decoded
+    /// value encodes identically. Not every message type implements
+    /// `PartialEq`, so encodings are compared rather than values.
+    fn check_round_trip<T: Decode + Encode + Debug>(value: &T) -> Result<(), TestCaseError> {
+        let encoded = value.get_encoded();
+        let decoded = T::get_decoded(&encoded)
+            .map_err(|e| TestCaseError::fail(format!("decoding {:?}: {}", value, e)))?;
+        prop_assert_eq!(decoded.get_encoded(), encoded);
+        Ok(())
+    }
+
+    macro_rules! round_trip_tests {
+        ($($name:ident: $type:ty,)*) => {
+            proptest! {
+                $(
+                    #[test]
+                    fn $name(value in any::<$type>()) {
+                        check_round_trip(&value)?;
+                    }
+                )*
+            }
+        };
+    }
+
+    round_trip_tests! {
+        time: Time,
+        duration: Duration,
+        nonce: Nonce,
+        interval: Interval,
+        batch_id: BatchId,
+        batch_selector: BatchSelector,
+        task_id: TaskId,
+        ciphertext: Ciphertext,
+        hpke_config: Config,
+        extension: Extension,
+        device_class: DeviceClass,
+        report: Report,
+        report_share: ReportShare,
+        transition: Transition,
+        transition_message: TransitionMessage,
+        partial_batch_selector: PartialBatchSelector,
+        aggregate_init_req: AggregateInitReq,
+        aggregate_req: AggregateReq,
+        aggregate_resp: AggregateResp,
+        aggregate_share_req: AggregateShareReq,
+        aggregate: Aggregate,
+        aggregate_message: AggregateMessage,
+        query: Query,
+        collect_request: CollectRequest<Prio3Aes128Count>,
+        collect_response: CollectResponse,
+    }
+}
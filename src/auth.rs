@@ -0,0 +1,317 @@
+//! Authentication of clients uploading reports to the leader.
+//!
+//! Tasks may require that clients authenticate uploads, either by presenting
+//! one of a set of bearer tokens in the `Authorization` header, or by signing
+//! the encoded `Report` with an Ed25519 key and presenting the signature in the
+//! `PPM-Signature` header. Either way, the leader checks credentials before it
+//! does any other work on the report.
+//!
+//! Operators using administrative endpoints authenticate with bearer tokens,
+//! and failures are reported with [`AdminError`] rather than [`Error`].
+
+use crate::error::{IntoHttpApiProblem, ProblemDocumentType};
+use http::{header::AUTHORIZATION, HeaderMap, HeaderValue, StatusCode};
+use ring::{
+    constant_time::verify_slices_are_equal,
+    signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519},
+};
+use serde::{Deserialize, Serialize};
+
+/// Header in which clients present Ed25519 signatures over reports
+pub const SIGNATURE_HEADER: &str = "PPM-Signature";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("missing upload credentials")]
+    MissingCredentials,
+    #[error("malformed upload credentials: {0}")]
+    MalformedCredentials(&'static str),
+    #[error("upload credentials not accepted")]
+    Unauthorized,
+    #[error("invalid signing key")]
+    InvalidSigningKey,
+}
+
+impl IntoHttpApiProblem for Error {
+    fn problem_document_type(&self) -> Option<ProblemDocumentType> {
+        match self {
+            Self::InvalidSigningKey => None,
+            _ => Some(ProblemDocumentType::UnauthorizedRequest),
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingCredentials | Self::MalformedCredentials(_) => StatusCode::UNAUTHORIZED,
+            Self::Unauthorized => StatusCode::FORBIDDEN,
+            Self::InvalidSigningKey => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Errors authenticating operators using administrative endpoints
+#[derive(Debug, thiserror::Error)]
+pub enum AdminError {
+    #[error("missing admin credentials")]
+    MissingCredentials,
+    #[error("malformed admin credentials: {0}")]
+    MalformedCredentials(&'static str),
+    #[error("admin credentials not accepted")]
+    Unauthorized,
+}
+
+impl IntoHttpApiProblem for AdminError {
+    fn problem_document_type(&self) -> Option<ProblemDocumentType> {
+        Some(ProblemDocumentType::UnauthorizedRequest)
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingCredentials | Self::MalformedCredentials(_) => StatusCode::UNAUTHORIZED,
+            Self::Unauthorized => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+/// Ways a bearer token check can fail, converted to the error of whichever
+/// kind of credential was being checked
+enum BearerTokenError {
+    Missing,
+    Malformed(&'static str),
+    NotAccepted,
+}
+
+impl From<BearerTokenError> for Error {
+    fn from(error: BearerTokenError) -> Self {
+        match error {
+            BearerTokenError::Missing => Self::MissingCredentials,
+            BearerTokenError::Malformed(reason) => Self::MalformedCredentials(reason),
+            BearerTokenError::NotAccepted => Self::Unauthorized,
+        }
+    }
+}
+
+impl From<BearerTokenError> for AdminError {
+    fn from(error: BearerTokenError) -> Self {
+        match error {
+            BearerTokenError::Missing => Self::MissingCredentials,
+            BearerTokenError::Malformed(reason) => Self::MalformedCredentials(reason),
+            BearerTokenError::NotAccepted => Self::Unauthorized,
+        }
+    }
+}
+
+/// An Ed25519 public key, represented as hex in config files
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Ed25519PublicKey(#[serde(with = "hex")] pub [u8; 32]);
+
+/// How the leader authenticates clients uploading reports to a task
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum UploadAuth {
+    /// Clients must present one of the tokens in an `Authorization: Bearer`
+    /// header
+    BearerToken { tokens: Vec<String> },
+    /// Clients must sign the encoded report with the private key corresponding
+    /// to one of the public keys
+    Ed25519 { public_keys: Vec<Ed25519PublicKey> },
+}
+
+impl UploadAuth {
+    /// Check the credentials in the headers of an upload request whose body is
+    /// `report`
+    pub fn authenticate(&self, headers: &HeaderMap, report: &[u8]) -> Result<(), Error> {
+        match self {
+            Self::BearerToken { tokens } => Ok(authenticate_bearer_token(tokens, headers)?),
+            Self::Ed25519 { public_keys } => {
+                let signature = headers
+                    .get(SIGNATURE_HEADER)
+                    .ok_or(Error::MissingCredentials)?
+                    .to_str()
+                    .map_err(|_| Error::MalformedCredentials("signature header"))?;
+                let signature = base64::decode(signature)
+                    .map_err(|_| Error::MalformedCredentials("signature is not base64"))?;
+
+                if public_keys.iter().any(|public_key| {
+                    UnparsedPublicKey::new(&ED25519, &public_key.0)
+                        .verify(report, &signature)
+                        .is_ok()
+                }) {
+                    Ok(())
+                } else {
+                    Err(Error::Unauthorized)
+                }
+            }
+        }
+    }
+}
+
+/// How aggregators authenticate operators using administrative endpoints such
+/// as `/status`. Operators must present one of the tokens in an
+/// `Authorization: Bearer` header.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct AdminAuth {
+    pub tokens: Vec<String>,
+}
+
+impl AdminAuth {
+    /// Check the credentials in the headers of a request to an administrative
+    /// endpoint
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<(), AdminError> {
+        Ok(authenticate_bearer_token(&self.tokens, headers)?)
+    }
+}
+
+/// Check that the headers contain an `Authorization: Bearer` header presenting
+/// one of the tokens
+fn authenticate_bearer_token(
+    tokens: &[String],
+    headers: &HeaderMap,
+) -> Result<(), BearerTokenError> {
+    let presented = headers
+        .get(AUTHORIZATION)
+        .ok_or(BearerTokenError::Missing)?
+        .to_str()
+        .map_err(|_| BearerTokenError::Malformed("authorization header"))?
+        .strip_prefix("Bearer ")
+        .ok_or(BearerTokenError::Malformed("expected bearer token"))?;
+
+    // Compare against every token so that timing reveals nothing about which,
+    // if any, matched
+    let mut authorized = false;
+    for token in tokens {
+        authorized |= verify_slices_are_equal(token.as_bytes(), presented.as_bytes()).is_ok();
+    }
+
+    if authorized {
+        Ok(())
+    } else {
+        Err(BearerTokenError::NotAccepted)
+    }
+}
+
+/// Credentials with which a client authenticates uploads
+#[derive(Debug)]
+pub enum ClientCredential {
+    BearerToken(String),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl ClientCredential {
+    /// Construct an Ed25519 credential from the 32 byte seed of a private key
+    pub fn ed25519_from_seed(seed: &[u8]) -> Result<Self, Error> {
+        Ed25519KeyPair::from_seed_unchecked(seed)
+            .map(Self::Ed25519)
+            .map_err(|_| Error::InvalidSigningKey)
+    }
+
+    /// The public key corresponding to an Ed25519 credential, for configuring
+    /// the leader
+    pub fn ed25519_public_key(&self) -> Option<Ed25519PublicKey> {
+        match self {
+            Self::BearerToken(_) => None,
+            Self::Ed25519(key_pair) => {
+                let mut public_key = [0u8; 32];
+                public_key.copy_from_slice(key_pair.public_key().as_ref());
+                Some(Ed25519PublicKey(public_key))
+            }
+        }
+    }
+
+    /// Construct the header that authenticates an upload of `report`
+    pub fn header(&self, report: &[u8]) -> Result<(&'static str, HeaderValue), Error> {
+        let (name, value) = match self {
+            Self::BearerToken(token) => (AUTHORIZATION.as_str(), format!("Bearer {}", token)),
+            Self::Ed25519(key_pair) => (
+                SIGNATURE_HEADER,
+                base64::encode(key_pair.sign(report).as_ref()),
+            ),
+        };
+
+        let value = HeaderValue::from_str(&value)
+            .map_err(|_| Error::MalformedCredentials("credential is not a valid header"))?;
+
+        Ok((name, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(credential: &ClientCredential, report: &[u8]) -> HeaderMap {
+        let (name, value) = credential.header(report).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value);
+        headers
+    }
+
+    #[test]
+    fn bearer_token() {
+        let auth = UploadAuth::BearerToken {
+            tokens: vec!["first".to_string(), "second".to_string()],
+        };
+
+        let credential = ClientCredential::BearerToken("second".to_string());
+        auth.authenticate(&headers(&credential, b"report"), b"report")
+            .unwrap();
+
+        let credential = ClientCredential::BearerToken("third".to_string());
+        assert!(matches!(
+            auth.authenticate(&headers(&credential, b"report"), b"report"),
+            Err(Error::Unauthorized)
+        ));
+        assert!(matches!(
+            auth.authenticate(&HeaderMap::new(), b"report"),
+            Err(Error::MissingCredentials)
+        ));
+    }
+
+    #[test]
+    fn admin_token() {
+        let auth = AdminAuth {
+            tokens: vec!["admin".to_string()],
+        };
+
+        auth.authenticate(&headers(
+            &ClientCredential::BearerToken("admin".to_string()),
+            b"",
+        ))
+        .unwrap();
+        assert!(matches!(
+            auth.authenticate(&headers(
+                &ClientCredential::BearerToken("other".to_string()),
+                b""
+            )),
+            Err(AdminError::Unauthorized)
+        ));
+        assert_eq!(
+            auth.authenticate(&HeaderMap::new())
+                .unwrap_err()
+                .to_string(),
+            "missing admin credentials"
+        );
+    }
+
+    #[test]
+    fn ed25519() {
+        let credential = ClientCredential::ed25519_from_seed(&[1u8; 32]).unwrap();
+        let other_credential = ClientCredential::ed25519_from_seed(&[2u8; 32]).unwrap();
+        let auth = UploadAuth::Ed25519 {
+            public_keys: vec![credential.ed25519_public_key().unwrap()],
+        };
+
+        auth.authenticate(&headers(&credential, b"report"), b"report")
+            .unwrap();
+
+        // Signature over some other report
+        assert!(matches!(
+            auth.authenticate(&headers(&credential, b"other report"), b"report"),
+            Err(Error::Unauthorized)
+        ));
+        // Signature by an unknown key
+        assert!(matches!(
+            auth.authenticate(&headers(&other_credential, b"report"), b"report"),
+            Err(Error::Unauthorized)
+        ));
+    }
+}
@@ -1,24 +1,235 @@
-use color_eyre::eyre::{Context, Result};
-use ppm_prototype::{client::PpmClient, parameters::Parameters, trace};
-use prio::vdaf::prio3::Prio3Aes128Sum;
+use clap::Parser;
+use color_eyre::eyre::{eyre, Context, Result};
+use ppm_prototype::{
+    auth::ClientCredential,
+    client::PpmClient,
+    parameters::{Parameters, VdafLabel},
+    report::{DeviceClass, Extension},
+    trace::{self, TraceConfiguration},
+    Time,
+};
+use prio::vdaf::{
+    prio3::{Prio3Aes128Count, Prio3Aes128Histogram, Prio3Aes128Sum},
+    Client,
+};
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+    process,
+};
 use tracing::info;
 
+/// Exit status when every measurement was uploaded
+const EXIT_SUCCESS: i32 = 0;
+/// Exit status when at least one measurement could not be uploaded
+const EXIT_UPLOAD_FAILED: i32 = 2;
+/// Exit status when at least one measurement could not be parsed. Takes
+/// precedence over `EXIT_UPLOAD_FAILED`.
+const EXIT_INVALID_MEASUREMENT: i32 = 3;
+
+/// Upload measurements to a PPM leader.
+///
+/// Measurements are taken from the command line arguments if any are provided,
+/// otherwise from the file named by `--input`, otherwise from standard input,
+/// one measurement per line. Measurements are interpreted according to the
+/// task's VDAF: `true`/`false` (or `1`/`0`) for Prio3Count64, an unsigned
+/// integer for Prio3Sum64 and the value to be bucketed for Prio3Histogram64.
+#[derive(Debug, Parser)]
+#[clap(version)]
+struct Options {
+    /// Read newline-delimited measurements from this file. Use `-` for standard
+    /// input.
+    #[clap(long, short, value_parser, conflicts_with = "measurements")]
+    input: Option<PathBuf>,
+
+    /// Time at which the measurements were taken, in seconds since the start of
+    /// the UNIX epoch. Defaults to the current time.
+    #[clap(long, short, value_parser)]
+    time: Option<u64>,
+
+    /// Tag each report with the class of device that took the measurements,
+    /// e.g. `mobile`
+    #[clap(long, value_parser)]
+    device_class: Option<String>,
+
+    /// Authenticate uploads with this bearer token, if the task requires it
+    #[clap(long, value_parser, conflicts_with = "signing-key")]
+    bearer_token: Option<String>,
+
+    /// Authenticate uploads by signing them with this hex encoded Ed25519
+    /// private key seed, if the task requires it
+    #[clap(long, value_parser = parse_signing_key)]
+    signing_key: Option<[u8; 32]>,
+
+    /// Measurements to upload
+    #[clap(value_parser)]
+    measurements: Vec<String>,
+}
+
+fn parse_signing_key(value: &str) -> Result<[u8; 32]> {
+    let seed = hex::decode(value).wrap_err("signing key must be hex encoded")?;
+    seed.try_into()
+        .map_err(|_| eyre!("signing key must be 32 bytes"))
+}
+
+impl Options {
+    /// The credential to authenticate uploads with, if any
+    fn credential(&self) -> Result<Option<ClientCredential>> {
+        if let Some(token) = &self.bearer_token {
+            return Ok(Some(ClientCredential::BearerToken(token.clone())));
+        }
+
+        self.signing_key
+            .as_ref()
+            .map(|seed| ClientCredential::ed25519_from_seed(seed))
+            .transpose()
+            .wrap_err("loading signing key")
+    }
+
+    /// Returns an iterator over the measurements, in the order in which they
+    /// should be uploaded.
+    fn measurements(&self) -> Result<Box<dyn Iterator<Item = io::Result<String>>>> {
+        if !self.measurements.is_empty() {
+            return Ok(Box::new(self.measurements.clone().into_iter().map(Ok)));
+        }
+
+        let reader: Box<dyn BufRead> = match &self.input {
+            Some(path) if path.as_os_str() != "-" => Box::new(BufReader::new(
+                File::open(path).wrap_err_with(|| format!("opening {}", path.display()))?,
+            )),
+            _ => Box::new(BufReader::new(io::stdin())),
+        };
+
+        Ok(Box::new(reader.lines()))
+    }
+}
+
+fn parse_count(measurement: &str) -> Result<u64> {
+    match measurement {
+        "true" | "1" => Ok(1),
+        "false" | "0" => Ok(0),
+        _ => Err(eyre!("expected one of true, false, 1 or 0")),
+    }
+}
+
+fn parse_integer(measurement: &str) -> Result<u128> {
+    measurement.parse().wrap_err("expected an unsigned integer")
+}
+
+/// Upload each measurement, logging the outcome for each one, and return the
+/// process exit status.
+async fn upload_measurements<C, P>(
+    ppm_parameters: &Parameters,
+    vdaf: C,
+    options: &Options,
+    parse_measurement: P,
+) -> Result<i32>
+where
+    C: Client,
+    P: Fn(&str) -> Result<C::Measurement>,
+{
+    let (public_parameter, _) = vdaf.setup()?;
+    let mut client = PpmClient::new(ppm_parameters, &vdaf, public_parameter).await?;
+    if let Some(device_class) = &options.device_class {
+        client = client.with_extension(Extension::from_typed(&DeviceClass(device_class.clone())));
+    }
+    if let Some(credential) = options.credential()? {
+        client = client.with_credential(credential);
+    }
+    let time = options.time.map(Time).unwrap_or_else(Time::now);
+
+    let mut uploaded = 0;
+    let mut upload_failures = 0;
+    let mut invalid_measurements = 0;
+
+    for (index, line) in options.measurements()?.enumerate() {
+        let line = line.wrap_err("reading measurements")?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let measurement = match parse_measurement(line) {
+            Ok(measurement) => measurement,
+            Err(error) => {
+                println!("{}\t{}\tinvalid: {:#}", index + 1, line, error);
+                invalid_measurements += 1;
+                continue;
+            }
+        };
+
+        match client.do_upload(time.0, &measurement).await {
+            Ok(()) => {
+                println!("{}\t{}\tuploaded", index + 1, line);
+                uploaded += 1;
+            }
+            Err(error) => {
+                println!("{}\t{}\tfailed: {}", index + 1, line, error);
+                upload_failures += 1;
+            }
+        }
+    }
+
+    info!(
+        uploaded,
+        upload_failures, invalid_measurements, "completed uploads"
+    );
+
+    Ok(if invalid_measurements > 0 {
+        EXIT_INVALID_MEASUREMENT
+    } else if upload_failures > 0 {
+        EXIT_UPLOAD_FAILED
+    } else {
+        EXIT_SUCCESS
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Pretty-print errors
     color_eyre::install()?;
-    trace::install_subscriber();
+    let trace_guard = trace::install_subscriber(
+        &TraceConfiguration::load().wrap_err("loading trace configuration")?,
+    )
+    .wrap_err("installing trace subscriber")?;
 
+    let options = Options::parse();
     let ppm_parameters = Parameters::from_config_file().wrap_err("loading task parameters")?;
-    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
-
-    let client = PpmClient::new(&ppm_parameters, &vdaf, ()).await?;
-
-    for count in 0..100 {
-        client.do_upload(1631907500 + count, &1).await?;
-    }
 
-    info!("completed uploads");
+    let exit_status = match &ppm_parameters.vdaf {
+        VdafLabel::Prio3Count64 => {
+            upload_measurements(
+                &ppm_parameters,
+                Prio3Aes128Count::new(2)?,
+                &options,
+                parse_count,
+            )
+            .await?
+        }
+        VdafLabel::Prio3Sum64 { bits } => {
+            upload_measurements(
+                &ppm_parameters,
+                Prio3Aes128Sum::new(2, *bits)?,
+                &options,
+                parse_integer,
+            )
+            .await?
+        }
+        VdafLabel::Prio3Histogram64 { buckets } => {
+            upload_measurements(
+                &ppm_parameters,
+                Prio3Aes128Histogram::new(2, buckets)?,
+                &options,
+                parse_integer,
+            )
+            .await?
+        }
+        label => return Err(eyre!("unsupported VDAF {:?}", label)),
+    };
 
-    Ok(())
+    // Exiting skips destructors, so flush logs first
+    drop(trace_guard);
+    process::exit(exit_status);
 }
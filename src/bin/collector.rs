@@ -1,34 +1,307 @@
-use color_eyre::eyre::Result;
+use clap::{ArgEnum, ArgGroup, CommandFactory, ErrorKind, Parser};
+use color_eyre::eyre::{eyre, Context, Result};
 use ppm_prototype::{
-    collect::run_collect, hpke, parameters::Parameters, trace, Duration, Interval, Role, Time,
+    collect::{self, AggregateShareLength, Collection, PpmCollector, Query},
+    dp::NoisyAggregateShare,
+    hpke,
+    parameters::{Parameters, VdafLabel},
+    trace::{self, TraceConfiguration},
+    BatchId, BatchSelector, Duration, Interval, Role, Time,
 };
-use prio::vdaf::prio3::Prio3Aes128Sum;
+use prio::{
+    codec::Decode,
+    vdaf::{
+        prio3::{
+            Prio3Aes128Count, Prio3Aes128Histogram, Prio3Aes128Sum, Prio3Result, Prio3ResultVec,
+        },
+        Collector,
+    },
+};
+use serde_json::json;
+use std::{convert::TryInto, process};
+
+/// Exit status when the batch interval does not contain enough reports
+const EXIT_INSUFFICIENT_BATCH_SIZE: i32 = 3;
+/// Exit status when the batch interval's privacy budget has been used up
+const EXIT_PRIVACY_BUDGET_EXCEEDED: i32 = 4;
+/// Exit status when the batch interval is not valid for the task
+const EXIT_INVALID_BATCH_INTERVAL: i32 = 5;
+
+/// Collect an aggregate result from a PPM leader.
+///
+/// Tasks with time interval batches are queried with `--start` or `--last`,
+/// and tasks with fixed size batches with `--batch-id` or `--current-batch`.
+#[derive(Debug, Parser)]
+#[clap(
+    version,
+    group(ArgGroup::new("query").required(true).args(&["start", "last", "batch-id", "current-batch"]))
+)]
+struct Options {
+    /// Start of the batch interval, in seconds since the start of the UNIX
+    /// epoch
+    #[clap(long, value_parser)]
+    start: Option<u64>,
+
+    /// Duration of the batch interval in seconds. Defaults to the task's
+    /// minimum batch duration.
+    #[clap(long, value_parser, requires = "start")]
+    duration: Option<u64>,
+
+    /// Collect over the last N complete minimum batch durations
+    #[clap(long, value_parser, value_name = "N")]
+    last: Option<u64>,
+
+    /// Hex encoded ID of the fixed size batch to collect
+    #[clap(long, value_parser = parse_batch_id)]
+    batch_id: Option<BatchId>,
+
+    /// Collect the oldest complete fixed size batch that has not yet been
+    /// collected
+    #[clap(long, value_parser)]
+    current_batch: bool,
+
+    /// Hex encoded aggregation parameter
+    #[clap(long, value_parser, default_value = "")]
+    aggregation_parameter: String,
+
+    /// Format in which to print the result
+    #[clap(long, arg_enum, value_parser, default_value = "human")]
+    format: OutputFormat,
+}
+
+impl Options {
+    /// Determine the query to make to the leader, failing with a usage error
+    /// if `--last` reaches back before the UNIX epoch
+    fn query(&self, ppm_parameters: &Parameters) -> Result<Query, clap::Error> {
+        let min_batch_duration = ppm_parameters.min_batch_duration;
+        let query = if let Some(start) = self.start {
+            Query::TimeInterval {
+                batch_interval: Interval {
+                    start: Time(start),
+                    duration: self.duration.map(Duration).unwrap_or(min_batch_duration),
+                },
+            }
+        } else if let Some(last) = self.last {
+            let end = Time::now().truncate(min_batch_duration);
+            let (start, duration) = min_batch_duration
+                .multiple(last)
+                .and_then(|duration| Some((end.0.checked_sub(duration.0)?, duration)))
+                .ok_or_else(|| {
+                    Self::command().error(
+                        ErrorKind::ValueValidation,
+                        format!("--last {} reaches back before the UNIX epoch", last),
+                    )
+                })?;
+            Query::TimeInterval {
+                batch_interval: Interval {
+                    start: Time(start),
+                    duration,
+                },
+            }
+        } else {
+            // clap ensures one of the query arguments is provided, so this is
+            // either --batch-id or --current-batch
+            Query::FixedSize {
+                batch_id: self.batch_id,
+            }
+        };
+
+        Ok(query)
+    }
+}
+
+fn parse_batch_id(batch_id: &str) -> Result<BatchId> {
+    let bytes = hex::decode(batch_id).wrap_err("decoding batch ID")?;
+    Ok(BatchId(
+        bytes
+            .try_into()
+            .map_err(|_| eyre!("batch ID must be 32 bytes"))?,
+    ))
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    Human,
+    Json,
+    Csv,
+}
+
+/// Aggregate results that the collector knows how to print
+trait PrintableResult {
+    /// The values making up the result
+    fn values(&self) -> Vec<u64>;
+
+    /// True if the result is a vector rather than a single value
+    fn is_vector(&self) -> bool;
+}
+
+impl PrintableResult for Prio3Result<u64> {
+    fn values(&self) -> Vec<u64> {
+        vec![self.0]
+    }
+
+    fn is_vector(&self) -> bool {
+        false
+    }
+}
+
+impl PrintableResult for Prio3ResultVec<u64> {
+    fn values(&self) -> Vec<u64> {
+        self.0.clone()
+    }
+
+    fn is_vector(&self) -> bool {
+        true
+    }
+}
+
+fn print_collection<R: PrintableResult>(collection: &Collection<R>, format: OutputFormat) {
+    let result = &collection.aggregate_result;
+    let values = result.values();
+
+    match format {
+        OutputFormat::Human => {
+            match &collection.batch {
+                BatchSelector::TimeInterval(interval) => println!("Batch interval: {}", interval),
+                BatchSelector::FixedSize(batch_id) => println!("Batch ID: {}", batch_id),
+            }
+            println!("Reports: {}", collection.report_count);
+            if result.is_vector() {
+                println!("Result: {:?}", values);
+            } else {
+                println!("Result: {}", values[0]);
+            }
+        }
+        OutputFormat::Json => {
+            let result = if result.is_vector() {
+                json!(values)
+            } else {
+                json!(values[0])
+            };
+            let mut output = json!({
+                "report_count": collection.report_count,
+                "result": result,
+            });
+            match &collection.batch {
+                BatchSelector::TimeInterval(interval) => {
+                    output["batch_interval"] = json!({
+                        "start": interval.start.0,
+                        "duration": interval.duration.0,
+                    });
+                }
+                BatchSelector::FixedSize(batch_id) => {
+                    output["batch_id"] = json!(batch_id.to_string());
+                }
+            }
+            println!("{}", output);
+        }
+        OutputFormat::Csv => {
+            let result_columns: Vec<String> = if result.is_vector() {
+                (0..values.len()).map(|i| format!("result_{}", i)).collect()
+            } else {
+                vec!["result".to_string()]
+            };
+            let result_values: Vec<String> = values.iter().map(u64::to_string).collect();
+            let (batch_columns, batch_values) = match &collection.batch {
+                BatchSelector::TimeInterval(interval) => (
+                    "batch_interval_start,batch_interval_duration",
+                    format!("{},{}", interval.start, interval.duration),
+                ),
+                BatchSelector::FixedSize(batch_id) => ("batch_id", batch_id.to_string()),
+            };
+            println!(
+                "{},report_count,{}",
+                batch_columns,
+                result_columns.join(",")
+            );
+            println!(
+                "{},{},{}",
+                batch_values,
+                collection.report_count,
+                result_values.join(",")
+            );
+        }
+    }
+}
+
+/// Run the collect protocol and print the result, returning the process exit
+/// status.
+async fn collect<C>(ppm_parameters: &Parameters, vdaf: C, options: &Options) -> Result<i32>
+where
+    C: Collector + AggregateShareLength,
+    C::AggregateShare: NoisyAggregateShare,
+    C::AggregateResult: PrintableResult,
+{
+    let hpke_config =
+        hpke::Config::from_config_file(Role::Collector).wrap_err("loading HPKE config")?;
+    let aggregation_parameter = C::AggregationParam::get_decoded(
+        &hex::decode(&options.aggregation_parameter).wrap_err("decoding aggregation parameter")?,
+    )
+    .wrap_err("decoding aggregation parameter")?;
+    // Usage errors exit with status 2
+    let query = options.query(ppm_parameters).unwrap_or_else(|e| e.exit());
+    let collector = PpmCollector::new(ppm_parameters, &vdaf, &hpke_config)?;
+
+    match collector.query(query, &aggregation_parameter).await {
+        Ok(collection) => {
+            print_collection(&collection, options.format);
+            Ok(0)
+        }
+        Err(collect::Error::ProblemDocument(problem_document)) => {
+            let exit_status = match problem_document.type_url.as_deref() {
+                Some("urn:ietf:params:ppm:error:insufficientBatchSize") => {
+                    EXIT_INSUFFICIENT_BATCH_SIZE
+                }
+                Some("urn:ietf:params:ppm:error:privacyBudgetExceeded") => {
+                    EXIT_PRIVACY_BUDGET_EXCEEDED
+                }
+                Some("urn:ietf:params:ppm:error:invalidBatchInterval") => {
+                    EXIT_INVALID_BATCH_INTERVAL
+                }
+                _ => return Err(collect::Error::ProblemDocument(problem_document).into()),
+            };
+            eprintln!(
+                "collecting {:?} failed: {}",
+                query,
+                problem_document.detail.unwrap_or_default()
+            );
+            Ok(exit_status)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Pretty-print errors
     color_eyre::install()?;
-    trace::install_subscriber();
-
-    let ppm_parameters = Parameters::from_config_file()?;
-    let hpke_config = hpke::Config::from_config_file(Role::Collector)?;
-    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
-    let aggregate_share_length = vdaf.output_len();
-
-    let sum = run_collect(
-        &ppm_parameters,
-        &hpke_config,
-        Interval {
-            start: Time(1631907500),
-            duration: Duration(100),
-        },
-        vdaf,
-        &(),
-        aggregate_share_length,
+    let trace_guard = trace::install_subscriber(
+        &TraceConfiguration::load().wrap_err("loading trace configuration")?,
     )
-    .await?;
+    .wrap_err("installing trace subscriber")?;
+
+    let options = Options::parse();
+    let ppm_parameters = Parameters::from_config_file().wrap_err("loading task parameters")?;
 
-    println!("Sum: {:?}", sum);
+    let exit_status = match &ppm_parameters.vdaf {
+        VdafLabel::Prio3Count64 => {
+            collect(&ppm_parameters, Prio3Aes128Count::new(2)?, &options).await?
+        }
+        VdafLabel::Prio3Sum64 { bits } => {
+            collect(&ppm_parameters, Prio3Aes128Sum::new(2, *bits)?, &options).await?
+        }
+        VdafLabel::Prio3Histogram64 { buckets } => {
+            collect(
+                &ppm_parameters,
+                Prio3Aes128Histogram::new(2, buckets)?,
+                &options,
+            )
+            .await?
+        }
+        label => return Err(eyre!("unsupported VDAF {:?}", label)),
+    };
 
-    Ok(())
+    // Exiting skips destructors, so flush logs first
+    drop(trace_guard);
+    process::exit(exit_status);
 }
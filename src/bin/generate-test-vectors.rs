@@ -0,0 +1,38 @@
+use clap::Parser;
+use color_eyre::eyre::{Context, Result};
+use ppm_prototype::test_vectors::{hpke_vectors, message_vectors, HPKE_FILE, MESSAGES_FILE};
+use serde::Serialize;
+use std::{fs, path::PathBuf};
+
+/// Write test vectors for PPM message encodings and HPKE application info and
+/// associated data.
+///
+/// The vectors are checked in under `test-vectors` and must only be
+/// regenerated when an encoding deliberately changes.
+#[derive(Debug, Parser)]
+#[clap(version)]
+struct Options {
+    /// Directory to write the vectors to
+    #[clap(long, value_parser, default_value = "test-vectors")]
+    output: PathBuf,
+}
+
+fn write_json<T: Serialize>(path: PathBuf, value: &T) -> Result<()> {
+    let mut json = serde_json::to_string_pretty(value)?;
+    json.push('\n');
+    fs::write(&path, json).wrap_err_with(|| format!("writing {}", path.display()))?;
+    println!("wrote {}", path.display());
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let options = Options::parse();
+    fs::create_dir_all(&options.output).wrap_err("creating output directory")?;
+
+    write_json(options.output.join(MESSAGES_FILE), &message_vectors())?;
+    write_json(options.output.join(HPKE_FILE), &hpke_vectors())?;
+
+    Ok(())
+}
@@ -1,20 +1,79 @@
-use color_eyre::eyre::{Context, Result};
-use ppm_prototype::{helper::run_helper, hpke, parameters::Parameters, trace, Role};
-use prio::vdaf::prio3::Prio3Aes128Sum;
+use color_eyre::eyre::{eyre, Context, Result};
+use ppm_prototype::{
+    dp::NoisyAggregateShare,
+    helper::run_helper,
+    hpke,
+    parameters::{Parameters, VdafLabel},
+    task::TaskStore,
+    trace::{self, TraceConfiguration},
+    Role,
+};
+use prio::{
+    codec::{Encode, ParameterizedDecode},
+    vdaf::{
+        prio3::{Prio3Aes128Count, Prio3Aes128Histogram, Prio3Aes128Sum},
+        Aggregator,
+    },
+};
+
+async fn run<A>(ppm_parameters: &Parameters, vdaf: A, hpke_config: &hpke::Config) -> Result<()>
+where
+    A: Aggregator<AggregationParam = ()> + 'static + Send + Sync,
+    A::VerifyParam: Encode + ParameterizedDecode<A> + Send + Sync,
+    A::PrepareStep: Send + Sync,
+    A::AggregateShare: NoisyAggregateShare + Send + Sync,
+    A::PrepareMessage: Send + Sync,
+    A::OutputShare: Send + Sync,
+{
+    let verify_param = ppm_parameters
+        .decode_vdaf_verification_parameter(Role::Helper, &vdaf)
+        .wrap_err("decoding VDAF verification parameter")?;
+
+    let task_store = TaskStore::from_config_dir(Role::Helper);
+
+    run_helper(
+        ppm_parameters,
+        &vdaf,
+        &verify_param,
+        &(),
+        hpke_config,
+        task_store,
+    )
+    .await
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
-    trace::install_subscriber();
+    let _trace_guard = trace::install_subscriber(
+        &TraceConfiguration::load().wrap_err("loading trace configuration")?,
+    )
+    .wrap_err("installing trace subscriber")?;
 
     let ppm_parameters = Parameters::from_config_file().wrap_err("loading task parameters")?;
     let hpke_config =
         hpke::Config::from_config_file(Role::Helper).wrap_err("loading HPKE config")?;
-    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
-
-    let verify_param = ppm_parameters
-        .decode_vdaf_verification_parameter(Role::Helper, &vdaf)
-        .wrap_err("decoding VDAF verification parameter")?;
 
-    run_helper(&ppm_parameters, &vdaf, &verify_param, &(), &hpke_config).await
+    match &ppm_parameters.vdaf {
+        VdafLabel::Prio3Count64 => {
+            run(&ppm_parameters, Prio3Aes128Count::new(2)?, &hpke_config).await
+        }
+        VdafLabel::Prio3Sum64 { bits } => {
+            run(
+                &ppm_parameters,
+                Prio3Aes128Sum::new(2, *bits)?,
+                &hpke_config,
+            )
+            .await
+        }
+        VdafLabel::Prio3Histogram64 { buckets } => {
+            run(
+                &ppm_parameters,
+                Prio3Aes128Histogram::new(2, buckets)?,
+                &hpke_config,
+            )
+            .await
+        }
+        label => Err(eyre!("unsupported VDAF {:?}", label)),
+    }
 }
@@ -1,20 +1,92 @@
-use color_eyre::eyre::{Context, Result};
-use ppm_prototype::{hpke, leader::run_leader, parameters::Parameters, trace, Role};
-use prio::vdaf::prio3::Prio3Aes128Sum;
+use color_eyre::eyre::{eyre, Context, Result};
+use ppm_prototype::{
+    dp::NoisyAggregateShare,
+    hpke,
+    leader::run_leader,
+    parameters::{Parameters, VdafLabel},
+    rate_limit::{PendingReportLimit, MAX_PENDING_REPORTS_ENV},
+    task::TaskStore,
+    trace::{self, TraceConfiguration},
+    Role,
+};
+use prio::{
+    codec::{Encode, ParameterizedDecode},
+    vdaf::{
+        prio3::{Prio3Aes128Count, Prio3Aes128Histogram, Prio3Aes128Sum},
+        Aggregator,
+    },
+};
+use std::env;
+
+async fn run<A>(ppm_parameters: &Parameters, vdaf: A, hpke_config: &hpke::Config) -> Result<()>
+where
+    A: Aggregator<AggregationParam = ()> + 'static + Send + Sync,
+    A::VerifyParam: Encode + ParameterizedDecode<A> + Send + Sync,
+    A::PrepareStep: Send + Sync,
+    A::AggregateShare: NoisyAggregateShare + Send + Sync,
+    A::PrepareMessage: Send + Sync,
+    A::OutputShare: Send + Sync,
+{
+    let verify_param = ppm_parameters
+        .decode_vdaf_verification_parameter(Role::Leader, &vdaf)
+        .wrap_err("decoding VDAF verification parameter")?;
+
+    let task_store = TaskStore::from_config_dir(Role::Leader);
+
+    // The cap on pending reports across all tasks isn't a task parameter
+    let max_pending_reports = match env::var(MAX_PENDING_REPORTS_ENV) {
+        Ok(max_pending_reports) => Some(
+            max_pending_reports
+                .parse()
+                .wrap_err_with(|| format!("parsing {}", MAX_PENDING_REPORTS_ENV))?,
+        ),
+        Err(_) => None,
+    };
+
+    run_leader(
+        ppm_parameters,
+        &vdaf,
+        &verify_param,
+        &(),
+        hpke_config,
+        task_store,
+        PendingReportLimit::new(max_pending_reports),
+    )
+    .await
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
-    trace::install_subscriber();
+    let _trace_guard = trace::install_subscriber(
+        &TraceConfiguration::load().wrap_err("loading trace configuration")?,
+    )
+    .wrap_err("installing trace subscriber")?;
 
     let ppm_parameters = Parameters::from_config_file().wrap_err("loading task parameters")?;
     let hpke_config =
         hpke::Config::from_config_file(Role::Leader).wrap_err("loading hpke config")?;
-    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
-
-    let verify_param = ppm_parameters
-        .decode_vdaf_verification_parameter(Role::Leader, &vdaf)
-        .wrap_err("decoding VDAF verification parameter")?;
 
-    run_leader(&ppm_parameters, &vdaf, &verify_param, &(), &hpke_config).await
+    match &ppm_parameters.vdaf {
+        VdafLabel::Prio3Count64 => {
+            run(&ppm_parameters, Prio3Aes128Count::new(2)?, &hpke_config).await
+        }
+        VdafLabel::Prio3Sum64 { bits } => {
+            run(
+                &ppm_parameters,
+                Prio3Aes128Sum::new(2, *bits)?,
+                &hpke_config,
+            )
+            .await
+        }
+        VdafLabel::Prio3Histogram64 { buckets } => {
+            run(
+                &ppm_parameters,
+                Prio3Aes128Histogram::new(2, buckets)?,
+                &hpke_config,
+            )
+            .await
+        }
+        label => Err(eyre!("unsupported VDAF {:?}", label)),
+    }
 }
@@ -0,0 +1,69 @@
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{Report, Result};
+use ppm_prototype::parameters::{self, Parameters};
+use std::{fs::File, path::PathBuf, process};
+
+/// Exit status when every file checked is valid
+const EXIT_SUCCESS: i32 = 0;
+/// Exit status when at least one file checked is invalid
+const EXIT_INVALID: i32 = 1;
+
+/// Administer PPM tasks.
+#[derive(Debug, Parser)]
+#[clap(version)]
+struct Options {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check that task parameters files are valid, listing every problem found
+    /// in each. Defaults to `parameters.json` in the config directory.
+    Validate {
+        #[clap(value_parser)]
+        files: Vec<PathBuf>,
+    },
+}
+
+/// Check each of the files, printing the outcome for each, and return the exit
+/// status
+fn validate(files: Vec<PathBuf>) -> i32 {
+    let files = if files.is_empty() {
+        vec![Parameters::config_file_path()]
+    } else {
+        files
+    };
+
+    let mut exit_status = EXIT_SUCCESS;
+    for path in files {
+        let result = File::open(&path)
+            .map_err(|e| parameters::Error::File(e, path.clone()))
+            .and_then(Parameters::from_json_reader);
+
+        match result {
+            Ok(parameters) => println!("{}: task {} is valid", path.display(), parameters.task_id),
+            Err(parameters::Error::Invalid(errors)) => {
+                println!("{}: invalid parameters:\n{}", path.display(), errors);
+                exit_status = EXIT_INVALID;
+            }
+            Err(e) => {
+                println!("{}: {:#}", path.display(), Report::new(e));
+                exit_status = EXIT_INVALID;
+            }
+        }
+    }
+
+    exit_status
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let options = Options::parse();
+    let exit_status = match options.command {
+        Command::Validate { files } => validate(files),
+    };
+
+    process::exit(exit_status);
+}
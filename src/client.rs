@@ -1,7 +1,8 @@
 use crate::{
+    auth::ClientCredential,
     hpke::{self, Label},
     parameters::Parameters,
-    report::Report,
+    report::{Extension, Report},
     Nonce, Role, Time,
 };
 use http::{header::CONTENT_TYPE, StatusCode};
@@ -25,9 +26,11 @@ pub enum Error {
     #[error("Unspecified error: {0}")]
     Unspecified(String),
     #[error("HTTP problem document {0}")]
-    ProblemDocument(HttpApiProblem),
+    ProblemDocument(Box<HttpApiProblem>),
     #[error("HTTP response status {0} body:\n{1:?}")]
-    HttpFailure(StatusCode, Option<Response>),
+    HttpFailure(StatusCode, Option<Box<Response>>),
+    #[error("authentication error {0}")]
+    Auth(#[from] crate::auth::Error),
 }
 
 static CLIENT_USER_AGENT: &str = concat!(
@@ -38,6 +41,24 @@ static CLIENT_USER_AGENT: &str = concat!(
     "client"
 );
 
+/// Ways to corrupt an input share, so that tests can check that aggregators
+/// exclude such reports from aggregates
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Tamper {
+    /// Flip a bit in the encoded input share before it is encrypted. The share
+    /// decrypts, but fails VDAF preparation.
+    EncodedShare,
+    /// Flip a bit in the encrypted input share, which then can't be decrypted
+    Ciphertext,
+}
+
+/// Flip the last bit of `bytes`
+fn flip_bit(bytes: &mut [u8]) {
+    if let Some(last) = bytes.last_mut() {
+        *last ^= 1;
+    }
+}
+
 #[derive(Debug)]
 pub struct PpmClient<C: Client> {
     http_client: reqwest::Client,
@@ -46,6 +67,10 @@ pub struct PpmClient<C: Client> {
     helper_hpke_config: hpke::Config,
     vdaf: C,
     public_parameter: C::PublicParam,
+    /// Extensions attached to every report
+    extensions: Vec<Extension>,
+    /// Credential with which uploads are authenticated, if the task requires it
+    credential: Option<ClientCredential>,
 }
 
 impl<C: Client> PpmClient<C> {
@@ -74,26 +99,40 @@ impl<C: Client> PpmClient<C> {
             helper_hpke_config,
             vdaf: vdaf_client.clone(),
             public_parameter,
+            extensions: vec![],
+            credential: None,
         })
     }
 
-    pub async fn do_upload(&self, time: u64, input: &C::Measurement) -> Result<(), Error> {
-        let tamper_func = |input_share: &C::InputShare| input_share.clone();
-        let tamper_func_ref = &tamper_func as &dyn Fn(&C::InputShare) -> C::InputShare;
+    /// Authenticate uploads with the credential
+    pub fn with_credential(mut self, credential: ClientCredential) -> Self {
+        self.credential = Some(credential);
+        self
+    }
+
+    /// Attach the extension to every report uploaded by this client
+    pub fn with_extension(mut self, extension: Extension) -> Self {
+        self.extensions.push(extension);
+        self
+    }
 
-        self.do_upload_tamper(time, input, tamper_func_ref, tamper_func_ref)
-            .await
+    pub async fn do_upload(&self, time: u64, input: &C::Measurement) -> Result<(), Error> {
+        self.do_upload_tamper(time, input, None, None).await
     }
 
+    /// Upload a report, corrupting the leader's and helper's input shares as
+    /// requested
     pub async fn do_upload_tamper(
         &self,
         time: u64,
         input: &C::Measurement,
-        tamper_leader_share: &dyn Fn(&C::InputShare) -> C::InputShare,
-        tamper_helper_share: &dyn Fn(&C::InputShare) -> C::InputShare,
+        tamper_leader_share: Option<Tamper>,
+        tamper_helper_share: Option<Tamper>,
     ) -> Result<(), Error> {
+        // Truncate the report time so that the leader learns no more about when
+        // the measurement was taken than the task's time precision allows
         let timestamp = Nonce {
-            time: Time(time),
+            time: self.parameters.report_time(Time(time)),
             rand: rand::random(),
         };
 
@@ -101,19 +140,23 @@ impl<C: Client> PpmClient<C> {
         // then proof share.
         let upload_shares = self.vdaf.shard(&self.public_parameter, input)?;
 
-        // Allow the caller to tamper with the input shares to force proof
-        // verification to fail
-        let leader_upload_share =
-            tamper_leader_share(&upload_shares[Role::Leader.index()]).get_encoded();
-        let helper_upload_share =
-            tamper_helper_share(&upload_shares[Role::Helper.index()]).get_encoded();
-        info!(
-            helper_upload_share = ?upload_shares[Role::Helper.index()],
-            tampered_helper_upload_share =
-                ?tamper_helper_share(&upload_shares[Role::Helper.index()]),
-            tampered_helper_upload_share_len = helper_upload_share.len(),
-            "encoding helper share"
-        );
+        let (leader_share, helper_share) = match upload_shares.as_slice() {
+            [leader_share, helper_share] => (leader_share, helper_share),
+            _ => {
+                return Err(Error::Unspecified(format!(
+                    "VDAF produced {} input shares, expected 2",
+                    upload_shares.len()
+                )))
+            }
+        };
+        let mut leader_upload_share = leader_share.get_encoded();
+        let mut helper_upload_share = helper_share.get_encoded();
+        if tamper_leader_share == Some(Tamper::EncodedShare) {
+            flip_bit(&mut leader_upload_share);
+        }
+        if tamper_helper_share == Some(Tamper::EncodedShare) {
+            flip_bit(&mut helper_upload_share);
+        }
 
         let leader_hpke_sender = self.leader_hpke_config.sender(
             &self.parameters.task_id,
@@ -129,25 +172,34 @@ impl<C: Client> PpmClient<C> {
             Role::Helper,
         )?;
 
-        let extensions = vec![];
-        let associated_data = Report::associated_data(timestamp, &extensions);
+        let associated_data = Report::associated_data(timestamp, &self.extensions);
+
+        let mut encrypted_leader_share =
+            leader_hpke_sender.seal(&leader_upload_share, &associated_data)?;
+        let mut encrypted_helper_share =
+            helper_hpke_sender.seal(&helper_upload_share, &associated_data)?;
+        if tamper_leader_share == Some(Tamper::Ciphertext) {
+            flip_bit(&mut encrypted_leader_share.payload);
+        }
+        if tamper_helper_share == Some(Tamper::Ciphertext) {
+            flip_bit(&mut encrypted_helper_share.payload);
+        }
 
         let report = Report {
             nonce: timestamp,
             task_id: self.parameters.task_id,
-            encrypted_input_shares: vec![
-                leader_hpke_sender.seal(&leader_upload_share, &associated_data)?,
-                helper_hpke_sender.seal(&helper_upload_share, &associated_data)?,
-            ],
-            extensions: vec![],
+            encrypted_input_shares: vec![encrypted_leader_share, encrypted_helper_share],
+            extensions: self.extensions.clone(),
         };
 
-        let upload_response = self
-            .http_client
-            .post(self.parameters.upload_endpoint()?)
-            .body(report.get_encoded())
-            .send()
-            .await?;
+        let encoded_report = report.get_encoded();
+        let mut upload_request = self.http_client.post(self.parameters.upload_endpoint()?);
+        if let Some(credential) = &self.credential {
+            let (name, value) = credential.header(&encoded_report)?;
+            upload_request = upload_request.header(name, value);
+        }
+
+        let upload_response = upload_request.body(encoded_report).send().await?;
         let status = upload_response.status();
         if !status.is_success() {
             match upload_response.headers().get(CONTENT_TYPE) {
@@ -159,7 +211,7 @@ impl<C: Client> PpmClient<C> {
                         Err(_) => return Err(Error::HttpFailure(status, None)),
                     }
                 }
-                _ => return Err(Error::HttpFailure(status, Some(upload_response))),
+                _ => return Err(Error::HttpFailure(status, Some(Box::new(upload_response)))),
             }
         }
 
@@ -183,7 +235,12 @@ impl<C: Client> PpmClient<C> {
                         Err(_) => return Err(Error::HttpFailure(status, None)),
                     }
                 }
-                _ => return Err(Error::HttpFailure(status, Some(aggregate_response))),
+                _ => {
+                    return Err(Error::HttpFailure(
+                        status,
+                        Some(Box::new(aggregate_response)),
+                    ))
+                }
             }
         }
 
@@ -1,20 +1,22 @@
 //! The collect portion of the PPM protocol
 
 use crate::{
+    dp::NoisyAggregateShare,
     error::{IntoHttpApiProblem, ProblemDocumentType},
     hpke,
     parameters::{Parameters, TaskId},
-    Interval, Role,
+    BatchId, BatchSelector, Interval, Role, Time,
 };
 use http::{header::CONTENT_TYPE, StatusCode};
 use http_api_problem::HttpApiProblem;
 use prio::{
     codec::{decode_u16_items, encode_u16_items, CodecError, Decode, Encode, ParameterizedDecode},
-    vdaf::{Collector, Vdaf},
+    flp::Type,
+    vdaf::{prg::Prg, prio3::Prio3, Aggregatable, Collector, Vdaf},
 };
 use reqwest::{Client, Response};
-use std::io::Cursor;
-use tracing::info;
+use std::{fmt::Debug, io::Cursor};
+use tracing::{info, warn};
 
 static COLLECTOR_USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
@@ -30,9 +32,11 @@ pub enum Error {
     #[error("encryption error")]
     Encryption(#[from] crate::hpke::Error),
     #[error("HTTP problem document {0}")]
-    ProblemDocument(HttpApiProblem),
+    ProblemDocument(Box<HttpApiProblem>),
     #[error("HTTP response status {0} body:\n{1:?}")]
-    HttpFailure(StatusCode, Option<Response>),
+    HttpFailure(StatusCode, Option<Box<Response>>),
+    #[error("collect response has no aggregate share from the {}", .0.name())]
+    MissingAggregateShare(Role),
     #[error("lengths do not match: leader {0} helper {1}")]
     LengthMismatch(u64, u64),
     #[error("reqwest error")]
@@ -45,6 +49,8 @@ pub enum Error {
     Vdaf(#[from] prio::vdaf::VdafError),
     #[error("{0}")]
     Unspecified(&'static str),
+    #[error("interval {0} of the series does not fit in a batch interval")]
+    SeriesOutOfRange(u64),
     #[error("I/O error")]
     Io(#[from] std::io::Error),
     #[error("Codec error")]
@@ -57,24 +63,111 @@ impl IntoHttpApiProblem for Error {
     }
 }
 
+/// The batch a collector wants to collect, which must match the task's query
+/// type.
+///
+/// struct {
+///   QueryType query_type;
+///   select (Query.query_type) {
+///     case time_interval: Interval batch_interval;
+///     case fixed_size: FixedSizeQuery fixed_size_query;
+///   }
+/// } Query;
+///
+/// struct {
+///   FixedSizeQueryType query_type;
+///   select (FixedSizeQuery.query_type) {
+///     case by_batch_id: BatchID batch_id;
+///     case current_batch: Empty;
+///   }
+/// } FixedSizeQuery;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Query {
+    /// Collect the reports whose timestamps fall in the batch interval
+    TimeInterval { batch_interval: Interval },
+    /// Collect the batch with the given ID, or if none is given, the oldest
+    /// batch that the leader considers complete and that has not yet been
+    /// collected
+    FixedSize { batch_id: Option<BatchId> },
+}
+
+impl Query {
+    /// Returns true if `batch` is a batch that could satisfy this query
+    pub fn matches(&self, batch: &BatchSelector) -> bool {
+        match (self, batch) {
+            (Self::TimeInterval { batch_interval }, BatchSelector::TimeInterval(interval)) => {
+                batch_interval == interval
+            }
+            (Self::FixedSize { batch_id: None }, BatchSelector::FixedSize(_)) => true,
+            (
+                Self::FixedSize {
+                    batch_id: Some(batch_id),
+                },
+                BatchSelector::FixedSize(id),
+            ) => batch_id == id,
+            _ => false,
+        }
+    }
+}
+
+impl Encode for Query {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Self::TimeInterval { batch_interval } => {
+                1u8.encode(bytes);
+                batch_interval.encode(bytes);
+            }
+            Self::FixedSize {
+                batch_id: Some(batch_id),
+            } => {
+                2u8.encode(bytes);
+                0u8.encode(bytes);
+                batch_id.encode(bytes);
+            }
+            Self::FixedSize { batch_id: None } => {
+                2u8.encode(bytes);
+                1u8.encode(bytes);
+            }
+        }
+    }
+}
+
+impl Decode for Query {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        match u8::decode(bytes)? {
+            1 => Ok(Self::TimeInterval {
+                batch_interval: Interval::decode(bytes)?,
+            }),
+            2 => match u8::decode(bytes)? {
+                0 => Ok(Self::FixedSize {
+                    batch_id: Some(BatchId::decode(bytes)?),
+                }),
+                1 => Ok(Self::FixedSize { batch_id: None }),
+                _ => Err(CodecError::UnexpectedValue),
+            },
+            _ => Err(CodecError::UnexpectedValue),
+        }
+    }
+}
+
 /// A collect request sent to a leader from a collector.
 ///
 /// struct {
 ///   TaskID task_id;
-///   Interval batch_interval;
+///   Query query;
 ///   opaque agg_param<0..2^16-1>;
 /// } CollectReq;
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct CollectRequest<V: Vdaf> {
     pub task_id: TaskId,
-    pub batch_interval: Interval,
+    pub query: Query,
     pub aggregation_parameter: V::AggregationParam,
 }
 
 impl<V: Vdaf> Encode for CollectRequest<V> {
     fn encode(&self, bytes: &mut Vec<u8>) {
         self.task_id.encode(bytes);
-        self.batch_interval.encode(bytes);
+        self.query.encode(bytes);
         // CollectReq.agg_param is encoded as a variable length opaque byte
         // string
         let aggregation_parameter_bytes = self.aggregation_parameter.get_encoded();
@@ -85,7 +178,7 @@ impl<V: Vdaf> Encode for CollectRequest<V> {
 impl<V: Vdaf> Decode for CollectRequest<V> {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
         let task_id = TaskId::decode(bytes)?;
-        let batch_interval = Interval::decode(bytes)?;
+        let query = Query::decode(bytes)?;
         // CollectReq.agg_param is encoded as a variable length opaque byte
         // string. Decode the byte string into Vec<u8>, then decode that into
         // V::AggregationParam.
@@ -94,7 +187,7 @@ impl<V: Vdaf> Decode for CollectRequest<V> {
 
         Ok(Self {
             task_id,
-            batch_interval,
+            query,
             aggregation_parameter,
         })
     }
@@ -102,104 +195,302 @@ impl<V: Vdaf> Decode for CollectRequest<V> {
 
 /// The response to a collect request
 /// struct {
+///   BatchSelector batch_selector;
+///   uint64 report_count;
 ///   HpkeCiphertext encrypted_agg_shares shares<1..2^16-1>;
 /// } CollectResp;
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct CollectResponse {
+    /// The batch that was collected. For fixed size queries for the current
+    /// batch, this tells the collector which batch the leader chose.
+    pub batch_selector: BatchSelector,
+    /// Number of reports included in the aggregate, noised if the task has
+    /// differential privacy
+    pub report_count: u64,
     pub encrypted_agg_shares: Vec<hpke::Ciphertext>,
 }
 
 impl Encode for CollectResponse {
     fn encode(&self, bytes: &mut Vec<u8>) {
+        self.batch_selector.encode(bytes);
+        self.report_count.encode(bytes);
         encode_u16_items(bytes, &(), &self.encrypted_agg_shares);
     }
 }
 
 impl Decode for CollectResponse {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        let batch_selector = BatchSelector::decode(bytes)?;
+        let report_count = u64::decode(bytes)?;
         let encrypted_output_shares = decode_u16_items(&(), bytes)?;
 
         Ok(Self {
+            batch_selector,
+            report_count,
             encrypted_agg_shares: encrypted_output_shares,
         })
     }
 }
 
-pub async fn run_collect<C: Collector>(
-    ppm_parameters: &Parameters,
-    hpke_config: &hpke::Config,
-    batch_interval: Interval,
+/// The outcome of a successful collect request: the aggregate result along
+/// with the batch it covers and the number of reports included in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Collection<R> {
+    pub batch: BatchSelector,
+    pub report_count: u64,
+    pub aggregate_result: R,
+}
+
+/// VDAFs whose aggregate shares have a length that can be determined from the
+/// VDAF instance. The collector needs this to decode aggregate shares.
+pub trait AggregateShareLength {
+    /// Length of the VDAF's aggregate shares, in field elements
+    fn aggregate_share_length(&self) -> usize;
+}
+
+impl<T, A, P, const L: usize> AggregateShareLength for Prio3<T, A, P, L>
+where
+    T: Type,
+    A: Clone + Debug,
+    P: Prg<L>,
+{
+    fn aggregate_share_length(&self) -> usize {
+        self.output_len()
+    }
+}
+
+impl Error {
+    /// Returns true if the error may not recur if the collect request is
+    /// retried, e.g. because an aggregator was unreachable or failed
+    /// internally, or because too few reports had been aggregated yet. Refused
+    /// collect requests consume no privacy budget, so retrying is safe.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Reqwest(_) => true,
+            Self::HttpFailure(status, _) => status.is_server_error(),
+            Self::ProblemDocument(problem_document) => {
+                problem_document
+                    .status
+                    .map(|status| status.is_server_error())
+                    .unwrap_or(false)
+                    || problem_document.type_url == Some(ProblemDocumentType::HelperError.into())
+                    || problem_document.type_url
+                        == Some(ProblemDocumentType::InsufficientBatchSize.into())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A collector for a PPM task. Holds the task parameters and the collector's
+/// HPKE configuration, and reuses a single HTTP client across collect requests.
+#[derive(Debug)]
+pub struct PpmCollector<C: Collector> {
+    http_client: Client,
+    parameters: Parameters,
+    hpke_config: hpke::Config,
     vdaf: C,
-    aggregation_parameter: &C::AggregationParam,
-    aggregate_share_length: usize,
-) -> Result<C::AggregateResult, Error> {
-    let http_client = Client::builder().user_agent(COLLECTOR_USER_AGENT).build()?;
-
-    let collect_request: CollectRequest<C> = CollectRequest {
-        task_id: ppm_parameters.task_id,
-        batch_interval,
-        aggregation_parameter: aggregation_parameter.clone(),
-    };
-
-    let collect_response = http_client
-        .post(ppm_parameters.collect_endpoint()?)
-        .body(collect_request.get_encoded())
-        .send()
-        .await?;
-
-    let status = collect_response.status();
-    info!(http_status = ?status, "collect request HTTP status");
-    if !status.is_success() {
-        match collect_response.headers().get(CONTENT_TYPE) {
-            Some(content_type) if content_type == "application/problem+json" => {
-                match collect_response.json().await {
-                    Ok(problem_document) => return Err(Error::ProblemDocument(problem_document)),
-                    Err(_) => return Err(Error::HttpFailure(status, None)),
+}
+
+impl<C> PpmCollector<C>
+where
+    C: Collector + AggregateShareLength,
+    C::AggregateShare: NoisyAggregateShare,
+{
+    pub fn new(
+        ppm_parameters: &Parameters,
+        vdaf: &C,
+        hpke_config: &hpke::Config,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            http_client: Client::builder().user_agent(COLLECTOR_USER_AGENT).build()?,
+            parameters: ppm_parameters.clone(),
+            hpke_config: hpke_config.clone(),
+            vdaf: vdaf.clone(),
+        })
+    }
+
+    /// Collect the aggregate over the batch interval, making a single collect
+    /// request to the leader.
+    pub async fn collect(
+        &self,
+        batch_interval: Interval,
+        aggregation_parameter: &C::AggregationParam,
+    ) -> Result<Collection<C::AggregateResult>, Error> {
+        self.query(
+            Query::TimeInterval { batch_interval },
+            aggregation_parameter,
+        )
+        .await
+    }
+
+    /// Collect the aggregate over a fixed size batch, making a single collect
+    /// request to the leader. If `batch_id` is `None`, the leader chooses the
+    /// oldest complete batch that has not yet been collected.
+    pub async fn collect_fixed_size(
+        &self,
+        batch_id: Option<BatchId>,
+        aggregation_parameter: &C::AggregationParam,
+    ) -> Result<Collection<C::AggregateResult>, Error> {
+        self.query(Query::FixedSize { batch_id }, aggregation_parameter)
+            .await
+    }
+
+    /// Collect the aggregate over the batch described by `query`, making a
+    /// single collect request to the leader.
+    #[tracing::instrument(skip(self, aggregation_parameter), err)]
+    pub async fn query(
+        &self,
+        query: Query,
+        aggregation_parameter: &C::AggregationParam,
+    ) -> Result<Collection<C::AggregateResult>, Error> {
+        let collect_request: CollectRequest<C> = CollectRequest {
+            task_id: self.parameters.task_id,
+            query,
+            aggregation_parameter: aggregation_parameter.clone(),
+        };
+
+        let collect_response = self
+            .http_client
+            .post(self.parameters.collect_endpoint()?)
+            .body(collect_request.get_encoded())
+            .send()
+            .await?;
+
+        let status = collect_response.status();
+        info!(http_status = ?status, "collect request HTTP status");
+        if !status.is_success() {
+            match collect_response.headers().get(CONTENT_TYPE) {
+                Some(content_type) if content_type == "application/problem+json" => {
+                    match collect_response.json().await {
+                        Ok(problem_document) => {
+                            return Err(Error::ProblemDocument(problem_document))
+                        }
+                        Err(_) => return Err(Error::HttpFailure(status, None)),
+                    }
                 }
+                _ => return Err(Error::HttpFailure(status, Some(Box::new(collect_response)))),
             }
-            _ => return Err(Error::HttpFailure(status, Some(collect_response))),
         }
-    }
 
-    let collect_response = CollectResponse::get_decoded(&collect_response.bytes().await?)?;
+        let collect_response = CollectResponse::get_decoded(&collect_response.bytes().await?)?;
 
-    let leader_ciphertext = &collect_response.encrypted_agg_shares[Role::Leader.index()];
+        let batch = collect_response.batch_selector;
+        if !query.matches(&batch) {
+            return Err(Error::Unspecified(
+                "leader collected a batch other than the one requested",
+            ));
+        }
 
-    let leader_recipient = hpke_config.recipient(
-        &ppm_parameters.task_id,
-        hpke::Label::AggregateShare,
-        Role::Leader,
-        Role::Collector,
-        &leader_ciphertext.encapsulated_context,
-    )?;
+        let leader_share = self.open_aggregate_share(
+            Role::Leader,
+            &batch,
+            Role::Leader
+                .entry(&collect_response.encrypted_agg_shares)
+                .ok_or(Error::MissingAggregateShare(Role::Leader))?,
+        )?;
+        let helper_share = self.open_aggregate_share(
+            Role::Helper,
+            &batch,
+            Role::Helper
+                .entry(&collect_response.encrypted_agg_shares)
+                .ok_or(Error::MissingAggregateShare(Role::Helper))?,
+        )?;
 
-    let leader_share = C::AggregateShare::get_decoded_with_param(
-        &aggregate_share_length,
-        &leader_recipient.open(leader_ciphertext, &batch_interval.associated_data())?,
-    )?;
+        let aggregate_shares = if self.parameters.differential_privacy.is_some() {
+            // Noise may have taken elements of the aggregate below zero, which
+            // VDAFs with unsigned results can't represent
+            let mut aggregate = leader_share;
+            aggregate.merge(&helper_share)?;
+            aggregate.clamp_negative();
+            vec![aggregate]
+        } else {
+            vec![leader_share, helper_share]
+        };
 
-    let helper_ciphertext = &collect_response.encrypted_agg_shares[Role::Helper.index()];
+        Ok(Collection {
+            batch,
+            report_count: collect_response.report_count,
+            aggregate_result: self.vdaf.unshard(aggregation_parameter, aggregate_shares)?,
+        })
+    }
 
-    let helper_recipient = hpke_config.recipient(
-        &ppm_parameters.task_id,
-        hpke::Label::AggregateShare,
-        Role::Helper,
-        Role::Collector,
-        &helper_ciphertext.encapsulated_context,
-    )?;
+    /// Collect the aggregate over the batch described by `query`, retrying up
+    /// to `max_attempts` times at `poll_interval` for as long as collect
+    /// requests fail with transient errors.
+    pub async fn poll_collect(
+        &self,
+        query: Query,
+        aggregation_parameter: &C::AggregationParam,
+        poll_interval: std::time::Duration,
+        max_attempts: usize,
+    ) -> Result<Collection<C::AggregateResult>, Error> {
+        let mut attempt = 1;
+        loop {
+            match self.query(query, aggregation_parameter).await {
+                Err(error) if error.is_transient() && attempt < max_attempts => {
+                    warn!(?error, attempt, "collect request failed, retrying");
+                    attempt += 1;
+                    tokio::time::sleep(poll_interval).await;
+                }
+                result => return result,
+            }
+        }
+    }
 
-    let helper_share = C::AggregateShare::get_decoded_with_param(
-        &aggregate_share_length,
-        &helper_recipient.open(helper_ciphertext, &batch_interval.associated_data())?,
-    )?;
+    /// Collect the aggregates over `count` consecutive batch intervals, each of
+    /// the same duration as `first_batch_interval`. A failure to collect one
+    /// interval does not prevent collection of the others. The series ends
+    /// early, with an error, at the first interval whose start can't be
+    /// represented.
+    pub async fn collect_series(
+        &self,
+        first_batch_interval: Interval,
+        count: u64,
+        aggregation_parameter: &C::AggregationParam,
+    ) -> Vec<Result<Collection<C::AggregateResult>, Error>> {
+        let mut collections = vec![];
+        for i in 0..count {
+            let start = match first_batch_interval
+                .duration
+                .0
+                .checked_mul(i)
+                .and_then(|offset| first_batch_interval.start.0.checked_add(offset))
+            {
+                Some(start) => Time(start),
+                None => {
+                    collections.push(Err(Error::SeriesOutOfRange(i)));
+                    break;
+                }
+            };
+            let batch_interval = Interval {
+                start,
+                duration: first_batch_interval.duration,
+            };
+            collections.push(self.collect(batch_interval, aggregation_parameter).await);
+        }
 
-    // TODO: include contribution count in aggregate share somehow
-    // if decrypted_leader_share.contributions != decrypted_helper_share.contributions {
-    //     return Err(Error::LengthMismatch(
-    //         decrypted_leader_share.contributions,
-    //         decrypted_helper_share.contributions,
-    //     ));
-    // }
+        collections
+    }
+
+    /// Decrypt and decode an aggregator's aggregate share
+    fn open_aggregate_share(
+        &self,
+        aggregator_role: Role,
+        batch: &BatchSelector,
+        ciphertext: &hpke::Ciphertext,
+    ) -> Result<C::AggregateShare, Error> {
+        let recipient = self.hpke_config.recipient(
+            &self.parameters.task_id,
+            hpke::Label::AggregateShare,
+            aggregator_role,
+            Role::Collector,
+            &ciphertext.encapsulated_context,
+        )?;
 
-    Ok(vdaf.unshard(aggregation_parameter, [leader_share, helper_share])?)
+        Ok(C::AggregateShare::get_decoded_with_param(
+            &self.vdaf.aggregate_share_length(),
+            &recipient.open(ciphertext, &batch.associated_data())?,
+        )?)
+    }
 }
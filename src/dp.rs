@@ -0,0 +1,614 @@
+//! Differential privacy for aggregate shares.
+//!
+//! Each aggregator adds noise drawn from a discrete Laplace or discrete
+//! Gaussian distribution to every element of its aggregate share before
+//! encrypting it to the collector. Since each aggregator's noise alone is
+//! calibrated to the task's privacy parameters, the unsharded aggregate
+//! satisfies differential privacy even if the collector colludes with one of
+//! the aggregators. The leader also noises the report count it returns to the
+//! collector, and aggregators don't publish exact per-batch counts for tasks
+//! with differential privacy, since an exact count alone could reveal whether
+//! a report was included. The task's epsilon and delta are split evenly
+//! between the aggregate share and the report count, so that together they
+//! spend no more than the configured budget.
+//!
+//! The samplers are the exact ones from Canonne, Kamath and Steinke, "The
+//! Discrete Gaussian for Differential Privacy" (https://arxiv.org/abs/2004.00010),
+//! and work only with integers and rationals so that floating point artifacts
+//! cannot leak information about the aggregate.
+//!
+//! Noise may drive an element of the aggregate below zero, in which case it
+//! wraps around the field modulus. The collector treats elements in the upper
+//! half of the field as negative and clamps them to zero before unsharding, so
+//! that VDAFs whose results are unsigned integers, such as histograms with
+//! empty buckets, can still be collected. Since clamping is post-processing of
+//! the noised aggregate, it doesn't weaken the privacy guarantee.
+
+use prio::{field::FieldElement, vdaf::AggregateShare};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid differential privacy parameter: {0}")]
+    InvalidParameter(&'static str),
+    #[error("noise too large for field")]
+    NoiseTooLarge,
+    #[error("noise sample out of range")]
+    SampleOutOfRange,
+}
+
+/// A non-negative rational number
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Rational {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Rational {
+    fn as_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    /// Half of this number, or None if its denominator would overflow
+    fn halved(&self) -> Option<Self> {
+        if self.numerator.is_multiple_of(2) {
+            Some(Self {
+                numerator: self.numerator / 2,
+                denominator: self.denominator,
+            })
+        } else {
+            Some(Self {
+                numerator: self.numerator,
+                denominator: self.denominator.checked_mul(2)?,
+            })
+        }
+    }
+}
+
+/// Mechanism used to generate noise
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Mechanism {
+    /// Discrete Laplace noise, providing pure `epsilon`-differential privacy
+    DiscreteLaplace { epsilon: Rational },
+    /// Discrete Gaussian noise, providing approximate `(epsilon,
+    /// delta)`-differential privacy. Epsilon must be less than 1.
+    DiscreteGaussian { epsilon: Rational, delta: Rational },
+}
+
+impl Mechanism {
+    /// The same mechanism with half of the privacy budget
+    fn halved(&self) -> Result<Self, Error> {
+        let halved = |value: &Rational| {
+            value
+                .halved()
+                .ok_or(Error::InvalidParameter("privacy budget too small to split"))
+        };
+        Ok(match self {
+            Self::DiscreteLaplace { epsilon } => Self::DiscreteLaplace {
+                epsilon: halved(epsilon)?,
+            },
+            Self::DiscreteGaussian { epsilon, delta } => Self::DiscreteGaussian {
+                epsilon: halved(epsilon)?,
+                delta: halved(delta)?,
+            },
+        })
+    }
+}
+
+/// A task's differential privacy configuration. Half of the budget is spent
+/// on the aggregate share and half on the report count.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DifferentialPrivacy {
+    pub mechanism: Mechanism,
+    /// The most that a single report can change any element of an aggregate
+    /// share, e.g. 1 for a count or `2^bits - 1` for a sum.
+    pub sensitivity: u64,
+}
+
+impl DifferentialPrivacy {
+    /// Check that the parameters describe a valid mechanism
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.sensitivity == 0 {
+            return Err(Error::InvalidParameter("sensitivity must be positive"));
+        }
+
+        let epsilon = match &self.mechanism {
+            Mechanism::DiscreteLaplace { epsilon } => epsilon,
+            Mechanism::DiscreteGaussian { epsilon, delta } => {
+                if delta.denominator == 0 || delta.numerator == 0 {
+                    return Err(Error::InvalidParameter("delta must be positive"));
+                }
+                if delta.numerator >= delta.denominator {
+                    return Err(Error::InvalidParameter("delta must be less than 1"));
+                }
+                epsilon
+            }
+        };
+
+        if epsilon.denominator == 0 || epsilon.numerator == 0 {
+            return Err(Error::InvalidParameter("epsilon must be positive"));
+        }
+        // The discrete Gaussian's calibration doesn't hold for larger epsilon
+        if matches!(self.mechanism, Mechanism::DiscreteGaussian { .. })
+            && epsilon.numerator >= epsilon.denominator
+        {
+            return Err(Error::InvalidParameter(
+                "epsilon must be less than 1 for the discrete Gaussian",
+            ));
+        }
+
+        self.check_calibration()?;
+        self.half_budget(self.sensitivity)?.check_calibration()?;
+        self.half_budget(1)?.check_calibration()
+    }
+
+    /// Check that noise can be calibrated to the parameters
+    fn check_calibration(&self) -> Result<(), Error> {
+        match &self.mechanism {
+            Mechanism::DiscreteLaplace { epsilon } => self.laplace_scale(epsilon).map(|_| ()),
+            Mechanism::DiscreteGaussian { epsilon, delta } => {
+                self.gaussian_variance(epsilon, delta).map(|_| ())
+            }
+        }
+    }
+
+    /// The mechanism for one of the two values noised with the task's budget,
+    /// with the sensitivity of that value
+    fn half_budget(&self, sensitivity: u64) -> Result<Self, Error> {
+        Ok(Self {
+            mechanism: self.mechanism.halved()?,
+            sensitivity,
+        })
+    }
+
+    /// Lap_Z(t) with scale t = sensitivity / epsilon
+    fn laplace_scale(&self, epsilon: &Rational) -> Result<Rational, Error> {
+        Ok(Rational {
+            numerator: self
+                .sensitivity
+                .checked_mul(epsilon.denominator)
+                .ok_or(Error::InvalidParameter("sensitivity / epsilon too large"))?,
+            denominator: epsilon.numerator,
+        })
+    }
+
+    /// The classic calibration sigma = sensitivity * sqrt(2 ln(1.25 / delta)) /
+    /// epsilon, which is only valid for epsilon < 1. Computing the variance in
+    /// floating point is fine since it depends only on public parameters, but
+    /// we round it to a rational before sampling.
+    fn gaussian_variance(&self, epsilon: &Rational, delta: &Rational) -> Result<Rational, Error> {
+        let sigma = self.sensitivity as f64 * (2.0 * (1.25 / delta.as_f64()).ln()).sqrt()
+            / epsilon.as_f64();
+        let numerator = (sigma * sigma * VARIANCE_DENOMINATOR as f64).ceil();
+        // Bounding the variance keeps the sampler's intermediate products well
+        // within u128
+        if numerator.is_nan() || numerator > MAX_VARIANCE_NUMERATOR as f64 {
+            return Err(Error::InvalidParameter(
+                "sensitivity / epsilon too large for delta",
+            ));
+        }
+
+        Ok(Rational {
+            numerator: numerator as u64,
+            denominator: VARIANCE_DENOMINATOR,
+        })
+    }
+
+    /// Draw a sample from the configured noise distribution
+    pub fn sample_noise<R: Rng>(&self, rng: &mut R) -> Result<i64, Error> {
+        self.validate()?;
+        self.sample_calibrated_noise(rng)
+    }
+
+    /// Draw a sample from the noise distribution, once the parameters have
+    /// been validated
+    fn sample_calibrated_noise<R: Rng>(&self, rng: &mut R) -> Result<i64, Error> {
+        match &self.mechanism {
+            Mechanism::DiscreteLaplace { epsilon } => {
+                Ok(sample_discrete_laplace(rng, self.laplace_scale(epsilon)?))
+            }
+            Mechanism::DiscreteGaussian { epsilon, delta } => {
+                sample_discrete_gaussian(rng, self.gaussian_variance(epsilon, delta)?)
+            }
+        }
+    }
+
+    /// Add noise to a count of reports, to which a single report contributes
+    /// at most one, using half of the configured budget. Negative results are
+    /// clamped to zero.
+    pub fn noise_count(&self, count: u64) -> Result<u64, Error> {
+        self.validate()?;
+        let noise = self
+            .half_budget(1)?
+            .sample_calibrated_noise(&mut thread_rng())?;
+
+        Ok(if noise < 0 {
+            count.saturating_sub(noise.unsigned_abs())
+        } else {
+            count.saturating_add(noise as u64)
+        })
+    }
+
+    /// Add independently sampled noise to each element of the aggregate share,
+    /// using half of the configured budget
+    pub fn add_noise<S: NoisyAggregateShare>(&self, aggregate_share: &mut S) -> Result<(), Error> {
+        self.validate()?;
+        let share_privacy = self.half_budget(self.sensitivity)?;
+        let mut rng = thread_rng();
+        aggregate_share.add_noise(|| share_privacy.sample_calibrated_noise(&mut rng))
+    }
+}
+
+/// Precision with which the discrete Gaussian's variance is represented
+const VARIANCE_DENOMINATOR: u64 = 1000;
+
+/// Largest numerator of the discrete Gaussian's variance, i.e. sigma^2 of about
+/// 2.9 * 10^14
+const MAX_VARIANCE_NUMERATOR: u64 = 1 << 58;
+
+/// Aggregate shares to which noise can be added
+pub trait NoisyAggregateShare {
+    /// Add a value returned by `noise` to each element of the aggregate share
+    fn add_noise<N>(&mut self, noise: N) -> Result<(), Error>
+    where
+        N: FnMut() -> Result<i64, Error>;
+
+    /// Replace each element that noise has taken below zero, and so wrapped
+    /// around to the upper half of the field, with zero
+    fn clamp_negative(&mut self);
+}
+
+impl<F: FieldElement> NoisyAggregateShare for AggregateShare<F> {
+    fn add_noise<N>(&mut self, mut noise: N) -> Result<(), Error>
+    where
+        N: FnMut() -> Result<i64, Error>,
+    {
+        let mut elements = self.as_ref().to_vec();
+        for element in elements.iter_mut() {
+            let sample = noise()?;
+            let magnitude = F::from(
+                F::Integer::try_from(sample.unsigned_abs() as usize)
+                    .map_err(|_| Error::NoiseTooLarge)?,
+            );
+            if sample < 0 {
+                *element -= magnitude;
+            } else {
+                *element += magnitude;
+            }
+        }
+        *self = AggregateShare::from(elements);
+
+        Ok(())
+    }
+
+    fn clamp_negative(&mut self) {
+        let mut elements = self.as_ref().to_vec();
+        for element in elements.iter_mut() {
+            // An element's negation is smaller than it exactly when it lies in
+            // the upper half of the field
+            if F::Integer::from(-*element) < F::Integer::from(*element) {
+                *element = F::zero();
+            }
+        }
+        *self = AggregateShare::from(elements);
+    }
+}
+
+/// Sample from Bernoulli(numerator / denominator)
+fn sample_bernoulli<R: Rng>(rng: &mut R, numerator: u128, denominator: u128) -> bool {
+    rng.gen_range(0..denominator) < numerator
+}
+
+/// Sample from Bernoulli(exp(-gamma)) for rational gamma >= 0 (CKS20
+/// algorithm 1)
+fn sample_bernoulli_exp<R: Rng>(rng: &mut R, numerator: u128, denominator: u128) -> bool {
+    if numerator <= denominator {
+        let mut k = 1;
+        loop {
+            if !sample_bernoulli(rng, numerator, denominator * k) {
+                break;
+            }
+            k += 1;
+        }
+        k % 2 == 1
+    } else {
+        for _ in 0..numerator / denominator {
+            if !sample_bernoulli_exp(rng, 1, 1) {
+                return false;
+            }
+        }
+        sample_bernoulli_exp(rng, numerator % denominator, denominator)
+    }
+}
+
+/// Sample from the discrete Laplace distribution with the given scale (CKS20
+/// algorithm 2)
+fn sample_discrete_laplace<R: Rng>(rng: &mut R, scale: Rational) -> i64 {
+    let s = scale.numerator as u128;
+    let t = scale.denominator as u128;
+    loop {
+        let u = rng.gen_range(0..s);
+        if !sample_bernoulli_exp(rng, u, s) {
+            continue;
+        }
+        let mut v = 0;
+        while sample_bernoulli_exp(rng, 1, 1) {
+            v += 1;
+        }
+        let y = ((u + s * v) / t) as i64;
+        let negative = sample_bernoulli(rng, 1, 2);
+        if negative && y == 0 {
+            continue;
+        }
+        return if negative { -y } else { y };
+    }
+}
+
+/// Sample from the discrete Gaussian distribution centered on zero with the
+/// given variance (CKS20 algorithm 3). The variance's numerator must be at most
+/// `MAX_VARIANCE_NUMERATOR`.
+fn sample_discrete_gaussian<R: Rng>(rng: &mut R, variance: Rational) -> Result<i64, Error> {
+    let (a, b) = (variance.numerator as u128, variance.denominator as u128);
+    // t = floor(sigma) + 1
+    let t = (variance.numerator as f64 / variance.denominator as f64)
+        .sqrt()
+        .floor() as u128
+        + 1;
+    loop {
+        let y = sample_discrete_laplace(
+            rng,
+            Rational {
+                numerator: t as u64,
+                denominator: 1,
+            },
+        );
+        // Accept with probability exp(-(|y| - sigma^2 / t)^2 / (2 sigma^2)),
+        // which with sigma^2 = a / b is exp(-(|y| b t - a)^2 / (2 a b t^2))
+        let difference = (y.unsigned_abs() as u128)
+            .checked_mul(b * t)
+            .map(|y_b_t| y_b_t.abs_diff(a))
+            .and_then(|difference| difference.checked_mul(difference))
+            .ok_or(Error::SampleOutOfRange)?;
+        if sample_bernoulli_exp(rng, difference, 2 * a * b * t * t) {
+            return Ok(y);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prio::field::Field128;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn mean_and_variance(samples: &[i64]) -> (f64, f64) {
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<i64>() as f64 / n;
+        let variance = samples
+            .iter()
+            .map(|s| (*s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        (mean, variance)
+    }
+
+    #[test]
+    fn discrete_laplace_moments() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let dp = DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteLaplace {
+                epsilon: Rational {
+                    numerator: 1,
+                    denominator: 2,
+                },
+            },
+            sensitivity: 1,
+        };
+
+        let samples: Vec<i64> = (0..20000)
+            .map(|_| dp.sample_noise(&mut rng).unwrap())
+            .collect();
+        let (mean, variance) = mean_and_variance(&samples);
+
+        // Scale t = 2, so variance is 2 e^(-1/2) / (1 - e^(-1/2))^2 ~= 7.83
+        assert!(mean.abs() < 0.2, "mean {}", mean);
+        assert!((variance - 7.83).abs() < 0.6, "variance {}", variance);
+    }
+
+    #[test]
+    fn discrete_gaussian_moments() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let dp = DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteGaussian {
+                epsilon: Rational {
+                    numerator: 1,
+                    denominator: 2,
+                },
+                delta: Rational {
+                    numerator: 1,
+                    denominator: 100000,
+                },
+            },
+            sensitivity: 1,
+        };
+
+        let samples: Vec<i64> = (0..20000)
+            .map(|_| dp.sample_noise(&mut rng).unwrap())
+            .collect();
+        let (mean, variance) = mean_and_variance(&samples);
+
+        // sigma^2 = 2 ln(1.25 / 10^-5) / (1/2)^2 ~= 93.9
+        assert!(mean.abs() < 0.4, "mean {}", mean);
+        assert!((variance - 93.9).abs() < 6.0, "variance {}", variance);
+    }
+
+    #[test]
+    fn noise_wraps_around_field() {
+        let mut aggregate_share =
+            AggregateShare::from(vec![Field128::from(10), Field128::from(10)]);
+        let mut samples = vec![-3, 4].into_iter();
+        aggregate_share
+            .add_noise(|| Ok(samples.next().unwrap()))
+            .unwrap();
+
+        assert_eq!(
+            aggregate_share.as_ref(),
+            &[Field128::from(7), Field128::from(14)]
+        );
+    }
+
+    #[test]
+    fn negative_noise_clamped() {
+        let mut aggregate_share = AggregateShare::from(vec![
+            Field128::from(0),
+            Field128::from(5),
+            Field128::from(0),
+        ]);
+        let mut samples = vec![-3, -2, 4].into_iter();
+        aggregate_share
+            .add_noise(|| Ok(samples.next().unwrap()))
+            .unwrap();
+        aggregate_share.clamp_negative();
+
+        assert_eq!(
+            aggregate_share.as_ref(),
+            &[Field128::from(0), Field128::from(3), Field128::from(4)]
+        );
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let dp = DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteGaussian {
+                epsilon: Rational {
+                    numerator: 1,
+                    denominator: 2,
+                },
+                delta: Rational {
+                    numerator: 2,
+                    denominator: 1,
+                },
+            },
+            sensitivity: 1,
+        };
+        assert!(dp.validate().is_err());
+    }
+
+    #[test]
+    fn gaussian_epsilon_below_one() {
+        let gaussian = |numerator| DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteGaussian {
+                epsilon: Rational {
+                    numerator,
+                    denominator: 4,
+                },
+                delta: Rational {
+                    numerator: 1,
+                    denominator: 100000,
+                },
+            },
+            sensitivity: 1,
+        };
+        gaussian(3).validate().unwrap();
+        assert!(matches!(
+            gaussian(4).validate(),
+            Err(Error::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            gaussian(5).validate(),
+            Err(Error::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn gaussian_variance_bounded() {
+        let mut dp = DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteGaussian {
+                epsilon: Rational {
+                    numerator: 1,
+                    denominator: 2,
+                },
+                delta: Rational {
+                    numerator: 1,
+                    denominator: 100000,
+                },
+            },
+            sensitivity: 1 << 19,
+        };
+        dp.validate().unwrap();
+        dp.sample_noise(&mut StdRng::seed_from_u64(0)).unwrap();
+
+        dp.sensitivity = u64::MAX;
+        assert!(matches!(dp.validate(), Err(Error::InvalidParameter(_))));
+        assert!(matches!(
+            dp.sample_noise(&mut StdRng::seed_from_u64(0)),
+            Err(Error::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn noised_count() {
+        let dp = DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteLaplace {
+                epsilon: Rational {
+                    numerator: 1,
+                    denominator: 1,
+                },
+            },
+            sensitivity: 1 << 40,
+        };
+
+        // The count's noise is calibrated to a sensitivity of 1, not the
+        // aggregate's, and never takes the count below zero
+        let counts: Vec<u64> = (0..1000).map(|_| dp.noise_count(100).unwrap()).collect();
+        assert!(counts.iter().all(|count| (50..150).contains(count)));
+        assert!(counts.iter().any(|count| *count != 100));
+        assert!((0..1000).all(|_| dp.noise_count(0).is_ok()));
+    }
+
+    #[test]
+    fn budget_split() {
+        let dp = DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteLaplace {
+                epsilon: Rational {
+                    numerator: 1,
+                    denominator: 1,
+                },
+            },
+            sensitivity: 1,
+        };
+
+        // Each noised value gets epsilon 1/2, i.e. scale t = 2, whose variance
+        // is about 7.83
+        let samples: Vec<i64> = (0..20000)
+            .map(|_| dp.noise_count(1000).unwrap() as i64 - 1000)
+            .collect();
+        let (_, variance) = mean_and_variance(&samples);
+        assert!((variance - 7.83).abs() < 0.6, "variance {}", variance);
+
+        let mut aggregate_share = AggregateShare::from(vec![Field128::from(1000); 20000]);
+        dp.add_noise(&mut aggregate_share).unwrap();
+        let samples: Vec<i64> = aggregate_share
+            .as_ref()
+            .iter()
+            .map(|element| u128::from(*element) as i64 - 1000)
+            .collect();
+        let (_, variance) = mean_and_variance(&samples);
+        assert!((variance - 7.83).abs() < 0.6, "variance {}", variance);
+
+        // Halving an epsilon with an odd numerator doubles its denominator,
+        // which mustn't overflow
+        let dp = DifferentialPrivacy {
+            mechanism: Mechanism::DiscreteLaplace {
+                epsilon: Rational {
+                    numerator: 1,
+                    denominator: u64::MAX,
+                },
+            },
+            sensitivity: 1,
+        };
+        assert!(matches!(dp.validate(), Err(Error::InvalidParameter(_))));
+    }
+}
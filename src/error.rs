@@ -1,8 +1,15 @@
 use crate::parameters::Parameters;
-use http::StatusCode;
+use http::{HeaderValue, StatusCode};
 use http_api_problem::HttpApiProblem;
-use std::{convert::Infallible, error::Error};
-use warp::reject::Rejection;
+use std::{convert::Infallible, error::Error, time::Duration};
+use warp::{
+    body::BodyDeserializeError,
+    reject::{
+        InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader,
+        PayloadTooLarge, Rejection, UnsupportedMediaType,
+    },
+    Reply,
+};
 
 /// Represents the possible URNs in PPM HTTP problem documents
 pub(crate) enum ProblemDocumentType {
@@ -15,11 +22,15 @@ pub(crate) enum ProblemDocumentType {
     HelperError,
     UnknownError,
     StaleReport,
+    QueryMismatch,
+    UnauthorizedRequest,
+    RateLimited,
 }
 
-impl From<ProblemDocumentType> for String {
-    fn from(type_urn: ProblemDocumentType) -> Self {
-        let problem_type = match type_urn {
+impl ProblemDocumentType {
+    /// The problem type's name, as it appears at the end of its URN
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
             ProblemDocumentType::UnrecognizedMessage => "unrecognizedMessage",
             ProblemDocumentType::UnrecognizedTask => "unrecognizedTask",
             ProblemDocumentType::OutdatedConfig => "outdatedConfig",
@@ -29,12 +40,28 @@ impl From<ProblemDocumentType> for String {
             ProblemDocumentType::HelperError => "helperError",
             ProblemDocumentType::UnknownError => "unknownError",
             ProblemDocumentType::StaleReport => "staleReport",
-        };
+            ProblemDocumentType::QueryMismatch => "queryMismatch",
+            ProblemDocumentType::UnauthorizedRequest => "unauthorizedRequest",
+            ProblemDocumentType::RateLimited => "rateLimited",
+        }
+    }
+}
 
-        format!("urn:ietf:params:ppm:error:{}", problem_type)
+impl From<ProblemDocumentType> for String {
+    fn from(type_urn: ProblemDocumentType) -> Self {
+        format!("urn:ietf:params:ppm:error:{}", type_urn.name())
     }
 }
 
+/// Key in problem documents under which [`handle_rejection`] finds the value of
+/// the `Retry-After` header
+const RETRY_AFTER_KEY: &str = "retryafter";
+
+/// Round up to whole seconds, so that clients don't retry too early
+pub(crate) fn retry_after_seconds(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
 /// Allows conversion into an `HttpApiProblem`. Intended for implementation by
 /// the crate's various error types.
 pub(crate) trait IntoHttpApiProblem: Error {
@@ -54,16 +81,23 @@ pub(crate) trait IntoHttpApiProblem: Error {
             None => "unknown".to_string(),
         };
 
-        match self.problem_document_type() {
+        let problem_document = match self.problem_document_type() {
             Some(problem_document_type) => {
-                HttpApiProblem::new(StatusCode::BAD_REQUEST).type_url(problem_document_type)
+                HttpApiProblem::new(self.status_code()).type_url(problem_document_type)
             }
             None => HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
                 .type_url(ProblemDocumentType::UnknownError),
         }
         .detail(self.to_string())
         .value("taskid", &task_id)
-        .instance(endpoint)
+        .instance(endpoint);
+
+        match self.retry_after() {
+            Some(retry_after) => {
+                problem_document.value(RETRY_AFTER_KEY, &retry_after_seconds(retry_after))
+            }
+            None => problem_document,
+        }
     }
 
     /// Get problem document type for the error, or None for errors not captured
@@ -71,6 +105,17 @@ pub(crate) trait IntoHttpApiProblem: Error {
     /// document with HTTP status code 500 is constructed.
     fn problem_document_type(&self) -> Option<ProblemDocumentType>;
 
+    /// HTTP status code for problem documents with a problem document type
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    /// How long the client should wait before retrying the request, if it is
+    /// worth retrying. Sent in the `Retry-After` header.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+
     /// Implementations may provide an HttpApiProblem representing the cause of
     /// this problem, which will be returned from [`problem_document`] instead
     /// of constructing a new problem document, though with the `instance`
@@ -96,20 +141,59 @@ impl IntoHttpApiProblem for prio::codec::CodecError {
 /// warp::Reply with appropriate status code and JSON body for an HTTP problem
 /// document.
 pub(crate) async fn handle_rejection(rejection: Rejection) -> Result<impl warp::Reply, Infallible> {
-    // All our warp rejections should wrap a problem document, so crash if we
-    // can't find one.
-    let problem_document = rejection.find::<HttpApiProblem>().unwrap();
-
-    Ok(warp::reply::with_header(
-        warp::reply::with_status(
-            warp::reply::json(problem_document),
-            problem_document
-                .status
-                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
-        ),
+    // Our own rejections wrap a problem document, but warp's rejections, e.g.
+    // for requests to paths we don't serve, don't
+    let problem_document = match rejection.find::<HttpApiProblem>() {
+        Some(problem_document) => problem_document.clone(),
+        None => warp_rejection_problem_document(&rejection),
+    };
+
+    let mut response = warp::reply::with_status(
+        warp::reply::json(&problem_document),
+        problem_document
+            .status
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+    )
+    .into_response();
+    response.headers_mut().insert(
         http::header::CONTENT_TYPE,
-        "application/problem+json",
-    ))
+        HeaderValue::from_static("application/problem+json"),
+    );
+    if let Some(retry_after) = problem_document.get_value::<&str, u64>(RETRY_AFTER_KEY) {
+        response
+            .headers_mut()
+            .insert(http::header::RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+
+    Ok(response)
+}
+
+/// Constructs a problem document for a rejection generated by warp's own
+/// filters rather than one of ours
+fn warp_rejection_problem_document(rejection: &Rejection) -> HttpApiProblem {
+    let problem_document = if rejection.is_not_found() {
+        HttpApiProblem::new(StatusCode::NOT_FOUND)
+    } else if rejection.find::<MethodNotAllowed>().is_some() {
+        HttpApiProblem::new(StatusCode::METHOD_NOT_ALLOWED)
+    } else if rejection.find::<LengthRequired>().is_some() {
+        HttpApiProblem::new(StatusCode::LENGTH_REQUIRED)
+    } else if rejection.find::<PayloadTooLarge>().is_some() {
+        HttpApiProblem::new(StatusCode::PAYLOAD_TOO_LARGE)
+    } else if rejection.find::<UnsupportedMediaType>().is_some() {
+        HttpApiProblem::new(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+    } else if rejection.find::<InvalidQuery>().is_some()
+        || rejection.find::<InvalidHeader>().is_some()
+        || rejection.find::<MissingHeader>().is_some()
+        || rejection.find::<BodyDeserializeError>().is_some()
+    {
+        HttpApiProblem::new(StatusCode::BAD_REQUEST)
+            .type_url(ProblemDocumentType::UnrecognizedMessage)
+    } else {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .type_url(ProblemDocumentType::UnknownError)
+    };
+
+    problem_document.detail(format!("{:?}", rejection))
 }
 
 /// Returns the problem document encoded into the response's body, if any. If
@@ -137,3 +221,40 @@ pub(crate) async fn response_to_api_problem(
 
     Ok(problem_document)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn warp_rejections() {
+        let response = handle_rejection(warp::reject::not_found())
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+
+        // Requests with the wrong method are rejected by warp's method filters
+        let route = warp::post().and(warp::path!("upload")).map(|| "uploaded");
+        let rejection = warp::test::request()
+            .method("GET")
+            .path("/upload")
+            .filter(&route)
+            .await
+            .unwrap_err();
+        let response = handle_rejection(rejection).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let problem_document = HttpApiProblem::new(StatusCode::CONFLICT);
+        let response = handle_rejection(warp::reject::custom(problem_document))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+}
@@ -3,29 +3,37 @@
 use crate::{
     aggregate::{
         Aggregate, AggregateInitReq, AggregateMessage, AggregateReq, AggregateResp, Aggregator,
-        Transition, TransitionError, TransitionMessage,
+        CollectAuditRecord, Transition, TransitionError, TransitionMessage,
     },
+    dp::NoisyAggregateShare,
     error::{handle_rejection, IntoHttpApiProblem, ProblemDocumentType},
     hpke,
+    metrics::Metrics,
     parameters::{Parameters, TaskId},
-    with_shared_value, Nonce, Role,
+    report::ExtensionRegistry,
+    server::{serve, Listener},
+    status::{
+        authenticate_admin, healthz_endpoint, CollectAuditResponse, FailuresResponse, Readiness,
+        StatusResponse, TaskStatus, READINESS_TIMEOUT,
+    },
+    task::{self, task_management_endpoints, Task, TaskFactory, TaskStore, Tasks},
+    with_shared_value, BatchSelector, Nonce, Role, Time,
 };
 use bytes::Bytes;
 use color_eyre::eyre::Result;
-use http::{Response, StatusCode};
+use http::{HeaderMap, Response, StatusCode};
 use prio::{
     codec::{Decode, Encode, ParameterizedDecode},
     vdaf::{self, PrepareTransition, VdafError},
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fmt::Debug,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::Arc,
 };
-use tokio::sync::Mutex;
-use tracing::{error, info, warn};
-use warp::{Filter, Rejection};
+use tokio::{sync::Mutex, time::timeout};
+use tracing::{info, warn};
+use warp::{reply, Filter, Rejection};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -70,8 +78,32 @@ impl IntoHttpApiProblem for Error {
 /// In-memory representation of a report stored by the leader
 #[derive(Clone, Debug)]
 pub enum StoredReport<A: vdaf::Aggregator> {
-    Waiting { step: A::PrepareStep },
-    Accumulated,
+    Waiting {
+        step: A::PrepareStep,
+        batch: BatchSelector,
+        /// The encoded prepare message last sent to the leader
+        payload: Vec<u8>,
+    },
+    Accumulated {
+        batch: BatchSelector,
+    },
+    /// Either aggregator rejected the report, which will never be aggregated
+    Failed {
+        batch: BatchSelector,
+        reason: TransitionError,
+    },
+}
+
+impl<A: vdaf::Aggregator> StoredReport<A> {
+    /// The interval of `min_batch_duration` or fixed size batch that the report
+    /// belongs to
+    fn batch(&self) -> BatchSelector {
+        match self {
+            Self::Waiting { batch, .. }
+            | Self::Accumulated { batch }
+            | Self::Failed { batch, .. } => *batch,
+        }
+    }
 }
 
 /// Implements endpoints for helper.
@@ -99,7 +131,7 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
             aggregation_parameter,
             // TODO: lame that both structs own a copy of parameters
             parameters,
-        );
+        )?;
 
         Ok(Self {
             parameters: parameters.clone(),
@@ -115,9 +147,9 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
     ) -> Result<AggregateMessage, Error> {
         // TODO: verify HMAC
 
-        let inner_response = match aggregate_message.aggregate {
-            Aggregate::Initialize(ref req) => Aggregate::Response(self.handle_aggregate_init(req)?),
-            Aggregate::Request(ref req) => Aggregate::Response(self.handle_aggregate_req(req)?),
+        let response = match aggregate_message.aggregate {
+            Aggregate::Initialize(ref req) => self.handle_aggregate_init(req)?,
+            Aggregate::Request(ref req) => self.handle_aggregate_req(req)?,
             ref message => {
                 return Err(Error::AggregateProtocol(format!(
                     "unexpected aggregate message {:?}",
@@ -127,7 +159,7 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
         };
 
         Ok(AggregateMessage {
-            aggregate: inner_response,
+            aggregate: Aggregate::Response(response),
             tag: [0u8; 32],
         })
     }
@@ -145,44 +177,87 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
         let mut transitions = vec![];
 
         for report_share in &request.report_shares {
-            if self.stored_reports.contains_key(&report_share.nonce) {
-                warn!(report_nonce = ?report_share.nonce, "duplicate report nonce");
+            let batch = self
+                .aggregator
+                .report_batch(report_share.nonce, &request.partial_batch_selector)?;
+
+            if let Some(stored_report) = self.stored_reports.get(&report_share.nonce) {
+                // The leader retries aggregation jobs it couldn't complete,
+                // e.g. because a response was lost, so a report already in
+                // the same batch is answered with its current state for the
+                // leader to catch up
+                let transition = match stored_report {
+                    StoredReport::Waiting {
+                        batch: stored_batch,
+                        payload,
+                        ..
+                    } if *stored_batch == batch => Transition::Continued {
+                        payload: payload.clone(),
+                    },
+                    StoredReport::Accumulated {
+                        batch: stored_batch,
+                    } if *stored_batch == batch => Transition::Finished,
+                    StoredReport::Failed {
+                        batch: stored_batch,
+                        reason,
+                    } if *stored_batch == batch => Transition::Failed { error: *reason },
+                    _ => {
+                        warn!(report_nonce = ?report_share.nonce, "duplicate report nonce");
+                        self.aggregator.report_failed(
+                            Some(stored_report.batch()),
+                            TransitionError::ReportReplayed,
+                        );
+                        Transition::Failed {
+                            error: TransitionError::ReportReplayed,
+                        }
+                    }
+                };
+                info!(report_nonce = ?report_share.nonce, "report already initialized");
                 transitions.push(TransitionMessage {
                     nonce: report_share.nonce,
-                    transition: Transition::Failed {
-                        error: TransitionError::ReportReplayed,
-                    },
+                    transition,
                 });
+                continue;
             }
 
             let (step, prepare_message) = match self.aggregator.prepare_message(
                 request.task_id,
                 report_share.nonce,
+                &batch,
                 &report_share.extensions,
                 &report_share.encrypted_input_share,
             ) {
                 Ok(v) => v,
                 Err(prep_error) => {
                     warn!(?prep_error, "prepare start of report failed");
+                    let reason = prep_error.into();
+                    self.aggregator.report_failed(Some(batch), reason);
                     transitions.push(TransitionMessage {
                         nonce: report_share.nonce,
-                        transition: Transition::Failed {
-                            error: prep_error.into(),
-                        },
+                        transition: Transition::Failed { error: reason },
                     });
+                    self.stored_reports
+                        .insert(report_share.nonce, StoredReport::Failed { batch, reason });
                     continue;
                 }
             };
 
+            let payload = prepare_message.get_encoded();
             transitions.push(TransitionMessage {
                 nonce: report_share.nonce,
                 transition: Transition::Continued {
-                    payload: prepare_message.get_encoded(),
+                    payload: payload.clone(),
                 },
             });
 
-            self.stored_reports
-                .insert(report_share.nonce, StoredReport::Waiting { step });
+            self.stored_reports.insert(
+                report_share.nonce,
+                StoredReport::Waiting {
+                    step,
+                    batch,
+                    payload,
+                },
+            );
         }
 
         self.aggregator.dump_accumulators();
@@ -211,6 +286,13 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
                 Some(v) => v,
                 None => {
                     warn!(leader_transition_nonce = ?leader_transition.nonce, "unrecognized nonce in leader transition");
+                    // There's nothing to give up on for a report the helper
+                    // never saw, and the leader expects no response to failures
+                    if matches!(leader_transition.transition, Transition::Failed { .. }) {
+                        continue;
+                    }
+                    self.aggregator
+                        .report_failed(None, TransitionError::UnrecognizedNonce);
                     transitions.push(TransitionMessage {
                         nonce: leader_transition.nonce,
                         transition: Transition::Failed {
@@ -224,13 +306,14 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
             match &leader_transition.transition {
                 Transition::Continued { payload } => {
                     info!(?leader_transition.nonce, "leader continued");
-                    let step = if let StoredReport::Waiting { step } = stored_report {
-                        step
-                    } else {
-                        return Err(Error::AggregateProtocol(
-                            "leader unexpectedly continued".to_string(),
-                        ));
-                    };
+                    let (step, batch) =
+                        if let StoredReport::Waiting { step, batch, .. } = stored_report {
+                            (step, *batch)
+                        } else {
+                            return Err(Error::AggregateProtocol(
+                                "leader unexpectedly continued".to_string(),
+                            ));
+                        };
 
                     let preprocessed_prepare_message =
                         A::PrepareMessage::get_decoded_with_param(step, payload)?;
@@ -245,19 +328,34 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
                             next_round_step,
                             next_round_prepare_message,
                         ) => {
+                            let payload = next_round_prepare_message.get_encoded();
                             *stored_report = StoredReport::Waiting {
                                 step: next_round_step,
+                                batch,
+                                payload: payload.clone(),
                             };
-                            Transition::Continued {
-                                payload: next_round_prepare_message.get_encoded(),
-                            }
+                            Transition::Continued { payload }
                         }
                         PrepareTransition::Finish(output_share) => {
-                            *stored_report = StoredReport::Accumulated;
                             info!(?leader_transition.nonce, "accumulating report");
-                            self.aggregator
-                                .accumulate_report(leader_transition.nonce, output_share)?;
-                            Transition::Finished
+                            match self.aggregator.accumulate_report(batch, output_share) {
+                                Ok(()) => {
+                                    *stored_report = StoredReport::Accumulated { batch };
+                                    self.aggregator.metrics().report_finished();
+                                    Transition::Finished
+                                }
+                                Err(error) => {
+                                    warn!(
+                                        ?error,
+                                        nonce = ?leader_transition.nonce,
+                                        "failed to accumulate report"
+                                    );
+                                    let reason = TransitionError::from(error);
+                                    self.aggregator.report_failed(Some(batch), reason);
+                                    *stored_report = StoredReport::Failed { batch, reason };
+                                    Transition::Failed { error: reason }
+                                }
+                            }
                         }
                         PrepareTransition::Fail(error) => {
                             warn!(
@@ -265,8 +363,10 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
                                 ?error,
                                 "proof did not check out for report"
                             );
-                            // Process other transitions
-                            continue;
+                            let reason = TransitionError::VdafPrepError;
+                            self.aggregator.report_failed(Some(batch), reason);
+                            *stored_report = StoredReport::Failed { batch, reason };
+                            Transition::Failed { error: reason }
                         }
                     };
 
@@ -283,11 +383,21 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
                     ));
                 }
                 Transition::Failed { error } => {
-                    // Leader should never send helper failed
-                    warn!(leader_error = ?error, ?leader_transition.nonce, "leader unexpected failed");
-                    return Err(Error::AggregateProtocol(
-                        "leader unexpectedly failed".to_string(),
-                    ));
+                    // The leader rejected a report the helper continued, which
+                    // must not be accumulated. No response is expected.
+                    warn!(leader_error = ?error, ?leader_transition.nonce, "leader failed report");
+                    let batch = if let StoredReport::Waiting { batch, .. } = stored_report {
+                        *batch
+                    } else {
+                        return Err(Error::AggregateProtocol(
+                            "leader unexpectedly failed".to_string(),
+                        ));
+                    };
+                    *stored_report = StoredReport::Failed {
+                        batch,
+                        reason: *error,
+                    };
+                    self.aggregator.report_failed(Some(batch), *error);
                 }
             }
         }
@@ -305,7 +415,10 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
     pub fn handle_aggregate_share(
         &mut self,
         aggregate_message: &AggregateMessage,
-    ) -> Result<AggregateMessage, Error> {
+    ) -> Result<AggregateMessage, Error>
+    where
+        A::AggregateShare: NoisyAggregateShare,
+    {
         // TODO: verify HMAC
 
         let request = match aggregate_message.aggregate {
@@ -317,53 +430,229 @@ impl<A: vdaf::Aggregator + Debug> Helper<A> {
             }
         };
 
+        // AggregateShareReq doesn't carry an aggregation parameter, so the
+        // leader has already checked the collector's against the task's
+        let aggregation_parameter = self.aggregator.aggregation_parameter().clone();
+        let aggregate_share = self.aggregator.extract_aggregate_share(
+            request.task_id,
+            request.batch_selector,
+            &aggregation_parameter,
+        )?;
+        self.aggregator.commit_aggregate_share(&aggregate_share);
+
         Ok(AggregateMessage {
-            aggregate: Aggregate::ShareResponse(
-                self.aggregator
-                    .extract_aggregate_share(request.task_id, request.batch_interval)?,
-            ),
+            aggregate: Aggregate::ShareResponse(aggregate_share.ciphertext),
             tag: [0u8; 32],
         })
     }
+
+    /// Every aggregate share request the helper has served or refused, oldest
+    /// first
+    pub fn collect_audit_log(&self) -> &VecDeque<CollectAuditRecord> {
+        self.aggregator.collect_audit_log()
+    }
+
+    /// Replace the report extensions the helper recognizes, which by default are
+    /// those defined in [`crate::report`]
+    pub fn set_extension_registry(&mut self, extension_registry: ExtensionRegistry) {
+        self.aggregator.set_extension_registry(extension_registry);
+    }
+
+    /// Describe the task's reports and accumulators
+    fn task_status(&self) -> TaskStatus {
+        let pending_reports = self
+            .stored_reports
+            .values()
+            .filter(|report| matches!(report, StoredReport::Waiting { .. }))
+            .count() as u64;
+        let aggregated_reports = self
+            .stored_reports
+            .values()
+            .filter(|report| matches!(report, StoredReport::Accumulated { .. }))
+            .count() as u64;
+
+        self.aggregator
+            .task_status(pending_reports, aggregated_reports)
+    }
+
+    /// Delete state for reports that have expired or whose batch has been
+    /// collected, along with accumulators that can no longer be collected,
+    /// per the task's retention policy. Once a report's batch is collected or
+    /// the report expires, replays of it are rejected without needing its
+    /// state.
+    pub fn collect_garbage(&mut self, now: Time) {
+        let reports_before = self.stored_reports.len();
+        let aggregator = &self.aggregator;
+        self.stored_reports.retain(|nonce, stored_report| {
+            if aggregator.is_expired(nonce.time, now) {
+                return false;
+            }
+            match stored_report {
+                StoredReport::Waiting { .. } => true,
+                StoredReport::Accumulated { batch } | StoredReport::Failed { batch, .. } => {
+                    !aggregator.is_collected(batch)
+                }
+            }
+        });
+
+        let deleted_batches = self.aggregator.collect_garbage(now);
+
+        info!(
+            deleted_reports = reports_before - self.stored_reports.len(),
+            deleted_accumulators = deleted_batches.len(),
+            "collected garbage"
+        );
+    }
 }
 
+impl<A> Task for Helper<A>
+where
+    A: vdaf::Aggregator + Debug + 'static + Send,
+    A::VerifyParam: Send,
+    A::AggregationParam: Send,
+    A::PrepareStep: Send,
+    A::AggregateShare: Send,
+{
+    fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    fn update_parameters(&mut self, parameters: &Parameters) {
+        self.parameters = parameters.clone();
+        self.aggregator.set_task_parameters(parameters);
+    }
+
+    fn collect_garbage(&mut self, now: Time) {
+        Helper::collect_garbage(self, now)
+    }
+}
+
+/// Find the task that an aggregate message concerns
+async fn aggregate_message_task<A>(
+    tasks: &Tasks<Helper<A>>,
+    body: &[u8],
+    endpoint: &'static str,
+) -> Result<(Arc<Mutex<Helper<A>>>, AggregateMessage), Rejection>
+where
+    A: vdaf::Aggregator + Debug + 'static + Send,
+    A::VerifyParam: Send,
+    A::AggregationParam: Send,
+    A::PrepareStep: Send,
+    A::AggregateShare: Send,
+{
+    let aggregate_message = AggregateMessage::get_decoded(body)
+        .map_err(|e| warp::reject::custom(e.problem_document(None, endpoint)))?;
+    let task_id = aggregate_message.task_id().ok_or_else(|| {
+        warp::reject::custom(
+            Error::AggregateProtocol(format!(
+                "unexpected aggregate message {:?}",
+                aggregate_message.aggregate
+            ))
+            .problem_document(None, endpoint),
+        )
+    })?;
+    let helper = tasks
+        .get(task_id)
+        .await
+        .map_err(|e| warp::reject::custom(e.problem_document(None, endpoint)))?;
+
+    Ok((helper, aggregate_message))
+}
+
+/// Run the helper on `0.0.0.0` at the port in its aggregator endpoint in
+/// `ppm_parameters`, for as long as the process runs
 pub async fn run_helper<A>(
     ppm_parameters: &Parameters,
     vdaf_aggregator: &A,
     verify_parameter: &A::VerifyParam,
     aggregation_parameter: &A::AggregationParam,
     hpke_config: &hpke::Config,
+    task_store: TaskStore,
 ) -> Result<()>
 where
     A: vdaf::Aggregator + 'static + Send + Sync,
-    A::VerifyParam: Send + Sync,
+    A::VerifyParam: Encode + ParameterizedDecode<A> + Send + Sync,
     A::AggregationParam: Send + Sync,
     A::PrepareStep: Send + Sync,
-    A::AggregateShare: Send + Sync,
+    A::AggregateShare: NoisyAggregateShare + Send + Sync,
 {
-    let port = ppm_parameters.aggregator_endpoints[Role::Helper.index()]
+    let port = ppm_parameters
+        .aggregator_endpoint(Role::Helper)?
         .port()
         .unwrap_or(80);
+    info!("helper serving on 0.0.0.0:{}", port);
 
-    let hpke_config_endpoint = hpke_config.warp_endpoint()?;
-
-    let helper_aggregator = Arc::new(Mutex::new(Helper::new(
+    serve_helper(
         ppm_parameters,
         vdaf_aggregator,
         verify_parameter,
         aggregation_parameter,
         hpke_config,
-    )?));
+        task_store,
+        Listener::bind(port)?,
+    )
+    .await
+}
+
+/// Run the helper, accepting connections on `listener` until it is shut down
+pub async fn serve_helper<A>(
+    ppm_parameters: &Parameters,
+    vdaf_aggregator: &A,
+    verify_parameter: &A::VerifyParam,
+    aggregation_parameter: &A::AggregationParam,
+    hpke_config: &hpke::Config,
+    task_store: TaskStore,
+    listener: Listener,
+) -> Result<()>
+where
+    A: vdaf::Aggregator + 'static + Send + Sync,
+    A::VerifyParam: Encode + ParameterizedDecode<A> + Send + Sync,
+    A::AggregationParam: Send + Sync,
+    A::PrepareStep: Send + Sync,
+    A::AggregateShare: NoisyAggregateShare + Send + Sync,
+{
+    let hpke_config_endpoint = hpke_config.warp_endpoint()?;
+
+    // Tasks created at runtime get their verification parameter from their
+    // parameters
+    let task_factory: TaskFactory<Helper<A>> = {
+        let vdaf_aggregator = vdaf_aggregator.clone();
+        let aggregation_parameter = aggregation_parameter.clone();
+        let hpke_config = hpke_config.clone();
+        Box::new(move |parameters: &Parameters| {
+            let verify_parameter =
+                parameters.decode_vdaf_verification_parameter(Role::Helper, &vdaf_aggregator)?;
+            Helper::new(
+                parameters,
+                &vdaf_aggregator,
+                &verify_parameter,
+                &aggregation_parameter,
+                &hpke_config,
+            )
+            .map_err(|e| task::Error::Setup(Box::new(e)))
+        })
+    };
+    let tasks = Arc::new(Tasks::new(
+        ppm_parameters,
+        Helper::new(
+            ppm_parameters,
+            vdaf_aggregator,
+            verify_parameter,
+            aggregation_parameter,
+            hpke_config,
+        )?,
+        task_factory,
+        task_store,
+    )?);
 
     let aggregate = warp::post()
         .and(warp::path("aggregate"))
         .and(warp::body::bytes())
-        .and(with_shared_value(helper_aggregator.clone()))
-        .and_then(|body: Bytes, helper: Arc<Mutex<Helper<_>>>| async move {
+        .and(with_shared_value(tasks.clone()))
+        .and_then(|body: Bytes, tasks: Arc<Tasks<Helper<_>>>| async move {
+            let (helper, aggregate_message) =
+                aggregate_message_task(&tasks, &body, "aggregate").await?;
             let mut helper = helper.lock().await;
-            let aggregate_message = AggregateMessage::get_decoded(&body).map_err(|e| {
-                warp::reject::custom(e.problem_document(Some(&helper.parameters), "aggregate"))
-            })?;
 
             let response = helper.handle_aggregate(&aggregate_message).map_err(|e| {
                 warp::reject::custom(e.problem_document(Some(&helper.parameters), "aggregate"))
@@ -383,14 +672,11 @@ where
     let aggregate_share = warp::post()
         .and(warp::path("aggregate_share"))
         .and(warp::body::bytes())
-        .and(with_shared_value(helper_aggregator.clone()))
-        .and_then(|body: Bytes, helper: Arc<Mutex<Helper<_>>>| async move {
+        .and(with_shared_value(tasks.clone()))
+        .and_then(|body: Bytes, tasks: Arc<Tasks<Helper<_>>>| async move {
+            let (helper, aggregate_message) =
+                aggregate_message_task(&tasks, &body, "aggregate_share").await?;
             let mut helper = helper.lock().await;
-            let aggregate_message = AggregateMessage::get_decoded(&body).map_err(|e| {
-                warp::reject::custom(
-                    e.problem_document(Some(&helper.parameters), "aggregate_share"),
-                )
-            })?;
 
             let response = helper
                 .handle_aggregate_share(&aggregate_message)
@@ -413,16 +699,121 @@ where
         })
         .with(warp::trace::named("aggregate_share"));
 
+    let metrics = warp::get()
+        .and(warp::path("metrics"))
+        .and(with_shared_value(tasks.clone()))
+        .and_then(|tasks: Arc<Tasks<Helper<_>>>| async move {
+            let mut metrics = vec![];
+            for helper in tasks.all().await {
+                metrics.push(helper.lock().await.aggregator.metrics().clone());
+            }
+
+            Metrics::encode(&metrics)
+                .map_err(|e| warp::reject::custom(e.problem_document(None, "metrics")))
+        })
+        .with(warp::trace::named("metrics"));
+
+    let readyz = warp::get()
+        .and(warp::path("readyz"))
+        .and(with_shared_value(tasks.clone()))
+        .and_then(|tasks: Arc<Tasks<Helper<_>>>| async move {
+            if let Err(e) = tasks.check_store() {
+                return Ok(Readiness::NotReady(format!(
+                    "task store unavailable: {}",
+                    e
+                ))) as Result<_, Rejection>;
+            }
+
+            for helper in tasks.all().await {
+                if timeout(READINESS_TIMEOUT, helper.lock()).await.is_err() {
+                    return Ok(Readiness::NotReady("helper state unavailable".to_string()))
+                        as Result<_, Rejection>;
+                }
+            }
+
+            Ok(Readiness::Ready)
+        })
+        .with(warp::trace::named("readyz"));
+
+    let status = warp::get()
+        .and(warp::path("status"))
+        .and(warp::header::headers_cloned())
+        .and(with_shared_value(tasks.clone()))
+        .and_then(
+            |headers: HeaderMap, tasks: Arc<Tasks<Helper<_>>>| async move {
+                authenticate_admin(tasks.admin_auth(), &headers)
+                    .map_err(|e| warp::reject::custom(e.problem_document(None, "status")))?;
+
+                let mut task_statuses = vec![];
+                for helper in tasks.all().await {
+                    task_statuses.push(helper.lock().await.task_status());
+                }
+
+                Ok(reply::json(&StatusResponse {
+                    tasks: task_statuses,
+                })) as Result<_, Rejection>
+            },
+        )
+        .with(warp::trace::named("status"));
+
+    let failures = warp::get()
+        .and(warp::path("failures"))
+        .and(warp::header::headers_cloned())
+        .and(with_shared_value(tasks.clone()))
+        .and_then(
+            |headers: HeaderMap, tasks: Arc<Tasks<Helper<_>>>| async move {
+                authenticate_admin(tasks.admin_auth(), &headers)
+                    .map_err(|e| warp::reject::custom(e.problem_document(None, "failures")))?;
+
+                let mut task_failures = vec![];
+                for helper in tasks.all().await {
+                    task_failures.push(helper.lock().await.aggregator.task_failures());
+                }
+
+                Ok(reply::json(&FailuresResponse {
+                    tasks: task_failures,
+                })) as Result<_, Rejection>
+            },
+        )
+        .with(warp::trace::named("failures"));
+
+    let collect_audit = warp::get()
+        .and(warp::path("collect_audit"))
+        .and(warp::header::headers_cloned())
+        .and(with_shared_value(tasks.clone()))
+        .and_then(
+            |headers: HeaderMap, tasks: Arc<Tasks<Helper<_>>>| async move {
+                authenticate_admin(tasks.admin_auth(), &headers)
+                    .map_err(|e| warp::reject::custom(e.problem_document(None, "collect_audit")))?;
+
+                let mut task_audits = vec![];
+                for helper in tasks.all().await {
+                    task_audits.push(helper.lock().await.aggregator.task_collect_audit());
+                }
+
+                Ok(reply::json(&CollectAuditResponse { tasks: task_audits }))
+                    as Result<_, Rejection>
+            },
+        )
+        .with(warp::trace::named("collect_audit"));
+
     let routes = hpke_config_endpoint
+        .or(healthz_endpoint())
+        .or(readyz)
+        .or(status)
+        .or(failures)
+        .or(collect_audit)
+        .or(metrics)
+        .or(task_management_endpoints(tasks.clone(), None))
         .or(aggregate)
         .or(aggregate_share)
         .recover(handle_rejection)
         .with(warp::trace::request());
 
-    info!("helper serving on 0.0.0.0:{}", port);
-    warp::serve(routes)
-        .run(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port))
-        .await;
+    tokio::select! {
+        result = serve(warp::service(routes), listener) => result?,
+        _ = tasks.run_garbage_collector() => unreachable!(),
+    }
 
-    unreachable!()
+    Ok(())
 }
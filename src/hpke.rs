@@ -193,6 +193,7 @@ pub struct Config {
     /// Private key with which messages should be decrypted
     #[serde(
         default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "crate::base64::serialize_bytes_option",
         deserialize_with = "crate::base64::deserialize_bytes_option"
     )]
@@ -292,7 +293,9 @@ impl Config {
         }
     }
 
-    fn application_info(
+    /// The application info string for HPKE contexts set up by `sender_role`
+    /// to seal messages for `recipient_role`
+    pub(crate) fn application_info(
         task_id: &TaskId,
         label: Label,
         sender_role: Role,
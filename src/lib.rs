@@ -1,23 +1,37 @@
 pub mod aggregate;
+#[cfg(test)]
+mod arbitrary;
+pub mod auth;
 pub mod client;
 pub mod collect;
+pub mod dp;
 mod error;
 pub mod helper;
 pub mod hpke;
 pub mod leader;
+pub mod metrics;
 pub mod parameters;
+pub mod rate_limit;
 pub mod report;
+pub mod server;
+pub mod status;
+pub mod task;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "test-util")]
+pub mod test_vectors;
 pub mod trace;
 
-use chrono::{DurationRound, TimeZone, Utc};
+use chrono::Utc;
 use directories::ProjectDirs;
 use prio::codec::{CodecError, Decode, Encode};
+use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::{
     convert::Infallible,
     fmt::{self, Display, Formatter},
-    io::Cursor,
+    io::{Cursor, Read},
     path::PathBuf,
 };
 use warp::Filter;
@@ -27,19 +41,33 @@ use warp::Filter;
 pub struct Time(pub u64);
 
 impl Time {
+    /// The current time, according to the system clock.
+    pub fn now() -> Self {
+        Self(Utc::now().timestamp() as u64)
+    }
+
     /// Determine the start of the aggregation window that this report falls in,
-    /// assuming the provided minimum batch duration
+    /// assuming the provided minimum batch duration. Times are returned as is
+    /// if the duration is zero.
     fn interval_start(self, min_batch_duration: Duration) -> Self {
-        Self(
-            Utc.timestamp(self.0 as i64, 0)
-                .duration_trunc(chrono::Duration::seconds(min_batch_duration.0 as i64))
-                .unwrap()
-                .timestamp() as u64,
-        )
+        Self(self.0 - self.0.checked_rem(min_batch_duration.0).unwrap_or(0))
+    }
+
+    /// Round this time down to a multiple of the provided precision, so that
+    /// it reveals nothing finer-grained than `precision`.
+    pub fn truncate(self, precision: Duration) -> Self {
+        self.interval_start(precision)
     }
 
+    /// Returns true if this time is a multiple of the provided precision.
+    pub fn is_aligned(self, precision: Duration) -> bool {
+        self.truncate(precision) == self
+    }
+
+    /// Adds the duration to this time, saturating rather than overflowing
+    /// since times may come from untrusted messages
     fn add(self, duration: Duration) -> Self {
-        Self(self.0 + duration.0)
+        Self(self.0.saturating_add(duration.0))
     }
 
     /// Returns the batch interval that this instant falls into, based on the
@@ -75,8 +103,10 @@ impl Decode for Time {
 pub struct Duration(pub u64);
 
 impl Duration {
-    pub fn multiple(self, factor: u64) -> Self {
-        Self(self.0 * factor)
+    /// This duration multiplied by `factor`, or `None` if the result would
+    /// overflow
+    pub fn multiple(self, factor: u64) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
     }
 }
 
@@ -148,6 +178,12 @@ impl Interval {
         [self.start.0.to_be_bytes(), self.duration.0.to_be_bytes()].concat()
     }
 
+    /// The end of the interval, which is excluded from it. Saturates rather
+    /// than overflowing, since intervals may come from untrusted messages.
+    pub(crate) fn end(&self) -> Time {
+        self.start.add(self.duration)
+    }
+
     /// Compute how many times an interval of length `duration` would fit in
     /// this interval.
     pub(crate) fn intervals_in_interval(&self, duration: Duration) -> u64 {
@@ -157,7 +193,7 @@ impl Interval {
 
 impl Display for Interval {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "[{} - {})", self.start, self.start.add(self.duration))
+        write!(f, "[{} - {})", self.start, self.end())
     }
 }
 
@@ -177,6 +213,102 @@ impl Decode for Interval {
     }
 }
 
+/// Randomly generated identifier for a batch of reports, assigned by the
+/// leader in tasks with fixed-size batches.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct BatchId(pub [u8; 32]);
+
+impl BatchId {
+    pub fn random() -> Self {
+        Self(thread_rng().gen::<[u8; 32]>())
+    }
+}
+
+impl Display for BatchId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl Encode for BatchId {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.0)
+    }
+}
+
+impl Decode for BatchId {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        let mut decoded = [0u8; 32];
+        bytes.read_exact(&mut decoded)?;
+        Ok(Self(decoded))
+    }
+}
+
+/// Identifies a batch of reports: either every report whose timestamp falls
+/// in an interval of time, or the reports that the leader assigned to a batch
+/// ID.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum BatchSelector {
+    TimeInterval(Interval),
+    FixedSize(BatchId),
+}
+
+impl BatchSelector {
+    /// Construct the HPKE AEAD associated data for the batch.
+    pub(crate) fn associated_data(&self) -> Vec<u8> {
+        match self {
+            Self::TimeInterval(interval) => interval.associated_data(),
+            Self::FixedSize(batch_id) => batch_id.0.to_vec(),
+        }
+    }
+
+    /// Returns true if every report in `other` is also in this batch
+    pub(crate) fn contains(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::TimeInterval(interval), Self::TimeInterval(other)) => {
+                interval.start <= other.start && other.end() <= interval.end()
+            }
+            (Self::FixedSize(batch_id), Self::FixedSize(other)) => batch_id == other,
+            _ => false,
+        }
+    }
+}
+
+impl Display for BatchSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TimeInterval(interval) => write!(f, "{}", interval),
+            Self::FixedSize(batch_id) => write!(f, "batch {}", batch_id),
+        }
+    }
+}
+
+impl Encode for BatchSelector {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        // The discriminant is the query type, as in `collect::Query`
+        match self {
+            Self::TimeInterval(interval) => {
+                1u8.encode(bytes);
+                interval.encode(bytes);
+            }
+            Self::FixedSize(batch_id) => {
+                2u8.encode(bytes);
+                batch_id.encode(bytes);
+            }
+        }
+    }
+}
+
+impl Decode for BatchSelector {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        match u8::decode(bytes)? {
+            1 => Ok(Self::TimeInterval(Interval::decode(bytes)?)),
+            2 => Ok(Self::FixedSize(BatchId::decode(bytes)?)),
+            _ => Err(CodecError::UnexpectedValue),
+        }
+    }
+}
+
 /// The roles that protocol participants can adopt
 #[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, Eq, PartialEq)]
 #[repr(u8)]
@@ -189,14 +321,30 @@ pub enum Role {
 
 impl Role {
     /// Returns the index into protocol message vectors at which this role's
-    /// entry can be found. e.g., the leader's input share in a `Report` is
-    /// `Report.encrypted_input_shares[Role::Leader.index()]`.
-    pub fn index(self) -> usize {
-        // TODO: this doesn't make sense anymore
+    /// entry can be found, or None for roles that have no entry. e.g., the
+    /// leader's input share in a `Report` is at
+    /// `Report.encrypted_input_shares[Role::Leader.index().unwrap()]`.
+    pub fn index(self) -> Option<usize> {
         match self {
-            Role::Leader => 0,
-            Role::Helper => 1,
-            r => panic!("unexpected role {:?}", r),
+            Role::Leader => Some(0),
+            Role::Helper => Some(1),
+            Role::Collector | Role::Client => None,
+        }
+    }
+
+    /// Returns this role's entry in a protocol message vector, or None if the
+    /// role has no entry or the vector is too short to contain it.
+    pub fn entry<T>(self, entries: &[T]) -> Option<&T> {
+        entries.get(self.index()?)
+    }
+
+    /// The role's name, as used in metrics and file names
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Collector => "collector",
+            Role::Client => "client",
+            Role::Leader => "leader",
+            Role::Helper => "helper",
         }
     }
 }
@@ -218,17 +366,20 @@ pub fn with_shared_value<T: Clone + Sync + Send>(
 mod base64 {
     //! Custom serialization module used to serialize byte sequences to base64
     use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::{convert::TryFrom, fmt::Display};
 
     pub fn serialize_bytes<V: AsRef<[u8]>, S: Serializer>(v: &V, s: S) -> Result<S::Ok, S::Error> {
-        String::serialize(&base64::encode(&v), s)
+        String::serialize(&base64::encode(v), s)
     }
 
-    pub fn deserialize_bytes<'de, D: Deserializer<'de>, V: From<Vec<u8>>>(
-        d: D,
-    ) -> Result<V, D::Error> {
+    pub fn deserialize_bytes<'de, D, V>(d: D) -> Result<V, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: TryFrom<Vec<u8>>,
+        V::Error: Display,
+    {
         let bytes = base64::decode(String::deserialize(d)?.as_bytes()).map_err(Error::custom)?;
-        let v = V::from(bytes);
-        Ok(v)
+        V::try_from(bytes).map_err(Error::custom)
     }
 
     pub fn serialize_bytes_vec<V: AsRef<[u8]>, S: Serializer>(
@@ -258,7 +409,7 @@ mod base64 {
         s: S,
     ) -> Result<S::Ok, S::Error> {
         match v {
-            Some(v) => String::serialize(&base64::encode(&v), s),
+            Some(v) => String::serialize(&base64::encode(v), s),
             None => <Option<Vec<u8>>>::serialize(&None, s),
         }
     }
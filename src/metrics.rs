@@ -0,0 +1,209 @@
+//! Prometheus metrics exported by the leader and helper on `/metrics`.
+
+use crate::{
+    aggregate::TransitionError,
+    error::{IntoHttpApiProblem, ProblemDocumentType},
+    parameters::TaskId,
+    BatchSelector, Role,
+};
+use http::StatusCode;
+use prometheus::{
+    proto::MetricFamily, Encoder, Histogram, HistogramOpts, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("metrics error {0}")]
+    Prometheus(#[from] prometheus::Error),
+    #[error("metrics are not valid UTF-8")]
+    Encoding(#[from] std::string::FromUtf8Error),
+}
+
+/// Outcome label for uploads that were accepted
+pub(crate) const UPLOAD_ACCEPTED: &str = "accepted";
+/// Outcome label for reports that were prepared and accumulated
+pub(crate) const REPORT_FINISHED: &str = "finished";
+
+/// Metrics for one aggregator. Each aggregator has its own registry, so that a
+/// leader and helper can run in the same process.
+#[derive(Clone, Debug)]
+pub(crate) struct Metrics {
+    registry: Registry,
+    /// Uploads, by `accepted` or the problem document type they were rejected
+    /// with
+    uploads: IntCounterVec,
+    /// Reports, by `finished` or the transition error they failed with
+    reports_prepared: IntCounterVec,
+    /// Time taken by the leader to run an aggregation job with the helper
+    aggregation_job_duration: Histogram,
+    /// Collect or aggregate share requests, by whether they consumed privacy
+    /// budget
+    collect_requests: IntCounterVec,
+    /// Privacy budget consumed from each accumulator, in queries
+    privacy_budget_consumed: IntGaugeVec,
+    /// Failed requests from the leader to the helper, by endpoint
+    helper_request_errors: IntCounterVec,
+}
+
+impl IntoHttpApiProblem for Error {
+    fn problem_document_type(&self) -> Option<ProblemDocumentType> {
+        None
+    }
+
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+impl Metrics {
+    pub(crate) fn new(role: Role, task_id: TaskId) -> Result<Self, Error> {
+        let registry = Registry::new_custom(
+            Some("ppm".to_string()),
+            Some(HashMap::from([
+                ("role".to_string(), role.name().to_string()),
+                ("task".to_string(), task_id.to_string()),
+            ])),
+        )?;
+
+        let uploads = IntCounterVec::new(
+            Opts::new("uploads_total", "Reports uploaded to the leader"),
+            &["outcome"],
+        )?;
+        let reports_prepared = IntCounterVec::new(
+            Opts::new(
+                "reports_prepared_total",
+                "Reports prepared by the aggregator",
+            ),
+            &["outcome"],
+        )?;
+        let aggregation_job_duration = Histogram::with_opts(HistogramOpts::new(
+            "aggregation_job_duration_seconds",
+            "Time taken to run an aggregation job",
+        ))?;
+        let collect_requests = IntCounterVec::new(
+            Opts::new(
+                "collect_requests_total",
+                "Requests for the aggregator's aggregate share",
+            ),
+            &["outcome"],
+        )?;
+        let privacy_budget_consumed = IntGaugeVec::new(
+            Opts::new(
+                "privacy_budget_consumed",
+                "Queries that have consumed each accumulator's privacy budget",
+            ),
+            &["batch"],
+        )?;
+        let helper_request_errors = IntCounterVec::new(
+            Opts::new(
+                "helper_request_errors_total",
+                "Failed requests from the leader to the helper",
+            ),
+            &["endpoint"],
+        )?;
+
+        registry.register(Box::new(uploads.clone()))?;
+        registry.register(Box::new(reports_prepared.clone()))?;
+        registry.register(Box::new(aggregation_job_duration.clone()))?;
+        registry.register(Box::new(collect_requests.clone()))?;
+        registry.register(Box::new(privacy_budget_consumed.clone()))?;
+        registry.register(Box::new(helper_request_errors.clone()))?;
+
+        Ok(Self {
+            registry,
+            uploads,
+            reports_prepared,
+            aggregation_job_duration,
+            collect_requests,
+            privacy_budget_consumed,
+            helper_request_errors,
+        })
+    }
+
+    /// Encode the metrics of several aggregators, which differ only in their
+    /// task, in the Prometheus text format
+    pub(crate) fn encode<'a, I: IntoIterator<Item = &'a Metrics>>(
+        metrics: I,
+    ) -> Result<String, Error> {
+        // Each family of metrics may appear only once, so merge the families
+        // from each aggregator's registry
+        let mut families: BTreeMap<String, MetricFamily> = BTreeMap::new();
+        for metrics in metrics {
+            for mut family in metrics.registry.gather() {
+                match families.get_mut(family.get_name()) {
+                    Some(merged) => merged.mut_metric().extend(family.take_metric()),
+                    None => {
+                        families.insert(family.get_name().to_string(), family);
+                    }
+                }
+            }
+        }
+
+        // Const labels are kept in a hash map, so put every metric's labels in
+        // a stable order
+        let mut families: Vec<_> = families.into_values().collect();
+        for family in &mut families {
+            for metric in family.mut_metric() {
+                metric
+                    .mut_label()
+                    .sort_by(|a, b| a.get_name().cmp(b.get_name()));
+            }
+        }
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&families, &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    pub(crate) fn upload(&self, outcome: &str) {
+        self.uploads.with_label_values(&[outcome]).inc();
+    }
+
+    pub(crate) fn report_finished(&self) {
+        self.reports_prepared
+            .with_label_values(&[REPORT_FINISHED])
+            .inc();
+    }
+
+    pub(crate) fn report_failed(&self, error: TransitionError) {
+        self.reports_prepared
+            .with_label_values(&[&format!("{:?}", error)])
+            .inc();
+    }
+
+    pub(crate) fn aggregation_job(&self, duration: Duration) {
+        self.aggregation_job_duration
+            .observe(duration.as_secs_f64());
+    }
+
+    pub(crate) fn collect_request(&self, collected: bool) {
+        self.collect_requests
+            .with_label_values(&[if collected { "collected" } else { "rejected" }])
+            .inc();
+    }
+
+    pub(crate) fn privacy_budget_consumed(&self, batch: &BatchSelector, consumed: u64) {
+        self.privacy_budget_consumed
+            .with_label_values(&[&batch.to_string()])
+            .set(consumed as i64);
+    }
+
+    /// Stop reporting on an accumulator that has been deleted
+    pub(crate) fn remove_batch(&self, batch: &BatchSelector) {
+        // Fails only if the accumulator was never collected, in which case
+        // there's nothing to remove
+        let _ = self
+            .privacy_budget_consumed
+            .remove_label_values(&[&batch.to_string()]);
+    }
+
+    pub(crate) fn helper_request_error(&self, endpoint: &str) {
+        self.helper_request_errors
+            .with_label_values(&[endpoint])
+            .inc();
+    }
+}
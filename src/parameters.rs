@@ -3,20 +3,31 @@
 //! Provides structures and functionality for dealing with a `struct PPMParam`
 //! and related types.
 
-use crate::{config_path, hpke, Duration, Interval, Role};
+use crate::{
+    auth::{AdminAuth, UploadAuth},
+    config_path,
+    dp::DifferentialPrivacy,
+    hpke,
+    rate_limit::UploadLimits,
+    Duration, Interval, Role, Time,
+};
 use prio::{
     codec::{CodecError, Decode, Encode, ParameterizedDecode},
-    vdaf::Vdaf,
+    vdaf::{
+        prio3::{Prio3Aes128Count, Prio3Aes128Histogram, Prio3Aes128Sum},
+        Vdaf, VdafError,
+    },
 };
 use rand::{thread_rng, Rng};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::{
-    convert::{AsRef, TryInto},
-    fmt::Display,
+    convert::{AsRef, TryFrom, TryInto},
+    fmt::{self, Display, Formatter},
     fs::File,
     io::{Cursor, Read},
     path::PathBuf,
+    str::FromStr,
 };
 use url::Url;
 
@@ -36,8 +47,58 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Codec error")]
     Codec(#[from] prio::codec::CodecError),
+    #[error("no aggregator endpoint for {}", .0.name())]
+    MissingAggregatorEndpoint(Role),
+    #[error("no VDAF verification parameter for {}", .0.name())]
+    MissingVdafVerificationParameter(Role),
+    #[error("invalid parameters:\n{0}")]
+    Invalid(#[from] ValidationErrors),
+}
+
+/// A problem with a task's parameters that would stop aggregators, clients or
+/// collectors from running the task
+#[derive(Debug, thiserror::Error)]
+pub enum ValidationError {
+    #[error("aggregator_endpoints must list the leader's and then the helper's endpoint, but has {0} entries")]
+    AggregatorEndpointCount(usize),
+    #[error("aggregator endpoint {0} can't have paths appended to it; use an http or https URL")]
+    AggregatorEndpointNotBase(Url),
+    #[error("aggregator endpoint {0} must end in '/' or its last path segment is replaced by the protocol's paths")]
+    AggregatorEndpointNoTrailingSlash(Url),
+    #[error("{0} must be greater than zero")]
+    Zero(&'static str),
+    #[error("vdaf_verification_parameter must have an entry for the leader and then the helper, but has {0} entries")]
+    VdafVerificationParameterCount(usize),
+    #[error("{} VDAF verification parameter doesn't match VDAF {:?}: {}", .0.name(), .1, .2)]
+    VdafVerificationParameter(Role, VdafLabel, #[source] CodecError),
+    #[error("invalid VDAF {0:?}: {1}")]
+    Vdaf(VdafLabel, #[source] VdafError),
+    #[error("{0}")]
+    DifferentialPrivacy(#[from] crate::dp::Error),
+    #[error("retention.report_max_age must be set, or the nonces of deleted reports are kept forever to detect replays")]
+    RetentionWithoutReportMaxAge,
+    #[error("retention.accumulator_max_age of {0} must be at least report_max_age plus min_batch_duration, {1}, or accumulators are deleted while their intervals still accept reports")]
+    AccumulatorMaxAgeTooShort(Duration, Duration),
+}
+
+/// Every problem found with a task's parameters
+#[derive(Debug)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
 }
 
+impl std::error::Error for ValidationErrors {}
+
 /// The configuration parameters for a PPM task, corresponding to
 /// `struct Param` in §4.1 of RFCXXXX.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -55,6 +116,16 @@ pub struct Parameters {
     pub min_batch_size: u64,
     /// Minimum time elapsed between start and end of a batch
     pub min_batch_duration: Duration,
+    /// How reports are grouped into batches. Defaults to batches defined by
+    /// intervals of time.
+    #[serde(default, skip_serializing_if = "QueryType::is_time_interval")]
+    pub query_type: QueryType,
+    /// Precision to which report timestamps are truncated. If set, clients
+    /// round the time in each report's nonce down to a multiple of this
+    /// duration and aggregators reject reports whose times are not aligned to
+    /// it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_precision: Option<Duration>,
     /// HMAC-SHA256 key used to authenticate messages exchanged between
     /// aggregators
     #[serde(
@@ -65,6 +136,27 @@ pub struct Parameters {
     pub aggregator_auth_key: Vec<u8>,
     /// What VDAF are we running
     pub vdaf: VdafLabel,
+    /// Differential privacy mechanism each aggregator applies to its aggregate
+    /// shares and the leader to report counts, if any. The privacy budget is
+    /// split evenly between the two.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub differential_privacy: Option<DifferentialPrivacy>,
+    /// How the leader authenticates clients uploading reports. If unset, anyone
+    /// may upload reports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_auth: Option<UploadAuth>,
+    /// Rate limits and quotas on uploads to the leader. If unset, uploads are
+    /// unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_limits: Option<UploadLimits>,
+    /// How long aggregators keep report and batch state. If unset, they keep
+    /// everything for as long as they run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+    /// How aggregators authenticate operators using administrative endpoints.
+    /// If unset, those endpoints refuse every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_auth: Option<AdminAuth>,
     // Encoded verification parameter for the VDAF, negotiated out of band
     // before the start of the protocol
     #[serde(
@@ -75,8 +167,13 @@ pub struct Parameters {
 }
 
 impl Parameters {
+    /// Path to the task parameters in the config directory
+    pub fn config_file_path() -> PathBuf {
+        config_path().join("parameters.json")
+    }
+
     pub fn from_config_file() -> Result<Self, Error> {
-        let ppm_parameters_path = config_path().join("parameters.json");
+        let ppm_parameters_path = Self::config_file_path();
 
         Self::from_json_reader(
             File::open(&ppm_parameters_path).map_err(|e| Error::File(e, ppm_parameters_path))?,
@@ -84,21 +181,131 @@ impl Parameters {
     }
 
     /// Read in a JSON encoded Param from the provided `std::io::Read` and
-    /// construct an instance of `Parameters`.
+    /// construct an instance of `Parameters`, checking that it is valid.
     ///
     /// Ideally this would be an implementation of `TryFrom<R: Read>` on
     /// `Parameters` but you can't provide generic implementations of `TryFrom`:
     /// https://github.com/rust-lang/rust/issues/50133
     pub fn from_json_reader<R: Read>(reader: R) -> Result<Self, Error> {
-        Ok(serde_json::from_reader(reader)?)
+        let parameters: Self = serde_json::from_reader(reader)?;
+        parameters.validate()?;
+        Ok(parameters)
+    }
+
+    /// Check that the parameters describe a task that can be run, returning
+    /// every problem found if not.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+
+        if self.aggregator_endpoints.len() != 2 {
+            errors.push(ValidationError::AggregatorEndpointCount(
+                self.aggregator_endpoints.len(),
+            ));
+        }
+        for endpoint in &self.aggregator_endpoints {
+            if endpoint.cannot_be_a_base() {
+                errors.push(ValidationError::AggregatorEndpointNotBase(endpoint.clone()));
+            } else if !endpoint.path().ends_with('/') {
+                errors.push(ValidationError::AggregatorEndpointNoTrailingSlash(
+                    endpoint.clone(),
+                ));
+            }
+        }
+
+        if self.max_batch_lifetime == 0 {
+            errors.push(ValidationError::Zero("max_batch_lifetime"));
+        }
+        if self.min_batch_duration.0 == 0 {
+            errors.push(ValidationError::Zero("min_batch_duration"));
+        }
+        if let QueryType::FixedSize { batch_size: 0 } = self.query_type {
+            errors.push(ValidationError::Zero("query_type.FixedSize.batch_size"));
+        }
+        if self.time_precision == Some(Duration(0)) {
+            errors.push(ValidationError::Zero("time_precision"));
+        }
+        if let Some(retention) = &self.retention {
+            match (retention.report_max_age, retention.accumulator_max_age) {
+                (None, _) => errors.push(ValidationError::RetentionWithoutReportMaxAge),
+                (Some(report_max_age), Some(accumulator_max_age)) => {
+                    // An accumulator may be created as soon as its interval
+                    // begins, and reports are accepted until report_max_age
+                    // after it ends
+                    let minimum =
+                        Duration(report_max_age.0.saturating_add(self.min_batch_duration.0));
+                    if accumulator_max_age < minimum {
+                        errors.push(ValidationError::AccumulatorMaxAgeTooShort(
+                            accumulator_max_age,
+                            minimum,
+                        ));
+                    }
+                }
+                (Some(_), None) => {}
+            }
+        }
+
+        if let Some(differential_privacy) = &self.differential_privacy {
+            if let Err(e) = differential_privacy.validate() {
+                errors.push(e.into());
+            }
+        }
+
+        if self.vdaf_verification_parameter.len() != 2 {
+            errors.push(ValidationError::VdafVerificationParameterCount(
+                self.vdaf_verification_parameter.len(),
+            ));
+        }
+        let vdaf_errors = match &self.vdaf {
+            VdafLabel::Prio3Count64 => {
+                Prio3Aes128Count::new(2).map(|vdaf| self.verification_parameter_errors(&vdaf))
+            }
+            VdafLabel::Prio3Sum64 { bits } => {
+                Prio3Aes128Sum::new(2, *bits).map(|vdaf| self.verification_parameter_errors(&vdaf))
+            }
+            VdafLabel::Prio3Histogram64 { buckets } => Prio3Aes128Histogram::new(2, buckets)
+                .map(|vdaf| self.verification_parameter_errors(&vdaf)),
+            // No aggregator implements Hits yet, so there's no verification
+            // parameter format to check against
+            VdafLabel::Hits => Ok(Vec::new()),
+        };
+        match vdaf_errors {
+            Ok(vdaf_errors) => errors.extend(vdaf_errors),
+            Err(e) => errors.push(ValidationError::Vdaf(self.vdaf.clone(), e)),
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    /// Check that each aggregator's VDAF verification parameter, if present,
+    /// decodes for the provided VDAF
+    fn verification_parameter_errors<V>(&self, vdaf: &V) -> Vec<ValidationError>
+    where
+        V: Vdaf,
+        V::VerifyParam: ParameterizedDecode<V>,
+    {
+        [Role::Leader, Role::Helper]
+            .iter()
+            .filter_map(|&role| {
+                let encoded = role.entry(&self.vdaf_verification_parameter)?;
+                V::VerifyParam::get_decoded_with_param(vdaf, encoded)
+                    .err()
+                    .map(|e| ValidationError::VdafVerificationParameter(role, self.vdaf.clone(), e))
+            })
+            .collect()
     }
 
-    fn aggregator_endpoint(&self, role: Role) -> &Url {
-        &self.aggregator_endpoints[role.index()]
+    /// The base URL of the aggregator playing the provided role
+    pub fn aggregator_endpoint(&self, role: Role) -> Result<&Url, Error> {
+        role.entry(&self.aggregator_endpoints)
+            .ok_or(Error::MissingAggregatorEndpoint(role))
     }
 
     fn hpke_config_endpoint(&self, role: Role) -> Result<Url, Error> {
-        Ok(self.aggregator_endpoint(role).join("hpke_config")?)
+        Ok(self.aggregator_endpoint(role)?.join("hpke_config")?)
     }
 
     #[tracing::instrument]
@@ -118,24 +325,28 @@ impl Parameters {
     }
 
     pub fn upload_endpoint(&self) -> Result<Url, Error> {
-        Ok(self.aggregator_endpoint(Role::Leader).join("upload")?)
+        Ok(self.aggregator_endpoint(Role::Leader)?.join("upload")?)
     }
 
     pub fn collect_endpoint(&self) -> Result<Url, Error> {
-        Ok(self.aggregator_endpoint(Role::Leader).join("collect")?)
+        Ok(self.aggregator_endpoint(Role::Leader)?.join("collect")?)
     }
 
     pub fn aggregate_endpoint(&self) -> Result<Url, Error> {
-        Ok(self.aggregator_endpoint(Role::Helper).join("aggregate")?)
+        Ok(self.aggregator_endpoint(Role::Helper)?.join("aggregate")?)
     }
 
     pub fn leader_aggregate_endpoint(&self) -> Result<Url, Error> {
-        Ok(self.aggregator_endpoint(Role::Leader).join("aggregate")?)
+        Ok(self.aggregator_endpoint(Role::Leader)?.join("aggregate")?)
+    }
+
+    pub fn healthz_endpoint(&self, role: Role) -> Result<Url, Error> {
+        Ok(self.aggregator_endpoint(role)?.join("healthz")?)
     }
 
     pub fn aggregate_share_endpoint(&self) -> Result<Url, Error> {
         Ok(self
-            .aggregator_endpoint(Role::Helper)
+            .aggregator_endpoint(Role::Helper)?
             .join("aggregate_share")?)
     }
 
@@ -144,7 +355,37 @@ impl Parameters {
     pub(crate) fn validate_batch_interval(&self, batch_interval: Interval) -> bool {
         batch_interval.duration.0 >= self.min_batch_duration.0
             && batch_interval.start.interval_start(self.min_batch_duration) == batch_interval.start
-            && batch_interval.duration.0 % self.min_batch_duration.0 == 0
+            && batch_interval
+                .duration
+                .0
+                .is_multiple_of(self.min_batch_duration.0)
+    }
+
+    /// Returns the time to put into a report's nonce for an event that occurred
+    /// at `time`, truncated to the task's time precision, if any.
+    pub fn report_time(&self, time: Time) -> Time {
+        match self.time_precision {
+            Some(precision) => time.truncate(precision),
+            None => time,
+        }
+    }
+
+    /// Returns true if the report time is aligned with the task's time
+    /// precision, or if the task does not specify a time precision.
+    pub(crate) fn validate_report_time(&self, time: Time) -> bool {
+        match self.time_precision {
+            Some(precision) => time.is_aligned(precision),
+            None => true,
+        }
+    }
+
+    /// Returns the time before which reports are considered expired, if the
+    /// task's retention policy expires reports.
+    pub(crate) fn report_expiry(&self, now: Time) -> Option<Time> {
+        self.retention
+            .as_ref()
+            .and_then(|retention| retention.report_max_age)
+            .map(|max_age| Time(now.0.saturating_sub(max_age.0)))
     }
 
     /// Decode the VDAF verification parameter for the provided Role
@@ -159,13 +400,14 @@ impl Parameters {
     {
         Ok(V::VerifyParam::get_decoded_with_param(
             vdaf,
-            &self.vdaf_verification_parameter[role.index()],
+            role.entry(&self.vdaf_verification_parameter)
+                .ok_or(Error::MissingVdafVerificationParameter(role))?,
         )?)
     }
 }
 
 /// Randomly generated byte sequence uniquely identifying a PPM task.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TaskId([u8; 32]);
 
 impl TaskId {
@@ -184,15 +426,31 @@ impl Display for TaskId {
     }
 }
 
+impl FromStr for TaskId {
+    type Err = hex::FromHexError;
+
+    /// Parse a hex encoded task ID
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut task_id = [0u8; 32];
+        hex::decode_to_slice(s, &mut task_id)?;
+        Ok(Self(task_id))
+    }
+}
+
 impl AsRef<[u8]> for TaskId {
     fn as_ref(&self) -> &[u8] {
         &self.0
     }
 }
 
-impl From<Vec<u8>> for TaskId {
-    fn from(v: Vec<u8>) -> Self {
-        Self(v.try_into().unwrap())
+impl TryFrom<Vec<u8>> for TaskId {
+    type Error = String;
+
+    fn try_from(v: Vec<u8>) -> Result<Self, Self::Error> {
+        let len = v.len();
+        Ok(Self(v.try_into().map_err(|_| {
+            format!("task ID must be 32 bytes, but is {} bytes", len)
+        })?))
     }
 }
 
@@ -210,6 +468,60 @@ impl Decode for TaskId {
     }
 }
 
+/// Policy for deleting state that aggregators no longer need. Aggregators
+/// periodically delete:
+///
+///   - reports that have been accumulated, keeping only what the helper needs
+///     to detect replays until the report's batch is collected or the report
+///     expires,
+///   - reports whose timestamps are older than `report_max_age`, which are
+///     also rejected if uploaded or aggregated. It must be set, since the
+///     nonces of deleted reports are remembered until they expire.
+///   - accumulators whose privacy budget is used up or that are older than
+///     `accumulator_max_age`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RetentionPolicy {
+    /// How often to delete state
+    #[serde(default = "RetentionPolicy::default_gc_interval")]
+    pub gc_interval: Duration,
+    /// Age after which a report expires, which [`Parameters::validate`]
+    /// requires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_max_age: Option<Duration>,
+    /// Age after which an accumulator is deleted, even if it has privacy budget
+    /// remaining. Must be at least `report_max_age` plus `min_batch_duration`,
+    /// so that accumulators outlive the intervals they accept reports for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accumulator_max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    fn default_gc_interval() -> Duration {
+        Duration(60)
+    }
+}
+
+/// The ways in which a task can group reports into batches, and thus the
+/// queries a collector can make
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum QueryType {
+    /// A batch is every report whose timestamp falls within a batch interval,
+    /// made up of one or more intervals of `min_batch_duration`
+    #[default]
+    TimeInterval,
+    /// The leader assigns reports to batches of `batch_size` reports, each
+    /// identified by a batch ID, regardless of report timestamps. Batches may
+    /// end up smaller than `batch_size` if some of their reports fail to
+    /// aggregate, but must still meet `min_batch_size` to be collected.
+    FixedSize { batch_size: u64 },
+}
+
+impl QueryType {
+    fn is_time_interval(&self) -> bool {
+        matches!(self, Self::TimeInterval)
+    }
+}
+
 /// VDAFs supported. Each entry should correspond to a VDAF instantiation in
 /// libprio
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -225,6 +537,7 @@ mod tests {
     use std::convert::TryInto;
 
     use super::*;
+    use assert_matches::assert_matches;
 
     #[test]
     fn parameters_json_parse() {
@@ -254,11 +567,18 @@ mod tests {
             max_batch_lifetime: 1,
             min_batch_size: 100,
             min_batch_duration: Duration(100000),
+            query_type: QueryType::TimeInterval,
+            time_precision: None,
             aggregator_auth_key: vec![
                 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9,
                 10, 11, 12, 13, 14, 15,
             ],
             vdaf: VdafLabel::Prio3Sum64 { bits: 64 },
+            differential_privacy: None,
+            upload_auth: None,
+            upload_limits: None,
+            retention: None,
+            admin_auth: None,
             vdaf_verification_parameter: vec![
                 vec![
                     203, 44, 250, 83, 141, 201, 227, 218, 70, 243, 219, 43, 18, 34, 210, 241, 0,
@@ -307,4 +627,91 @@ mod tests {
         assert_eq!(params, params_again);
         assert_eq!(params_from_json, params);
     }
+
+    #[test]
+    fn report_time_precision() {
+        let mut params =
+            Parameters::from_json_reader(&include_bytes!("../sample-config/parameters.json")[..])
+                .unwrap();
+
+        params.time_precision = None;
+        assert_eq!(params.report_time(Time(1631907537)), Time(1631907537));
+        assert!(params.validate_report_time(Time(1631907537)));
+
+        params.time_precision = Some(Duration(100));
+        assert_eq!(params.report_time(Time(1631907537)), Time(1631907500));
+        assert!(params.validate_report_time(Time(1631907500)));
+        assert!(!params.validate_report_time(Time(1631907537)));
+    }
+
+    #[test]
+    fn validation() {
+        let params =
+            Parameters::from_json_reader(&include_bytes!("../sample-config/parameters.json")[..])
+                .unwrap();
+        params.validate().unwrap();
+
+        let mut invalid = params.clone();
+        invalid.aggregator_endpoints.truncate(1);
+        invalid.min_batch_duration = Duration(0);
+        invalid.time_precision = Some(Duration(0));
+        invalid.vdaf_verification_parameter[1].push(0);
+        let errors = invalid.validate().unwrap_err().0;
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert_matches!(errors[0], ValidationError::AggregatorEndpointCount(1));
+        assert_matches!(errors[1], ValidationError::Zero("min_batch_duration"));
+        assert_matches!(errors[2], ValidationError::Zero("time_precision"));
+        assert_matches!(
+            errors[3],
+            ValidationError::VdafVerificationParameter(Role::Helper, _, _)
+        );
+
+        let mut invalid = params.clone();
+        invalid.retention = Some(RetentionPolicy {
+            gc_interval: Duration(60),
+            report_max_age: None,
+            accumulator_max_age: Some(Duration(3600)),
+        });
+        let errors = invalid.validate().unwrap_err().0;
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_matches!(errors[0], ValidationError::RetentionWithoutReportMaxAge);
+
+        let mut invalid = params.clone();
+        invalid.retention = Some(RetentionPolicy {
+            gc_interval: Duration(60),
+            report_max_age: Some(Duration(3600)),
+            accumulator_max_age: Some(Duration(3600)),
+        });
+        let errors = invalid.validate().unwrap_err().0;
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_matches!(
+            errors[0],
+            ValidationError::AccumulatorMaxAgeTooShort(Duration(3600), Duration(3650))
+        );
+
+        let mut invalid = params.clone();
+        invalid.aggregator_endpoints[1] = "https://helper.fake/ppm".try_into().unwrap();
+        invalid.vdaf = VdafLabel::Prio3Sum64 { bits: 200 };
+        let errors = invalid.validate().unwrap_err().0;
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_matches!(
+            errors[0],
+            ValidationError::AggregatorEndpointNoTrailingSlash(_)
+        );
+        assert_matches!(errors[1], ValidationError::Vdaf(_, _));
+
+        let mut invalid = params;
+        invalid.vdaf_verification_parameter.pop();
+        let json = serde_json::to_vec(&invalid).unwrap();
+        assert_matches!(
+            Parameters::from_json_reader(&json[..]),
+            Err(Error::Invalid(_))
+        );
+    }
+
+    #[test]
+    fn task_id_length() {
+        assert!(TaskId::try_from(vec![0; 32]).is_ok());
+        assert!(TaskId::try_from(vec![0; 31]).is_err());
+    }
 }
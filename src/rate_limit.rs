@@ -0,0 +1,404 @@
+//! Token bucket rate limiting of uploads to the leader, and caps on the reports
+//! it holds that have yet to be aggregated.
+
+use crate::error::{retry_after_seconds, IntoHttpApiProblem, ProblemDocumentType};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Environment variable capping how many reports the leader holds that have
+/// yet to be aggregated, across all of the tasks it serves
+pub const MAX_PENDING_REPORTS_ENV: &str = "PPM_MAX_PENDING_REPORTS";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("upload rate limit exceeded, retry after {} seconds", retry_after_seconds(*.0))]
+    RateLimited(Duration),
+}
+
+impl IntoHttpApiProblem for Error {
+    fn problem_document_type(&self) -> Option<ProblemDocumentType> {
+        Some(ProblemDocumentType::RateLimited)
+    }
+
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited(retry_after) => Some(*retry_after),
+        }
+    }
+}
+
+/// A token bucket rate limit
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RateLimit {
+    /// How many uploads may be made in a burst
+    pub burst: u64,
+    /// How many uploads per second may be sustained
+    pub per_second: u64,
+}
+
+/// Limits on uploads to the leader
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UploadLimits {
+    /// Rate limit on all uploads to the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_task: Option<RateLimit>,
+    /// Rate limit on uploads to the task from any one IP address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_source_ip: Option<RateLimit>,
+    /// How many of the task's reports the leader may hold that have yet to be
+    /// aggregated. This applies on top of the leader-wide
+    /// [`PendingReportLimit`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pending_reports_per_task: Option<u64>,
+}
+
+/// Bucket of tokens that refills continuously at a constant rate, up to its
+/// capacity
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second as f64).min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Take a token from the bucket if there is one, otherwise return how long
+    /// until there will be
+    fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        self.available(now)?;
+        self.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Check whether the bucket has a token, without taking it, returning how
+    /// long until it will if not
+    fn available(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            return Ok(());
+        }
+
+        if self.limit.per_second == 0 {
+            // The bucket never refills, so the client may as well wait forever
+            return Err(Duration::from_secs(u32::MAX as u64));
+        }
+
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.limit.per_second as f64,
+        ))
+    }
+}
+
+/// Most source IP addresses whose buckets are tracked before the least
+/// recently used one is discarded
+const MAX_TRACKED_SOURCES: usize = 10000;
+
+/// Bucket of a source IP address
+#[derive(Debug)]
+struct TrackedSource {
+    bucket: TokenBucket,
+    /// Key of the source in [`UploadRateLimiter::recently_used`]
+    last_use: u64,
+}
+
+/// Enforces a task's upload rate limits
+#[derive(Debug)]
+pub(crate) struct UploadRateLimiter {
+    task: Option<TokenBucket>,
+    per_source_ip: Option<RateLimit>,
+    sources: HashMap<IpAddr, TrackedSource>,
+    /// Tracked sources by when they were last used, least recent first
+    recently_used: BTreeMap<u64, IpAddr>,
+    next_use: u64,
+}
+
+impl UploadRateLimiter {
+    pub(crate) fn new(limits: &UploadLimits) -> Self {
+        Self {
+            task: limits
+                .per_task
+                .map(|limit| TokenBucket::new(limit, Instant::now())),
+            per_source_ip: limits.per_source_ip,
+            sources: HashMap::new(),
+            recently_used: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    /// Permit an upload from the source, if its rate limit allows it. This is
+    /// checked before the upload is authenticated, so that each source can
+    /// only use up its own limit.
+    pub(crate) fn check_source(
+        &mut self,
+        source: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), Error> {
+        if let (Some(limit), Some(source)) = (self.per_source_ip, source) {
+            if let Some(tracked) = self.sources.get(&source) {
+                self.recently_used.remove(&tracked.last_use);
+            } else if self.sources.len() >= MAX_TRACKED_SOURCES {
+                // The forgotten source's limit starts over if it comes back
+                if let Some((_, evicted)) = self.recently_used.pop_first() {
+                    self.sources.remove(&evicted);
+                }
+            }
+
+            let last_use = self.next_use;
+            self.next_use += 1;
+            self.recently_used.insert(last_use, source);
+            let tracked = self.sources.entry(source).or_insert_with(|| TrackedSource {
+                bucket: TokenBucket::new(limit, now),
+                last_use,
+            });
+            tracked.last_use = last_use;
+            tracked
+                .bucket
+                .try_acquire(now)
+                .map_err(Error::RateLimited)?;
+        }
+
+        Ok(())
+    }
+
+    /// Permit an authenticated upload, if the task's rate limit allows it
+    pub(crate) fn check_task(&mut self, now: Instant) -> Result<(), Error> {
+        if let Some(task_bucket) = &mut self.task {
+            task_bucket.try_acquire(now).map_err(Error::RateLimited)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A cap on the reports that the leader holds across all of the tasks it
+/// serves that have yet to be aggregated, along with how many it holds. Clones
+/// share the count.
+#[derive(Clone, Debug, Default)]
+pub struct PendingReportLimit {
+    max_pending_reports: Option<u64>,
+    pending_reports: Arc<AtomicU64>,
+}
+
+impl PendingReportLimit {
+    /// A limit of `max_pending_reports`, or no limit if it's `None`
+    pub fn new(max_pending_reports: Option<u64>) -> Self {
+        Self {
+            max_pending_reports,
+            pending_reports: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// How many reports are pending across all tasks
+    pub fn pending_reports(&self) -> u64 {
+        self.pending_reports.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if another report may become pending
+    fn has_room(&self) -> bool {
+        self.max_pending_reports
+            .is_none_or(|max| self.pending_reports() < max)
+    }
+}
+
+/// How many of a task's reports are pending, which also count towards the
+/// leader-wide [`PendingReportLimit`]. They stop counting towards it when this
+/// is dropped along with the task.
+#[derive(Debug)]
+pub(crate) struct PendingReports {
+    task: u64,
+    limit: PendingReportLimit,
+}
+
+impl PendingReports {
+    pub(crate) fn new(limit: PendingReportLimit) -> Self {
+        Self { task: 0, limit }
+    }
+
+    /// How many of the task's reports are pending
+    pub(crate) fn task(&self) -> u64 {
+        self.task
+    }
+
+    /// Returns true if the leader-wide limit leaves room for another report
+    pub(crate) fn has_room(&self) -> bool {
+        self.limit.has_room()
+    }
+
+    /// Count another of the task's reports as pending, unless the leader
+    /// already holds as many pending reports as the leader-wide limit allows,
+    /// in which case false is returned
+    pub(crate) fn try_add(&mut self) -> bool {
+        let max = self.limit.max_pending_reports.unwrap_or(u64::MAX);
+        let added = self
+            .limit
+            .pending_reports
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                (pending < max).then(|| pending + 1)
+            })
+            .is_ok();
+        if added {
+            self.task += 1;
+        }
+        added
+    }
+
+    /// Stop counting one of the task's reports as pending
+    pub(crate) fn remove(&mut self) {
+        self.task -= 1;
+        self.limit.pending_reports.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for PendingReports {
+    fn drop(&mut self) {
+        self.limit
+            .pending_reports
+            .fetch_sub(self.task, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(
+            RateLimit {
+                burst: 2,
+                per_second: 1,
+            },
+            start,
+        );
+
+        bucket.try_acquire(start).unwrap();
+        bucket.try_acquire(start).unwrap();
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_secs(1)));
+
+        let later = start + Duration::from_millis(1500);
+        bucket.try_acquire(later).unwrap();
+        assert_eq!(bucket.try_acquire(later), Err(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn rate_limited_message() {
+        // The message agrees with the Retry-After header
+        assert_eq!(
+            Error::RateLimited(Duration::from_millis(500)).to_string(),
+            "upload rate limit exceeded, retry after 1 seconds"
+        );
+    }
+
+    #[test]
+    fn per_source_ip_limit() {
+        let now = Instant::now();
+        let mut limiter = UploadRateLimiter::new(&UploadLimits {
+            per_task: Some(RateLimit {
+                burst: 3,
+                per_second: 1,
+            }),
+            per_source_ip: Some(RateLimit {
+                burst: 1,
+                per_second: 1,
+            }),
+            max_pending_reports_per_task: None,
+        });
+        let first = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let second = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)));
+
+        let mut check = |source| {
+            limiter.check_source(source, now)?;
+            limiter.check_task(now)
+        };
+
+        check(first).unwrap();
+        assert!(matches!(check(first), Err(Error::RateLimited(_))));
+        check(second).unwrap();
+        // Refused uploads don't count against the task's limit
+        check(None).unwrap();
+        assert!(matches!(check(None), Err(Error::RateLimited(_))));
+    }
+
+    #[test]
+    fn tracked_sources_bounded() {
+        let now = Instant::now();
+        let mut limiter = UploadRateLimiter::new(&UploadLimits {
+            per_source_ip: Some(RateLimit {
+                burst: 1,
+                per_second: 0,
+            }),
+            ..Default::default()
+        });
+        let source = |index: usize| Some(IpAddr::V4(Ipv4Addr::from(index as u32)));
+
+        for index in 0..MAX_TRACKED_SOURCES {
+            limiter.check_source(source(index), now).unwrap();
+        }
+        // Using the first source makes the second the least recently used
+        assert!(limiter.check_source(source(0), now).is_err());
+        limiter
+            .check_source(source(MAX_TRACKED_SOURCES), now)
+            .unwrap();
+        assert_eq!(limiter.sources.len(), MAX_TRACKED_SOURCES);
+        assert_eq!(limiter.recently_used.len(), MAX_TRACKED_SOURCES);
+
+        assert!(limiter.check_source(source(0), now).is_err());
+        limiter.check_source(source(1), now).unwrap();
+    }
+
+    #[test]
+    fn pending_report_limit_shared() {
+        let limit = PendingReportLimit::new(Some(3));
+        let mut first = PendingReports::new(limit.clone());
+        let mut second = PendingReports::new(limit.clone());
+
+        assert!(first.try_add());
+        assert!(first.try_add());
+        assert!(second.try_add());
+        assert!(!second.has_room());
+        assert!(!first.try_add());
+        assert_eq!((first.task(), second.task()), (2, 1));
+
+        first.remove();
+        assert!(second.try_add());
+        assert_eq!(limit.pending_reports(), 3);
+
+        // Dropping a task releases its pending reports
+        drop(second);
+        assert_eq!(limit.pending_reports(), 1);
+        assert!(first.try_add());
+    }
+}
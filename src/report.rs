@@ -6,9 +6,15 @@ use crate::{
     parameters::TaskId,
     Nonce,
 };
-use num_enum::{IntoPrimitive, TryFromPrimitive};
-use prio::codec::{decode_u16_items, encode_u16_items, CodecError, Decode, Encode};
-use std::{convert::TryFrom, io::Cursor};
+use prio::codec::{
+    decode_u16_items, decode_u8_items, encode_u16_items, encode_u8_items, CodecError, Decode,
+    Encode,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    io::Cursor,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -22,6 +28,12 @@ pub enum Error {
     Parameters(#[from] crate::parameters::Error),
     #[error("Primitive conversion: {0}")]
     Primitive(String),
+    #[error("unrecognized critical extension {0}")]
+    UnrecognizedExtension(ExtensionType),
+    #[error("invalid extension {0}: {1}")]
+    InvalidExtension(ExtensionType, String),
+    #[error("duplicate extension {0}")]
+    DuplicateExtension(ExtensionType),
 }
 
 impl IntoHttpApiProblem for Error {
@@ -84,10 +96,45 @@ pub struct Extension {
     extension_data: Vec<u8>,
 }
 
+impl Extension {
+    pub fn new(extension_type: ExtensionType, extension_data: Vec<u8>) -> Self {
+        Self {
+            extension_type: extension_type.canonical(),
+            extension_data,
+        }
+    }
+
+    /// Construct an extension from its typed representation
+    pub fn from_typed<T: TypedExtension>(extension: &T) -> Self {
+        Self::new(T::EXTENSION_TYPE, extension.get_encoded())
+    }
+
+    pub fn extension_type(&self) -> ExtensionType {
+        self.extension_type
+    }
+
+    pub fn extension_data(&self) -> &[u8] {
+        &self.extension_data
+    }
+
+    /// Decode the extension's data as `T`. Fails if the extension is of some
+    /// other type or if the data is malformed.
+    pub fn to_typed<T: TypedExtension>(&self) -> Result<T, Error> {
+        if self.extension_type != T::EXTENSION_TYPE {
+            return Err(Error::InvalidExtension(
+                self.extension_type,
+                format!("expected extension {}", T::EXTENSION_TYPE),
+            ));
+        }
+
+        T::get_decoded(&self.extension_data)
+            .map_err(|e| Error::InvalidExtension(self.extension_type, e.to_string()))
+    }
+}
+
 impl Decode for Extension {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
-        let extension_type = ExtensionType::try_from(u16::decode(bytes)?)
-            .map_err(|e| CodecError::Other(Box::new(e)))?;
+        let extension_type = ExtensionType::from(u16::decode(bytes)?);
         let extension_data = decode_u16_items(&(), bytes)?;
 
         Ok(Self {
@@ -104,10 +151,251 @@ impl Encode for Extension {
     }
 }
 
-/// Types of report extensions
-#[derive(Clone, Copy, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
-#[repr(u16)]
+/// Types of report extensions. Extension types with the high bit set are
+/// critical: aggregators must reject reports bearing critical extensions they
+/// don't recognize, but ignore unrecognized extensions that aren't critical.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub enum ExtensionType {
-    TBD = 0,
-    MaximumExtensionType = 65535,
+    /// See [`DeviceClass`]
+    DeviceClass,
+    /// Any extension type this implementation doesn't know about
+    Unknown(u16),
+}
+
+impl ExtensionType {
+    const DEVICE_CLASS: u16 = 0x0001;
+    const CRITICAL_BIT: u16 = 0x8000;
+
+    /// Returns true if aggregators that don't recognize the extension must
+    /// reject the report
+    pub fn is_critical(self) -> bool {
+        u16::from(self) & Self::CRITICAL_BIT != 0
+    }
+
+    /// The variant decoding would produce for this type's value, so that e.g.
+    /// `Unknown(0x0001)` compares equal to `DeviceClass`
+    fn canonical(self) -> Self {
+        Self::from(u16::from(self))
+    }
+}
+
+impl From<u16> for ExtensionType {
+    fn from(value: u16) -> Self {
+        match value {
+            Self::DEVICE_CLASS => Self::DeviceClass,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<ExtensionType> for u16 {
+    fn from(extension_type: ExtensionType) -> Self {
+        match extension_type {
+            ExtensionType::DeviceClass => ExtensionType::DEVICE_CLASS,
+            ExtensionType::Unknown(value) => value,
+        }
+    }
+}
+
+impl Display for ExtensionType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeviceClass => write!(f, "DeviceClass"),
+            Self::Unknown(value) => write!(f, "{:#06x}", value),
+        }
+    }
+}
+
+/// An extension whose data has a structured encoding
+pub trait TypedExtension: Encode + Decode {
+    const EXTENSION_TYPE: ExtensionType;
+
+    /// Check that the extension is acceptable, beyond being well formed
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Tags a report with the class of device that produced it (e.g. "mobile" or
+/// "desktop"), so that aggregators can tell clients apart without learning
+/// anything more specific about them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceClass(pub String);
+
+impl DeviceClass {
+    /// Longest device class permitted, in bytes
+    pub const MAX_LENGTH: usize = 64;
+}
+
+impl Encode for DeviceClass {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_u8_items(bytes, &(), self.0.as_bytes());
+    }
+}
+
+impl Decode for DeviceClass {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        let class: Vec<u8> = decode_u8_items(&(), bytes)?;
+        let class = String::from_utf8(class).map_err(|e| CodecError::Other(Box::new(e)))?;
+
+        Ok(Self(class))
+    }
+}
+
+impl TypedExtension for DeviceClass {
+    const EXTENSION_TYPE: ExtensionType = ExtensionType::DeviceClass;
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.0.is_empty() || self.0.len() > Self::MAX_LENGTH {
+            return Err(Error::InvalidExtension(
+                Self::EXTENSION_TYPE,
+                format!("length must be between 1 and {}", Self::MAX_LENGTH),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+type ExtensionValidator = Box<dyn Fn(&Extension) -> Result<(), Error> + Send + Sync>;
+
+/// The report extensions an aggregator recognizes, and how to validate each of
+/// them. The default registry recognizes all the extensions defined in this
+/// module.
+pub struct ExtensionRegistry {
+    validators: HashMap<ExtensionType, ExtensionValidator>,
+}
+
+impl ExtensionRegistry {
+    /// A registry that recognizes no extensions
+    pub fn empty() -> Self {
+        Self {
+            validators: HashMap::new(),
+        }
+    }
+
+    /// Recognize extensions of type `T`, which must decode and pass
+    /// `T::validate`
+    pub fn register<T: TypedExtension>(&mut self) {
+        self.register_validator(T::EXTENSION_TYPE, |extension| {
+            extension.to_typed::<T>()?.validate()
+        });
+    }
+
+    /// Recognize extensions of the type, validating them with the provided
+    /// function
+    pub fn register_validator<F>(&mut self, extension_type: ExtensionType, validator: F)
+    where
+        F: Fn(&Extension) -> Result<(), Error> + Send + Sync + 'static,
+    {
+        self.validators
+            .insert(extension_type.canonical(), Box::new(validator));
+    }
+
+    pub fn is_registered(&self, extension_type: ExtensionType) -> bool {
+        self.validators.contains_key(&extension_type.canonical())
+    }
+
+    /// Check a report's extensions. Recognized extensions must be valid and
+    /// unrecognized ones must not be critical. No extension type may appear
+    /// more than once.
+    pub fn validate(&self, extensions: &[Extension]) -> Result<(), Error> {
+        for (index, extension) in extensions.iter().enumerate() {
+            if extensions[..index]
+                .iter()
+                .any(|other| other.extension_type == extension.extension_type)
+            {
+                return Err(Error::DuplicateExtension(extension.extension_type));
+            }
+
+            match self.validators.get(&extension.extension_type) {
+                Some(validator) => validator(extension)?,
+                None if extension.extension_type.is_critical() => {
+                    return Err(Error::UnrecognizedExtension(extension.extension_type))
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ExtensionRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register::<DeviceClass>();
+        registry
+    }
+}
+
+impl fmt::Debug for ExtensionRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.validators.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_device_class() {
+        let extension = Extension::from_typed(&DeviceClass("mobile".to_string()));
+        assert_eq!(extension.extension_type(), ExtensionType::DeviceClass);
+
+        let decoded = Extension::get_decoded(&extension.get_encoded()).unwrap();
+        assert_eq!(
+            decoded.to_typed::<DeviceClass>().unwrap(),
+            DeviceClass("mobile".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_variant_of_known_extension_type() {
+        let extension = Extension::new(ExtensionType::Unknown(0x0001), b"mobile".to_vec());
+        assert_eq!(extension.extension_type(), ExtensionType::DeviceClass);
+        assert_eq!(
+            extension,
+            Extension::get_decoded(&extension.get_encoded()).unwrap()
+        );
+        assert!(ExtensionRegistry::default().is_registered(ExtensionType::Unknown(0x0001)));
+
+        // Validated as a device class, so it's a duplicate of one
+        let device_class = Extension::from_typed(&DeviceClass("mobile".to_string()));
+        assert!(matches!(
+            ExtensionRegistry::default().validate(&[device_class, extension]),
+            Err(Error::DuplicateExtension(ExtensionType::DeviceClass))
+        ));
+    }
+
+    #[test]
+    fn registry_validation() {
+        let registry = ExtensionRegistry::default();
+        let device_class = Extension::from_typed(&DeviceClass("desktop".to_string()));
+        let unknown = Extension::new(ExtensionType::Unknown(0x0100), vec![1, 2, 3]);
+        let unknown_critical = Extension::new(ExtensionType::Unknown(0x8100), vec![]);
+
+        registry
+            .validate(&[device_class.clone(), unknown.clone()])
+            .unwrap();
+
+        assert!(matches!(
+            registry.validate(&[unknown_critical]),
+            Err(Error::UnrecognizedExtension(ExtensionType::Unknown(0x8100)))
+        ));
+        assert!(matches!(
+            registry.validate(&[device_class.clone(), device_class]),
+            Err(Error::DuplicateExtension(ExtensionType::DeviceClass))
+        ));
+        assert!(matches!(
+            registry.validate(&[Extension::from_typed(&DeviceClass(String::new()))]),
+            Err(Error::InvalidExtension(ExtensionType::DeviceClass, _))
+        ));
+        assert!(matches!(
+            ExtensionRegistry::empty()
+                .validate(&[Extension::new(ExtensionType::DeviceClass, vec![0xff])]),
+            Ok(())
+        ));
+    }
 }
@@ -0,0 +1,118 @@
+//! Serving an aggregator's routes on a listener provided by the caller, so that
+//! aggregators can listen on ephemeral ports and be shut down gracefully
+
+use std::{
+    convert::Infallible,
+    future::{self, Future},
+    io,
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    pin::Pin,
+};
+use warp::{
+    hyper::{
+        server::conn::AddrStream,
+        service::{make_service_fn, service_fn, Service},
+        Body, Request, Response, Server,
+    },
+    Filter,
+};
+
+/// Where an aggregator accepts connections, and when it stops doing so
+pub struct Listener {
+    listener: TcpListener,
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl Listener {
+    /// Listen on `0.0.0.0` at `port` for as long as the process runs
+    pub fn bind(port: u16) -> io::Result<Self> {
+        Ok(Self::new(
+            TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?,
+            future::pending(),
+        ))
+    }
+
+    /// Accept connections on `listener` until `shutdown` completes, after which
+    /// requests already in progress are allowed to finish
+    pub fn new(listener: TcpListener, shutdown: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self {
+            listener,
+            shutdown: Box::pin(shutdown),
+        }
+    }
+
+    /// The address connections are accepted on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+/// Address of the peer that sent a request, attached to each request by
+/// [`serve`]
+#[derive(Clone, Copy, Debug)]
+struct RemoteAddr(SocketAddr);
+
+/// Filter that extracts the address of the peer that sent the request. Unlike
+/// `warp::addr::remote`, this works for routes served by [`serve`].
+pub(crate) fn remote_addr(
+) -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::ext::optional::<RemoteAddr>().map(|remote: Option<RemoteAddr>| remote.map(|r| r.0))
+}
+
+/// Serve requests to `service` (e.g. `warp::service(routes)`) on the listener
+/// until it is shut down
+pub(crate) async fn serve<S>(service: S, listener: Listener) -> Result<(), warp::hyper::Error>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let make_service = make_service_fn(move |connection: &AddrStream| {
+        let remote_addr = RemoteAddr(connection.remote_addr());
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(remote_addr);
+                service.clone().call(request)
+            }))
+        }
+    });
+
+    Server::from_tcp(listener.listener)?
+        .serve(make_service)
+        .with_graceful_shutdown(listener.shutdown)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn serve_until_shutdown() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let (shutdown, receiver) = oneshot::channel();
+        let listener = Listener::new(listener, async move {
+            receiver.await.unwrap();
+        });
+
+        let route =
+            remote_addr().map(|remote: Option<SocketAddr>| remote.unwrap().ip().to_string());
+        let server = tokio::spawn(serve(warp::service(route), listener));
+
+        let body = reqwest::get(format!("http://{}/", address))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "127.0.0.1");
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+//! Health, readiness and status endpoints served by the leader and helper.
+//!
+//! `/healthz` succeeds for as long as the process is serving requests.
+//! `/readyz` succeeds once the aggregator can service protocol requests, read
+//! its task store and, for the leader, reach the helper. `/status` reports on
+//! the aggregator's reports and accumulators and `/failures` counts failed
+//! reports by reason in each batch and `/collect_audit` lists the queries made
+//! against each task's aggregate shares. All three require the credentials in
+//! `parameters.json`'s `admin_auth`, which cover every task the aggregator
+//! serves.
+
+use crate::{auth, parameters::TaskId};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+use warp::{filters::BoxedFilter, reply::Response, Filter, Reply};
+
+/// How long readiness checks wait for the aggregator's state or the helper
+pub(crate) const READINESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Status of a task in an aggregator
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TaskStatus {
+    pub task_id: TaskId,
+    /// Reports that have been received but not yet accumulated or failed
+    pub pending_reports: u64,
+    /// Reports that have been accumulated and are still stored
+    pub aggregated_reports: u64,
+    /// Reports that could not be prepared or were rejected by the other
+    /// aggregator
+    pub failed_reports: u64,
+    /// Each interval of `min_batch_duration` or fixed size batch's accumulator
+    pub batches: Vec<BatchStatus>,
+}
+
+/// Status of the accumulator for an interval of `min_batch_duration` or a
+/// fixed size batch
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BatchStatus {
+    pub batch: String,
+    /// How many reports have been accumulated, or `None` for tasks with
+    /// differential privacy, whose exact counts aren't published
+    pub contributions: Option<u64>,
+    /// How many queries have consumed the accumulator's privacy budget
+    pub consumed_privacy_budget: u64,
+    /// Whether the batch has been collected
+    pub collected: bool,
+    /// When the first report was accumulated, in seconds since the UNIX epoch
+    pub created: u64,
+}
+
+/// Response to a request to `/status`
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct StatusResponse {
+    pub tasks: Vec<TaskStatus>,
+}
+
+/// Reports in an interval of `min_batch_duration` or a fixed size batch that
+/// failed to be prepared by either aggregator
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BatchFailures {
+    /// The batch, or `None` for reports whose batch isn't known, like those
+    /// with a nonce the helper doesn't recognize
+    pub batch: Option<String>,
+    /// How many reports failed for each `TransitionError`, e.g.
+    /// `VdafPrepError`
+    pub reasons: BTreeMap<String, u64>,
+}
+
+/// Failed reports of a task in an aggregator
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TaskFailures {
+    pub task_id: TaskId,
+    /// Each batch with failed reports, oldest failure first
+    pub batches: Vec<BatchFailures>,
+}
+
+/// Response to a request to `/failures`
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct FailuresResponse {
+    pub tasks: Vec<TaskFailures>,
+}
+
+/// A query against an aggregator's aggregate shares
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CollectAuditEntry {
+    pub batch: String,
+    /// Hex encoded aggregation parameter
+    pub aggregation_parameter: String,
+    /// When the aggregator received the query, in seconds since the UNIX epoch
+    pub timestamp: u64,
+    /// Whether the aggregate share was released, consuming privacy budget
+    pub collected: bool,
+    /// How many reports the released aggregate share included, or `None` if
+    /// it wasn't released or the task has differential privacy
+    pub report_count: Option<u64>,
+    /// Why the query was refused or failed, if it was
+    pub rejection_reason: Option<String>,
+}
+
+/// Queries made against a task's aggregate shares
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TaskCollectAudit {
+    pub task_id: TaskId,
+    /// Each query whose batch still has an accumulator or is remembered as
+    /// collected, oldest first
+    pub queries: Vec<CollectAuditEntry>,
+}
+
+/// Response to a request to `/collect_audit`
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CollectAuditResponse {
+    pub tasks: Vec<TaskCollectAudit>,
+}
+
+/// Outcome of a readiness check, rendered as the response to `/readyz`
+#[derive(Debug)]
+pub(crate) enum Readiness {
+    Ready,
+    NotReady(String),
+}
+
+impl Reply for Readiness {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Ready => "ready".into_response(),
+            Self::NotReady(reason) => warp::reply::with_status(
+                format!("not ready: {}", reason),
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .into_response(),
+        }
+    }
+}
+
+/// Check the credentials presented to an administrative endpoint against the
+/// task's `admin_auth`
+pub(crate) fn authenticate_admin(
+    admin_auth: Option<&auth::AdminAuth>,
+    headers: &http::HeaderMap,
+) -> Result<(), auth::AdminError> {
+    admin_auth
+        .ok_or(auth::AdminError::Unauthorized)?
+        .authenticate(headers)
+}
+
+/// The `/healthz` endpoint
+pub(crate) fn healthz_endpoint() -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("healthz"))
+        .map(|| "ok")
+        .with(warp::trace::named("healthz"))
+        .boxed()
+}
@@ -0,0 +1,1027 @@
+//! Management of the tasks an aggregator serves.
+//!
+//! Aggregators serve the task defined in `parameters.json` along with any tasks
+//! created at runtime through the task management API, whose parameters are
+//! persisted in a [`TaskStore`] so that they survive restarts. Every task must
+//! use the same VDAF as the task in `parameters.json`.
+//!
+//! The API requires the credentials in `parameters.json`'s `admin_auth`, which
+//! are read at startup and guard every task. Tasks can't be created or updated
+//! with other credentials, since they would have no effect:
+//!
+//!   - `GET /tasks` lists the IDs of the tasks being served
+//!   - `POST /tasks` creates a task from the JSON encoded `Parameters` in the
+//!     body
+//!   - `GET /tasks/{task ID}` returns the task's parameters as a [`TaskView`]
+//!   - `PUT /tasks/{task ID}` replaces the task's `Parameters`, e.g. to rotate
+//!     its `aggregator_auth_key`. Parameters that existing reports and
+//!     accumulators depend on can't be changed.
+//!   - `DELETE /tasks/{task ID}` retires the task, deleting its reports and
+//!     accumulators. Retired tasks stay retired across restarts, even the one
+//!     in `parameters.json`, and their IDs can't be used again.
+//!
+//! Creating and updating tasks also respond with a [`TaskView`], which leaves
+//! out the task's secrets. Task IDs in paths are hex encoded. If the leader is
+//! asked to with `?push_to_helper=true`, it makes the same change on the helper
+//! before making it itself, presenting the helper with the credentials it was
+//! given.
+
+use crate::{
+    auth::{self, AdminAuth, Ed25519PublicKey, UploadAuth},
+    config_path,
+    dp::DifferentialPrivacy,
+    error::{response_to_api_problem, IntoHttpApiProblem, ProblemDocumentType},
+    hpke,
+    parameters::{self, Parameters, QueryType, RetentionPolicy, TaskId, VdafLabel},
+    rate_limit::UploadLimits,
+    with_shared_value, Duration, Role, Time,
+};
+use bytes::Bytes;
+use http::{header::AUTHORIZATION, HeaderMap, Method, StatusCode};
+use http_api_problem::HttpApiProblem;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+use url::Url;
+use warp::{filters::BoxedFilter, reply, Filter, Rejection, Reply};
+
+/// How often aggregators check whether any task is due for garbage collection
+const GARBAGE_COLLECTION_TICK: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown task ID {0}")]
+    UnrecognizedTask(TaskId),
+    #[error("task {0} already exists")]
+    TaskExists(TaskId),
+    #[error("task {0} was retired")]
+    TaskRetired(TaskId),
+    #[error("malformed task ID")]
+    MalformedTaskId,
+    #[error("task ID {0} in parameters doesn't match request")]
+    TaskIdMismatch(TaskId),
+    #[error("task uses VDAF {0:?}, which this aggregator doesn't serve")]
+    UnsupportedVdaf(VdafLabel),
+    #[error("task parameter {0} can't be changed")]
+    ImmutableParameter(&'static str),
+    #[error("task's admin_auth must match the aggregator's")]
+    AdminAuthMismatch,
+    #[error("only the leader can push tasks to the helper")]
+    NotLeader,
+    #[error("{0}")]
+    Auth(#[from] auth::AdminError),
+    #[error("invalid task parameters: {0}")]
+    Parameters(#[from] parameters::Error),
+    #[error("failed to set up task: {0}")]
+    Setup(Box<dyn std::error::Error + Send + Sync>),
+    #[error("task store error: {1}")]
+    Store(#[source] io::Error, PathBuf),
+    #[error("JSON error {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("HTTP client error {0}")]
+    HttpClient(#[from] reqwest::Error),
+    #[error("helper error: {0:?}")]
+    HelperError(Box<HttpApiProblem>),
+    #[error("HTTP response status {0} from helper: {1}")]
+    HelperHttpRequest(StatusCode, String),
+}
+
+impl IntoHttpApiProblem for Error {
+    fn problem_document_type(&self) -> Option<ProblemDocumentType> {
+        match self {
+            Self::UnrecognizedTask(_) => Some(ProblemDocumentType::UnrecognizedTask),
+            Self::TaskExists(_)
+            | Self::TaskRetired(_)
+            | Self::MalformedTaskId
+            | Self::TaskIdMismatch(_)
+            | Self::UnsupportedVdaf(_)
+            | Self::ImmutableParameter(_)
+            | Self::AdminAuthMismatch
+            | Self::NotLeader
+            | Self::Parameters(_)
+            | Self::Json(_) => Some(ProblemDocumentType::UnrecognizedMessage),
+            Self::Auth(e) => e.problem_document_type(),
+            Self::HelperError(_) | Self::HelperHttpRequest(_, _) => {
+                Some(ProblemDocumentType::HelperError)
+            }
+            Self::Setup(_) | Self::Store(_, _) | Self::HttpClient(_) => None,
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::TaskExists(_) => StatusCode::CONFLICT,
+            Self::Auth(e) => e.status_code(),
+            Self::TaskRetired(_) => StatusCode::GONE,
+            Self::HelperError(_) | Self::HelperHttpRequest(_, _) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Where an aggregator persists the parameters of tasks created at runtime
+#[derive(Clone, Debug)]
+pub struct TaskStore {
+    /// Directory containing a JSON file per task and an empty `.retired` file
+    /// per retired task, or None if tasks are only kept in memory
+    directory: Option<PathBuf>,
+}
+
+impl TaskStore {
+    /// A store that keeps nothing, so that tasks created at runtime are lost
+    /// when the aggregator exits
+    pub fn in_memory() -> Self {
+        Self { directory: None }
+    }
+
+    /// A store that keeps each task's parameters in a JSON file in `directory`
+    pub fn directory<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: Some(directory.into()),
+        }
+    }
+
+    /// The store in the config directory for an aggregator in the role
+    pub fn from_config_dir(role: Role) -> Self {
+        Self::directory(config_path().join(format!("{}-tasks", role.name())))
+    }
+
+    fn task_path(&self, task_id: TaskId) -> Option<PathBuf> {
+        self.directory
+            .as_ref()
+            .map(|directory| directory.join(format!("{}.json", task_id)))
+    }
+
+    fn retired_path(&self, task_id: TaskId) -> Option<PathBuf> {
+        self.directory
+            .as_ref()
+            .map(|directory| directory.join(format!("{}.retired", task_id)))
+    }
+
+    /// Paths of the files in the store's directory with the extension
+    fn paths_with_extension(&self, extension: &str) -> Result<Vec<PathBuf>, Error> {
+        let directory = match &self.directory {
+            Some(directory) => directory,
+            None => return Ok(vec![]),
+        };
+
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(Error::Store(e, directory.clone())),
+        };
+
+        let mut paths = vec![];
+        for entry in entries {
+            let path = entry
+                .map_err(|e| Error::Store(e, directory.clone()))?
+                .path();
+            if path.extension().is_some_and(|e| e == extension) {
+                paths.push(path);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// Check that the store's directory can be created and read, so that
+    /// tasks created at runtime can be persisted
+    pub fn check(&self) -> Result<(), Error> {
+        let directory = match &self.directory {
+            Some(directory) => directory,
+            None => return Ok(()),
+        };
+
+        fs::create_dir_all(directory).map_err(|e| Error::Store(e, directory.clone()))?;
+        fs::read_dir(directory).map_err(|e| Error::Store(e, directory.clone()))?;
+
+        Ok(())
+    }
+
+    /// Load the parameters of every stored task
+    pub fn load(&self) -> Result<Vec<Parameters>, Error> {
+        let mut tasks = vec![];
+        for path in self.paths_with_extension("json")? {
+            let file = File::open(&path).map_err(|e| Error::Store(e, path.clone()))?;
+            tasks.push(Parameters::from_json_reader(file)?);
+        }
+
+        Ok(tasks)
+    }
+
+    /// Load the IDs of every retired task
+    pub fn load_retired(&self) -> Result<HashSet<TaskId>, Error> {
+        let mut retired = HashSet::new();
+        for path in self.paths_with_extension("retired")? {
+            let task_id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+                .ok_or(Error::MalformedTaskId)?;
+            retired.insert(task_id);
+        }
+
+        Ok(retired)
+    }
+
+    /// Store the task's parameters, replacing any previously stored
+    pub fn put(&self, parameters: &Parameters) -> Result<(), Error> {
+        let (directory, path) = match (&self.directory, self.task_path(parameters.task_id)) {
+            (Some(directory), Some(path)) => (directory, path),
+            _ => return Ok(()),
+        };
+
+        fs::create_dir_all(directory).map_err(|e| Error::Store(e, directory.clone()))?;
+
+        // Write to a temporary file and rename it over the old one so that a
+        // crash never leaves a partially written task behind
+        let temporary_path = path.with_extension("json.tmp");
+        fs::write(&temporary_path, serde_json::to_vec_pretty(parameters)?)
+            .map_err(|e| Error::Store(e, temporary_path.clone()))?;
+        fs::rename(&temporary_path, &path).map_err(|e| Error::Store(e, path))
+    }
+
+    /// Record that the task was retired and delete its parameters
+    pub fn retire(&self, task_id: TaskId) -> Result<(), Error> {
+        let (directory, path) = match (&self.directory, self.retired_path(task_id)) {
+            (Some(directory), Some(path)) => (directory, path),
+            _ => return Ok(()),
+        };
+
+        fs::create_dir_all(directory).map_err(|e| Error::Store(e, directory.clone()))?;
+        fs::write(&path, b"").map_err(|e| Error::Store(e, path))?;
+        self.delete(task_id)
+    }
+
+    /// Delete the task's parameters
+    pub fn delete(&self, task_id: TaskId) -> Result<(), Error> {
+        match self.task_path(task_id) {
+            Some(path) => match fs::remove_file(&path) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(Error::Store(e, path)),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check that the parameters a task is being updated to leave unchanged
+/// everything the task's existing reports and accumulators depend on
+pub(crate) fn check_update(current: &Parameters, updated: &Parameters) -> Result<(), Error> {
+    if current.task_id != updated.task_id {
+        return Err(Error::TaskIdMismatch(updated.task_id));
+    }
+    if current.aggregator_endpoints != updated.aggregator_endpoints {
+        return Err(Error::ImmutableParameter("aggregator_endpoints"));
+    }
+    if current.query_type != updated.query_type {
+        return Err(Error::ImmutableParameter("query_type"));
+    }
+    if current.min_batch_duration != updated.min_batch_duration {
+        return Err(Error::ImmutableParameter("min_batch_duration"));
+    }
+    if current.time_precision != updated.time_precision {
+        return Err(Error::ImmutableParameter("time_precision"));
+    }
+    if current.vdaf != updated.vdaf {
+        return Err(Error::ImmutableParameter("vdaf"));
+    }
+    if current.vdaf_verification_parameter != updated.vdaf_verification_parameter {
+        return Err(Error::ImmutableParameter("vdaf_verification_parameter"));
+    }
+    // Administrative credentials are the aggregator's, from parameters.json
+    if current.admin_auth != updated.admin_auth {
+        return Err(Error::ImmutableParameter("admin_auth"));
+    }
+
+    Ok(())
+}
+
+/// An aggregator's state for a single task
+pub(crate) trait Task: Send + 'static {
+    fn parameters(&self) -> &Parameters;
+
+    /// Replace the task's parameters with ones that have passed
+    /// [`check_update`]
+    fn update_parameters(&mut self, parameters: &Parameters);
+
+    /// Delete state that the task's retention policy says is no longer needed
+    fn collect_garbage(&mut self, now: Time);
+}
+
+/// Constructs a task's state from its parameters
+pub(crate) type TaskFactory<T> = Box<dyn Fn(&Parameters) -> Result<T, Error> + Send + Sync>;
+
+/// The tasks an aggregator serves
+pub(crate) struct Tasks<T> {
+    tasks: RwLock<BTreeMap<TaskId, Arc<Mutex<T>>>>,
+    /// Tasks that were retired, whose IDs can't be used again. Only changed
+    /// while holding the lock on `tasks`.
+    retired: Mutex<HashSet<TaskId>>,
+    factory: TaskFactory<T>,
+    store: TaskStore,
+    /// The VDAF every task must use
+    vdaf: VdafLabel,
+    /// Credentials operators must present to administrative endpoints
+    admin_auth: Option<AdminAuth>,
+    /// When each task last had its garbage collected
+    last_garbage_collection: Mutex<HashMap<TaskId, Instant>>,
+}
+
+impl<T: Task> Tasks<T> {
+    /// Serve the task in `parameters` using the state in `task`, along with
+    /// those in the store
+    pub(crate) fn new(
+        parameters: &Parameters,
+        task: T,
+        factory: TaskFactory<T>,
+        store: TaskStore,
+    ) -> Result<Self, Error> {
+        let mut tasks = BTreeMap::new();
+        let retired = store.load_retired()?;
+
+        for stored_parameters in store.load()? {
+            if stored_parameters.vdaf != parameters.vdaf {
+                return Err(Error::UnsupportedVdaf(stored_parameters.vdaf));
+            }
+            let task_id = stored_parameters.task_id;
+            if retired.contains(&task_id) {
+                continue;
+            }
+            // Tasks updated at runtime take precedence over parameters.json,
+            // whose task is only served as configured if it isn't stored
+            tasks.insert(task_id, Arc::new(Mutex::new(factory(&stored_parameters)?)));
+        }
+
+        if !retired.contains(&parameters.task_id) {
+            tasks
+                .entry(parameters.task_id)
+                .or_insert_with(|| Arc::new(Mutex::new(task)));
+        }
+
+        Ok(Self {
+            tasks: RwLock::new(tasks),
+            retired: Mutex::new(retired),
+            factory,
+            store,
+            vdaf: parameters.vdaf.clone(),
+            admin_auth: parameters.admin_auth.clone(),
+            last_garbage_collection: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn admin_auth(&self) -> Option<&AdminAuth> {
+        self.admin_auth.as_ref()
+    }
+
+    /// Check that the task store is reachable, for readiness checks
+    pub(crate) fn check_store(&self) -> Result<(), Error> {
+        self.store.check()
+    }
+
+    /// Look up the state of the task
+    pub(crate) async fn get(&self, task_id: TaskId) -> Result<Arc<Mutex<T>>, Error> {
+        self.tasks
+            .read()
+            .await
+            .get(&task_id)
+            .cloned()
+            .ok_or(Error::UnrecognizedTask(task_id))
+    }
+
+    /// The state of every task, ordered by task ID
+    pub(crate) async fn all(&self) -> Vec<Arc<Mutex<T>>> {
+        self.tasks.read().await.values().cloned().collect()
+    }
+
+    /// Run garbage collection on each task whose retention policy's
+    /// `gc_interval` has elapsed since it last ran
+    pub(crate) async fn collect_garbage(&self) {
+        let now = Instant::now();
+        let mut last_garbage_collection = self.last_garbage_collection.lock().await;
+
+        for task in self.all().await {
+            let mut task = task.lock().await;
+            let (task_id, gc_interval) = match &task.parameters().retention {
+                Some(retention) => (
+                    task.parameters().task_id,
+                    std::time::Duration::from_secs(retention.gc_interval.0),
+                ),
+                None => continue,
+            };
+            if let Some(last) = last_garbage_collection.get(&task_id) {
+                if now.saturating_duration_since(*last) < gc_interval {
+                    continue;
+                }
+            }
+            task.collect_garbage(Time::now());
+            last_garbage_collection.insert(task_id, now);
+        }
+    }
+
+    /// Collect garbage from tasks forever
+    pub(crate) async fn run_garbage_collector(&self) {
+        let mut tick = tokio::time::interval(GARBAGE_COLLECTION_TICK);
+        loop {
+            tick.tick().await;
+            self.collect_garbage().await;
+        }
+    }
+
+    /// Check that a task with the ID could be created
+    async fn check_unused(
+        &self,
+        tasks: &BTreeMap<TaskId, Arc<Mutex<T>>>,
+        task_id: TaskId,
+    ) -> Result<(), Error> {
+        if tasks.contains_key(&task_id) {
+            return Err(Error::TaskExists(task_id));
+        }
+        if self.retired.lock().await.contains(&task_id) {
+            return Err(Error::TaskRetired(task_id));
+        }
+
+        Ok(())
+    }
+
+    async fn create(
+        &self,
+        parameters: &Parameters,
+        push: Option<&HelperPush>,
+    ) -> Result<(), Error> {
+        self.check_unused(&*self.tasks.read().await, parameters.task_id)
+            .await?;
+        if parameters.vdaf != self.vdaf {
+            return Err(Error::UnsupportedVdaf(parameters.vdaf.clone()));
+        }
+        if parameters.admin_auth != self.admin_auth {
+            return Err(Error::AdminAuthMismatch);
+        }
+        let task = (self.factory)(parameters)?;
+
+        // Don't hold up requests for other tasks while waiting for the helper
+        if let Some(push) = push {
+            push.send(Method::POST, parameters, Some(parameters))
+                .await?;
+        }
+
+        let mut tasks = self.tasks.write().await;
+        self.check_unused(&tasks, parameters.task_id).await?;
+        self.store.put(parameters)?;
+        tasks.insert(parameters.task_id, Arc::new(Mutex::new(task)));
+        info!(task_id = %parameters.task_id, "created task");
+
+        Ok(())
+    }
+
+    async fn update(
+        &self,
+        task_id: TaskId,
+        parameters: &Parameters,
+        push: Option<&HelperPush>,
+    ) -> Result<(), Error> {
+        if parameters.task_id != task_id {
+            return Err(Error::TaskIdMismatch(parameters.task_id));
+        }
+        let task = self.get(task_id).await?;
+        let mut task = task.lock().await;
+        check_update(task.parameters(), parameters)?;
+
+        if let Some(push) = push {
+            push.send(Method::PUT, parameters, Some(parameters)).await?;
+        }
+
+        self.store.put(parameters)?;
+        task.update_parameters(parameters);
+        info!(%task_id, "updated task");
+
+        Ok(())
+    }
+
+    async fn retire(&self, task_id: TaskId, push: Option<&HelperPush>) -> Result<(), Error> {
+        // Don't hold up requests for other tasks while waiting for the helper
+        if let Some(push) = push {
+            let parameters = self.get(task_id).await?.lock().await.parameters().clone();
+            push.send(Method::DELETE, &parameters, None).await?;
+        }
+
+        let mut tasks = self.tasks.write().await;
+        if !tasks.contains_key(&task_id) {
+            return Err(Error::UnrecognizedTask(task_id));
+        }
+        self.store.retire(task_id)?;
+        self.retired.lock().await.insert(task_id);
+        tasks.remove(&task_id);
+        self.last_garbage_collection.lock().await.remove(&task_id);
+        info!(%task_id, "retired task");
+
+        Ok(())
+    }
+}
+
+/// Forwards a change to a task to the helper, on the leader's behalf
+struct HelperPush {
+    http_client: Client,
+    /// The `Authorization` header presented to the leader
+    authorization: Option<http::HeaderValue>,
+}
+
+impl HelperPush {
+    async fn send(
+        &self,
+        method: Method,
+        task: &Parameters,
+        body: Option<&Parameters>,
+    ) -> Result<(), Error> {
+        let endpoint = tasks_endpoint(task, method != Method::POST)?;
+        let mut request = self.http_client.request(method, endpoint);
+        if let Some(authorization) = &self.authorization {
+            request = request.header(AUTHORIZATION, authorization.clone());
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let problem_document = response_to_api_problem(response).await;
+            warn!(?problem_document, "helper rejected task change");
+            return match problem_document {
+                Ok(document) => Err(Error::HelperError(Box::new(document))),
+                Err(message) => Err(Error::HelperHttpRequest(status, message)),
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// The helper's endpoint for the task management API, either for all tasks or
+/// for the task itself
+fn tasks_endpoint(task: &Parameters, for_task: bool) -> Result<Url, Error> {
+    let tasks = task
+        .aggregator_endpoint(Role::Helper)?
+        .join("tasks/")
+        .map_err(parameters::Error::from)?;
+    if !for_task {
+        return Ok(tasks);
+    }
+
+    Ok(tasks
+        .join(&task.task_id.to_string())
+        .map_err(parameters::Error::from)?)
+}
+
+/// Options for requests that change tasks
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct TaskChangeOptions {
+    /// Whether the leader should make the same change on the helper
+    #[serde(default)]
+    pub push_to_helper: bool,
+}
+
+/// Response to a request to list tasks
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TaskList {
+    /// Hex encoded IDs of the tasks being served
+    pub task_ids: Vec<String>,
+}
+
+/// A task's parameters as returned by the task management API, leaving out its
+/// secrets: the aggregator auth key, the VDAF verification parameters, bearer
+/// tokens and any private key in the collector's HPKE config
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TaskView {
+    /// Hex encoded task ID
+    pub task_id: String,
+    pub aggregator_endpoints: Vec<Url>,
+    pub collector_config: hpke::Config,
+    pub max_batch_lifetime: u64,
+    pub min_batch_size: u64,
+    pub min_batch_duration: Duration,
+    pub query_type: QueryType,
+    pub time_precision: Option<Duration>,
+    pub vdaf: VdafLabel,
+    pub differential_privacy: Option<DifferentialPrivacy>,
+    pub upload_auth: Option<UploadAuthView>,
+    pub upload_limits: Option<UploadLimits>,
+    pub retention: Option<RetentionPolicy>,
+}
+
+/// How the leader authenticates uploads to a task, without any bearer tokens
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum UploadAuthView {
+    BearerToken { token_count: usize },
+    Ed25519 { public_keys: Vec<Ed25519PublicKey> },
+}
+
+impl From<&Parameters> for TaskView {
+    fn from(parameters: &Parameters) -> Self {
+        let mut collector_config = parameters.collector_config.clone();
+        collector_config.private_key = None;
+
+        Self {
+            task_id: parameters.task_id.to_string(),
+            aggregator_endpoints: parameters.aggregator_endpoints.clone(),
+            collector_config,
+            max_batch_lifetime: parameters.max_batch_lifetime,
+            min_batch_size: parameters.min_batch_size,
+            min_batch_duration: parameters.min_batch_duration,
+            query_type: parameters.query_type,
+            time_precision: parameters.time_precision,
+            vdaf: parameters.vdaf.clone(),
+            differential_privacy: parameters.differential_privacy.clone(),
+            upload_auth: parameters
+                .upload_auth
+                .as_ref()
+                .map(|upload_auth| match upload_auth {
+                    UploadAuth::BearerToken { tokens } => UploadAuthView::BearerToken {
+                        token_count: tokens.len(),
+                    },
+                    UploadAuth::Ed25519 { public_keys } => UploadAuthView::Ed25519 {
+                        public_keys: public_keys.clone(),
+                    },
+                }),
+            upload_limits: parameters.upload_limits.clone(),
+            retention: parameters.retention.clone(),
+        }
+    }
+}
+
+/// Reject requests to administrative endpoints that lack the operator's
+/// credentials
+fn authenticate<T: Task>(tasks: &Tasks<T>, headers: &HeaderMap) -> Result<(), Error> {
+    Ok(tasks
+        .admin_auth()
+        .ok_or(auth::AdminError::Unauthorized)?
+        .authenticate(headers)?)
+}
+
+fn reject(error: Error, endpoint: &'static str) -> Rejection {
+    warp::reject::custom(error.problem_document(None, endpoint))
+}
+
+/// Decide whether to push a change to the helper, and if so, how
+fn helper_push(
+    options: TaskChangeOptions,
+    http_client: &Option<Client>,
+    headers: &HeaderMap,
+) -> Result<Option<HelperPush>, Error> {
+    match (options.push_to_helper, http_client) {
+        (false, _) => Ok(None),
+        (true, None) => Err(Error::NotLeader),
+        (true, Some(http_client)) => Ok(Some(HelperPush {
+            http_client: http_client.clone(),
+            authorization: headers.get(AUTHORIZATION).cloned(),
+        })),
+    }
+}
+
+fn parse_parameters(body: &[u8]) -> Result<Parameters, Error> {
+    Ok(Parameters::from_json_reader(body)?)
+}
+
+fn parse_task_id(task_id: &str) -> Result<TaskId, Error> {
+    task_id.parse().map_err(|_| Error::MalformedTaskId)
+}
+
+/// The task management API. Leaders provide an HTTP client with which to push
+/// changes to the helper.
+pub(crate) fn task_management_endpoints<T: Task>(
+    tasks: Arc<Tasks<T>>,
+    http_client: Option<Client>,
+) -> BoxedFilter<(impl Reply,)> {
+    let list = warp::get()
+        .and(warp::path!("tasks"))
+        .and(warp::header::headers_cloned())
+        .and(with_shared_value(tasks.clone()))
+        .and_then(|headers: HeaderMap, tasks: Arc<Tasks<T>>| async move {
+            authenticate(&tasks, &headers).map_err(|e| reject(e, "tasks"))?;
+
+            let task_ids = tasks
+                .tasks
+                .read()
+                .await
+                .keys()
+                .map(ToString::to_string)
+                .collect();
+
+            Ok(reply::json(&TaskList { task_ids }).into_response()) as Result<_, Rejection>
+        });
+
+    let create = warp::post()
+        .and(warp::path!("tasks"))
+        .and(warp::query::<TaskChangeOptions>())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and(with_shared_value(tasks.clone()))
+        .and(with_shared_value(http_client.clone()))
+        .and_then(
+            |options: TaskChangeOptions,
+             headers: HeaderMap,
+             body: Bytes,
+             tasks: Arc<Tasks<T>>,
+             http_client: Option<Client>| async move {
+                authenticate(&tasks, &headers).map_err(|e| reject(e, "tasks"))?;
+                let parameters = parse_parameters(&body).map_err(|e| reject(e, "tasks"))?;
+                let push =
+                    helper_push(options, &http_client, &headers).map_err(|e| reject(e, "tasks"))?;
+
+                tasks
+                    .create(&parameters, push.as_ref())
+                    .await
+                    .map_err(|e| reject(e, "tasks"))?;
+
+                Ok(reply::with_status(
+                    reply::json(&TaskView::from(&parameters)),
+                    StatusCode::CREATED,
+                )
+                .into_response()) as Result<_, Rejection>
+            },
+        );
+
+    let get = warp::get()
+        .and(warp::path!("tasks" / String))
+        .and(warp::header::headers_cloned())
+        .and(with_shared_value(tasks.clone()))
+        .and_then(
+            |task_id: String, headers: HeaderMap, tasks: Arc<Tasks<T>>| async move {
+                authenticate(&tasks, &headers).map_err(|e| reject(e, "tasks"))?;
+                let task_id = parse_task_id(&task_id).map_err(|e| reject(e, "tasks"))?;
+
+                let task = tasks.get(task_id).await.map_err(|e| reject(e, "tasks"))?;
+                let task_view = TaskView::from(task.lock().await.parameters());
+
+                Ok(reply::json(&task_view).into_response()) as Result<_, Rejection>
+            },
+        );
+
+    let update = warp::put()
+        .and(warp::path!("tasks" / String))
+        .and(warp::query::<TaskChangeOptions>())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and(with_shared_value(tasks.clone()))
+        .and(with_shared_value(http_client.clone()))
+        .and_then(
+            |task_id: String,
+             options: TaskChangeOptions,
+             headers: HeaderMap,
+             body: Bytes,
+             tasks: Arc<Tasks<T>>,
+             http_client: Option<Client>| async move {
+                authenticate(&tasks, &headers).map_err(|e| reject(e, "tasks"))?;
+                let task_id = parse_task_id(&task_id).map_err(|e| reject(e, "tasks"))?;
+                let parameters = parse_parameters(&body).map_err(|e| reject(e, "tasks"))?;
+                let push =
+                    helper_push(options, &http_client, &headers).map_err(|e| reject(e, "tasks"))?;
+
+                tasks
+                    .update(task_id, &parameters, push.as_ref())
+                    .await
+                    .map_err(|e| reject(e, "tasks"))?;
+
+                Ok(reply::json(&TaskView::from(&parameters)).into_response())
+                    as Result<_, Rejection>
+            },
+        );
+
+    let retire = warp::delete()
+        .and(warp::path!("tasks" / String))
+        .and(warp::query::<TaskChangeOptions>())
+        .and(warp::header::headers_cloned())
+        .and(with_shared_value(tasks))
+        .and(with_shared_value(http_client))
+        .and_then(
+            |task_id: String,
+             options: TaskChangeOptions,
+             headers: HeaderMap,
+             tasks: Arc<Tasks<T>>,
+             http_client: Option<Client>| async move {
+                authenticate(&tasks, &headers).map_err(|e| reject(e, "tasks"))?;
+                let task_id = parse_task_id(&task_id).map_err(|e| reject(e, "tasks"))?;
+                let push =
+                    helper_push(options, &http_client, &headers).map_err(|e| reject(e, "tasks"))?;
+
+                tasks
+                    .retire(task_id, push.as_ref())
+                    .await
+                    .map_err(|e| reject(e, "tasks"))?;
+
+                Ok(reply::with_status(reply(), StatusCode::NO_CONTENT).into_response())
+                    as Result<_, Rejection>
+            },
+        );
+
+    list.or(create)
+        .unify()
+        .or(get)
+        .unify()
+        .or(update)
+        .unify()
+        .or(retire)
+        .unify()
+        .with(warp::trace::named("tasks"))
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Duration;
+    use assert_matches::assert_matches;
+
+    fn sample_parameters() -> Parameters {
+        Parameters::from_json_reader(&include_bytes!("../sample-config/parameters.json")[..])
+            .unwrap()
+    }
+
+    #[test]
+    fn task_store() {
+        let directory = std::env::temp_dir().join(format!("ppm-tasks-{}", TaskId::random()));
+        let store = TaskStore::directory(&directory);
+        assert!(store.load().unwrap().is_empty());
+
+        let parameters = sample_parameters();
+        let mut other_parameters = sample_parameters();
+        other_parameters.task_id = TaskId::random();
+        store.put(&parameters).unwrap();
+        store.put(&other_parameters).unwrap();
+
+        let mut loaded = store.load().unwrap();
+        loaded.sort_by_key(|parameters| parameters.task_id);
+        let mut expected = vec![parameters.clone(), other_parameters.clone()];
+        expected.sort_by_key(|parameters| parameters.task_id);
+        assert_eq!(loaded, expected);
+
+        store.delete(other_parameters.task_id).unwrap();
+        // Deleting a task that isn't stored is not an error
+        store.delete(other_parameters.task_id).unwrap();
+        assert_eq!(store.load().unwrap(), vec![parameters.clone()]);
+        assert!(store.load_retired().unwrap().is_empty());
+
+        store.retire(parameters.task_id).unwrap();
+        assert!(store.load().unwrap().is_empty());
+        assert_eq!(
+            store.load_retired().unwrap(),
+            [parameters.task_id].iter().cloned().collect()
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn task_store_check() {
+        TaskStore::in_memory().check().unwrap();
+
+        let directory = std::env::temp_dir().join(format!("ppm-tasks-{}", TaskId::random()));
+        let store = TaskStore::directory(&directory);
+        // The directory is created if it doesn't exist yet
+        store.check().unwrap();
+        assert!(directory.is_dir());
+        fs::remove_dir_all(&directory).unwrap();
+
+        // A file where the directory should be makes the store unreachable
+        fs::write(&directory, b"").unwrap();
+        assert_matches!(store.check(), Err(Error::Store(_, _)));
+        fs::remove_file(directory).unwrap();
+    }
+
+    #[derive(Debug)]
+    struct TestTask(Parameters);
+
+    impl Task for TestTask {
+        fn parameters(&self) -> &Parameters {
+            &self.0
+        }
+
+        fn update_parameters(&mut self, parameters: &Parameters) {
+            self.0 = parameters.clone();
+        }
+
+        fn collect_garbage(&mut self, _: Time) {}
+    }
+
+    fn tasks(parameters: &Parameters, store: &TaskStore) -> Tasks<TestTask> {
+        Tasks::new(
+            parameters,
+            TestTask(parameters.clone()),
+            Box::new(|parameters| Ok(TestTask(parameters.clone()))),
+            store.clone(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retired_tasks() {
+        let directory = std::env::temp_dir().join(format!("ppm-tasks-{}", TaskId::random()));
+        let store = TaskStore::directory(&directory);
+        let parameters = sample_parameters();
+        let mut other_parameters = sample_parameters();
+        other_parameters.task_id = TaskId::random();
+
+        let served = tasks(&parameters, &store);
+        // Tasks carry the aggregator's credentials
+        let mut other_credentials = other_parameters.clone();
+        other_credentials.admin_auth = Some(AdminAuth {
+            tokens: vec!["other token".to_string()],
+        });
+        assert_matches!(
+            served.create(&other_credentials, None).await,
+            Err(Error::AdminAuthMismatch)
+        );
+        served.create(&other_parameters, None).await.unwrap();
+        served.retire(parameters.task_id, None).await.unwrap();
+        served.retire(other_parameters.task_id, None).await.unwrap();
+        for task_id in [parameters.task_id, other_parameters.task_id].iter() {
+            assert_matches!(served.get(*task_id).await, Err(Error::UnrecognizedTask(_)));
+        }
+        assert_matches!(
+            served.create(&other_parameters, None).await,
+            Err(Error::TaskRetired(_))
+        );
+
+        // Neither the task in parameters.json nor the created one come back
+        // after a restart
+        let restarted = tasks(&parameters, &store);
+        assert!(restarted.all().await.is_empty());
+        assert_matches!(
+            restarted.create(&parameters, None).await,
+            Err(Error::TaskRetired(_))
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn task_view_leaves_out_secrets() {
+        let mut parameters = sample_parameters();
+        parameters.collector_config =
+            hpke::ConfigFile::from_json_reader(&include_bytes!("../sample-config/hpke.json")[..])
+                .unwrap()
+                .collector;
+        parameters.upload_auth = Some(UploadAuth::BearerToken {
+            tokens: vec!["upload token".to_string()],
+        });
+        parameters.admin_auth = Some(AdminAuth {
+            tokens: vec!["admin token".to_string()],
+        });
+
+        let task_view = TaskView::from(&parameters);
+        assert!(task_view.collector_config.private_key.is_none());
+        assert_eq!(
+            task_view.upload_auth,
+            Some(UploadAuthView::BearerToken { token_count: 1 })
+        );
+
+        let json = serde_json::to_string(&task_view).unwrap();
+        assert!(!json.contains("private_key"));
+        assert!(!json.contains("upload token"));
+        assert!(!json.contains("admin token"));
+        assert!(!json.contains(&base64::encode(&parameters.aggregator_auth_key)));
+    }
+
+    #[test]
+    fn immutable_parameters() {
+        let current = sample_parameters();
+
+        let mut updated = current.clone();
+        updated.aggregator_auth_key = vec![7; 32];
+        updated.min_batch_size *= 2;
+        check_update(&current, &updated).unwrap();
+
+        let mut updated = current.clone();
+        updated.task_id = TaskId::random();
+        assert_matches!(
+            check_update(&current, &updated),
+            Err(Error::TaskIdMismatch(_))
+        );
+
+        let mut updated = current.clone();
+        updated.min_batch_duration = Duration(current.min_batch_duration.0 + 1);
+        assert_matches!(
+            check_update(&current, &updated),
+            Err(Error::ImmutableParameter("min_batch_duration"))
+        );
+
+        let mut updated = current.clone();
+        updated.admin_auth = Some(AdminAuth {
+            tokens: vec!["rotated token".to_string()],
+        });
+        assert_matches!(
+            check_update(&current, &updated),
+            Err(Error::ImmutableParameter("admin_auth"))
+        );
+    }
+}
@@ -0,0 +1,483 @@
+//! A leader and helper running in-process for tests. Each listens on an
+//! ephemeral port on the loopback interface, so that tests using them can run in
+//! parallel. Requires the `test-util` feature.
+//!
+//! ```no_run
+//! # async fn example() -> color_eyre::Result<()> {
+//! use ppm_prototype::test_util::{sample_parameters, TestAggregators};
+//! use prio::vdaf::prio3::Prio3Aes128Count;
+//!
+//! let aggregators = TestAggregators::new(sample_parameters(), Prio3Aes128Count::new(2)?, ())
+//!     .start()
+//!     .await?;
+//! // Clients and collectors find the aggregators through these parameters
+//! let parameters = aggregators.parameters();
+//! aggregators.shutdown().await
+//! # }
+//! ```
+
+use crate::{
+    aggregate::{Aggregate, AggregateMessage, AggregateResp, Transition},
+    dp::NoisyAggregateShare,
+    helper::serve_helper,
+    hpke,
+    leader::serve_leader,
+    parameters::Parameters,
+    rate_limit::PendingReportLimit,
+    server::{serve, Listener},
+    task::TaskStore,
+    Role,
+};
+use bytes::Bytes;
+use color_eyre::eyre::Result;
+use http::{header::CONTENT_TYPE, HeaderMap, Method, StatusCode};
+use prio::{
+    codec::{Decode, Encode, ParameterizedDecode},
+    vdaf,
+};
+use reqwest::{Client, RequestBuilder};
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    net::{Ipv4Addr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{sync::oneshot, task::JoinHandle};
+use url::Url;
+use warp::{filters::path::FullPath, Filter, Rejection};
+
+/// The parameters in `sample-config/parameters.json`
+pub fn sample_parameters() -> Parameters {
+    Parameters::from_json_reader(Cursor::new(include_bytes!(
+        "../sample-config/parameters.json"
+    )))
+    .unwrap()
+}
+
+/// The HPKE configs in `sample-config/hpke.json`
+pub fn sample_hpke_configs() -> hpke::ConfigFile {
+    hpke::ConfigFile::from_json_reader(Cursor::new(include_bytes!("../sample-config/hpke.json")))
+        .unwrap()
+}
+
+/// Configures a leader and helper to be started together
+pub struct TestAggregators<A: vdaf::Aggregator> {
+    parameters: Parameters,
+    vdaf: A,
+    aggregation_parameter: A::AggregationParam,
+    verify_parameters: Option<(A::VerifyParam, A::VerifyParam)>,
+    hpke_configs: hpke::ConfigFile,
+    faults: Option<FaultScript>,
+    max_pending_reports: Option<u64>,
+}
+
+impl<A> TestAggregators<A>
+where
+    A: vdaf::Aggregator + 'static + Send + Sync,
+    A::VerifyParam: Encode + ParameterizedDecode<A> + Send + Sync,
+    A::AggregationParam: Send + Sync,
+    A::PrepareStep: Send + Sync,
+    A::AggregateShare: NoisyAggregateShare + Send + Sync,
+    A::PrepareMessage: Send + Sync,
+    A::OutputShare: Send + Sync,
+{
+    /// Aggregators serving the task in `parameters` with `vdaf`. Their
+    /// aggregator endpoints are replaced once they are listening. Unless
+    /// overridden, the VDAF verification parameters are those in `parameters`
+    /// and the HPKE configs are those in `sample-config/hpke.json`.
+    pub fn new(
+        parameters: Parameters,
+        vdaf: A,
+        aggregation_parameter: A::AggregationParam,
+    ) -> Self {
+        Self {
+            parameters,
+            vdaf,
+            aggregation_parameter,
+            verify_parameters: None,
+            hpke_configs: sample_hpke_configs(),
+            faults: None,
+            max_pending_reports: None,
+        }
+    }
+
+    /// Use these verification parameters rather than those in the task's
+    /// parameters
+    pub fn with_verify_parameters(
+        mut self,
+        leader: A::VerifyParam,
+        helper: A::VerifyParam,
+    ) -> Self {
+        self.verify_parameters = Some((leader, helper));
+        self
+    }
+
+    /// Use these HPKE configs rather than those in `sample-config/hpke.json`
+    pub fn with_hpke_configs(mut self, hpke_configs: hpke::ConfigFile) -> Self {
+        self.hpke_configs = hpke_configs;
+        self
+    }
+
+    /// Cap the reports the leader holds awaiting aggregation across all of its
+    /// tasks
+    pub fn with_max_pending_reports(mut self, max_pending_reports: u64) -> Self {
+        self.max_pending_reports = Some(max_pending_reports);
+        self
+    }
+
+    /// Put a proxy between the leader and the helper that injects the scripted
+    /// faults into the helper's responses to aggregate requests. The leader
+    /// and clients reach the helper through the proxy, while
+    /// [`RunningAggregators::helper`] is the helper itself.
+    pub fn with_faults(mut self, faults: FaultScript) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Start the leader and helper, which run until
+    /// [`RunningAggregators::shutdown`] is called or the `RunningAggregators`
+    /// is dropped
+    pub async fn start(self) -> Result<RunningAggregators> {
+        let (leader_verify_parameter, helper_verify_parameter) = match self.verify_parameters {
+            Some(verify_parameters) => verify_parameters,
+            None => (
+                self.parameters
+                    .decode_vdaf_verification_parameter(Role::Leader, &self.vdaf)?,
+                self.parameters
+                    .decode_vdaf_verification_parameter(Role::Helper, &self.vdaf)?,
+            ),
+        };
+
+        // Bind every listener up front, since each aggregator's parameters
+        // must name both endpoints
+        let leader_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let helper_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let helper_url = Url::parse(&format!("http://{}/", helper_listener.local_addr()?))?;
+        let faulty_helper = match self.faults {
+            Some(faults) => {
+                let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+                let url = Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+                let (listener, shutdown) = AggregatorHandle::listener(listener);
+                let join_handle =
+                    tokio::spawn(serve_faulty_helper(helper_url.clone(), faults, listener));
+                Some(AggregatorHandle {
+                    url,
+                    shutdown,
+                    join_handle,
+                })
+            }
+            None => None,
+        };
+        let mut parameters = self.parameters;
+        parameters.aggregator_endpoints = vec![
+            Url::parse(&format!("http://{}/", leader_listener.local_addr()?))?,
+            faulty_helper
+                .as_ref()
+                .map_or_else(|| helper_url.clone(), |handle| handle.url.clone()),
+        ];
+
+        let leader = {
+            let (listener, shutdown) = AggregatorHandle::listener(leader_listener);
+            let task_parameters = parameters.clone();
+            let vdaf = self.vdaf.clone();
+            let aggregation_parameter = self.aggregation_parameter.clone();
+            let hpke_config = self.hpke_configs.leader.clone();
+            let pending_report_limit = PendingReportLimit::new(self.max_pending_reports);
+            let join_handle = tokio::spawn(async move {
+                serve_leader(
+                    &task_parameters,
+                    &vdaf,
+                    &leader_verify_parameter,
+                    &aggregation_parameter,
+                    &hpke_config,
+                    TaskStore::in_memory(),
+                    pending_report_limit,
+                    listener,
+                )
+                .await
+            });
+            AggregatorHandle {
+                url: parameters.aggregator_endpoints[0].clone(),
+                shutdown,
+                join_handle,
+            }
+        };
+
+        let helper = {
+            let (listener, shutdown) = AggregatorHandle::listener(helper_listener);
+            let task_parameters = parameters.clone();
+            let vdaf = self.vdaf;
+            let aggregation_parameter = self.aggregation_parameter;
+            let hpke_config = self.hpke_configs.helper.clone();
+            let join_handle = tokio::spawn(async move {
+                serve_helper(
+                    &task_parameters,
+                    &vdaf,
+                    &helper_verify_parameter,
+                    &aggregation_parameter,
+                    &hpke_config,
+                    TaskStore::in_memory(),
+                    listener,
+                )
+                .await
+            });
+            AggregatorHandle {
+                url: helper_url,
+                shutdown,
+                join_handle,
+            }
+        };
+
+        Ok(RunningAggregators {
+            parameters,
+            hpke_configs: self.hpke_configs,
+            leader,
+            helper,
+            faulty_helper,
+        })
+    }
+}
+
+/// A leader and helper started by [`TestAggregators::start`]
+#[derive(Debug)]
+pub struct RunningAggregators {
+    parameters: Parameters,
+    hpke_configs: hpke::ConfigFile,
+    leader: AggregatorHandle,
+    helper: AggregatorHandle,
+    faulty_helper: Option<AggregatorHandle>,
+}
+
+impl RunningAggregators {
+    /// The task's parameters, with the aggregators' actual endpoints
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// The HPKE configs the aggregators and collector use
+    pub fn hpke_configs(&self) -> &hpke::ConfigFile {
+        &self.hpke_configs
+    }
+
+    pub fn leader(&self) -> &AggregatorHandle {
+        &self.leader
+    }
+
+    /// The helper itself, bypassing any proxy injecting faults
+    pub fn helper(&self) -> &AggregatorHandle {
+        &self.helper
+    }
+
+    /// The proxy injecting faults into the helper's responses, if
+    /// [`TestAggregators::with_faults`] was used
+    pub fn faulty_helper(&self) -> Option<&AggregatorHandle> {
+        self.faulty_helper.as_ref()
+    }
+
+    /// Stop both aggregators, returning the first error any of them
+    /// encountered
+    pub async fn shutdown(self) -> Result<()> {
+        let leader = self.leader.shutdown().await;
+        let helper = self.helper.shutdown().await;
+        let faulty_helper = match self.faulty_helper {
+            Some(faulty_helper) => faulty_helper.shutdown().await,
+            None => Ok(()),
+        };
+        leader.and(helper).and(faulty_helper)
+    }
+}
+
+/// A running aggregator
+#[derive(Debug)]
+pub struct AggregatorHandle {
+    url: Url,
+    shutdown: oneshot::Sender<()>,
+    join_handle: JoinHandle<Result<()>>,
+}
+
+impl AggregatorHandle {
+    /// A listener that stops accepting connections once the returned sender is
+    /// used or dropped
+    fn listener(listener: TcpListener) -> (Listener, oneshot::Sender<()>) {
+        let (sender, receiver) = oneshot::channel();
+        let listener = Listener::new(listener, async move {
+            let _ = receiver.await;
+        });
+        (listener, sender)
+    }
+
+    /// The aggregator's endpoint, e.g. `http://127.0.0.1:12345/`
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Stop the aggregator once requests in progress complete, returning any
+    /// error it encountered
+    pub async fn shutdown(self) -> Result<()> {
+        // The aggregator may have already exited, dropping the receiver
+        let _ = self.shutdown.send(());
+        self.join_handle.await?
+    }
+}
+
+/// A fault injected into the helper's response to an aggregate request by the
+/// proxy that [`TestAggregators::with_faults`] puts in front of the helper
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// Reverse the order of the transitions
+    Reorder,
+    /// Leave out the transition at this index
+    Drop(usize),
+    /// Repeat the transition at this index
+    Duplicate(usize),
+    /// Change the nonce of the transition at this index
+    WrongNonce(usize),
+    /// Claim that the helper finished the report at this index
+    Finish(usize),
+    /// Cut the encoded response short, so that it doesn't decode
+    Malformed,
+    /// Wait this long before forwarding the request
+    Delay(std::time::Duration),
+    /// Respond with this status without forwarding the request to the helper,
+    /// which never sees it
+    Status(StatusCode),
+}
+
+impl Fault {
+    /// Inject the fault into the helper's response
+    fn apply(&self, response: &mut AggregateResp) {
+        let transitions = &mut response.transitions;
+        match *self {
+            Self::Reorder => transitions.reverse(),
+            Self::Drop(index) => {
+                transitions.remove(index);
+            }
+            Self::Duplicate(index) => transitions.insert(index, transitions[index].clone()),
+            Self::WrongNonce(index) => {
+                let nonce = &mut transitions[index].nonce;
+                nonce.rand = nonce.rand.wrapping_add(1);
+            }
+            Self::Finish(index) => transitions[index].transition = Transition::Finished,
+            Self::Malformed | Self::Delay(_) | Self::Status(_) => {}
+        }
+    }
+}
+
+/// Faults to inject into the helper's responses to successive aggregate
+/// requests
+#[derive(Clone, Debug, Default)]
+pub struct FaultScript {
+    faults: HashMap<usize, Vec<Fault>>,
+}
+
+impl FaultScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject `fault` into the handling of the `request`th aggregate request
+    /// the leader sends, counting from zero. Faults injected into the same
+    /// request are applied in order.
+    pub fn inject(mut self, request: usize, fault: Fault) -> Self {
+        self.faults.entry(request).or_default().push(fault);
+        self
+    }
+}
+
+/// Forward requests to the helper at `helper`, injecting the scripted faults
+/// into the handling of aggregate requests
+async fn serve_faulty_helper(helper: Url, script: FaultScript, listener: Listener) -> Result<()> {
+    let http_client = Client::new();
+    let script = Arc::new(script);
+    let aggregate_requests = Arc::new(AtomicUsize::new(0));
+
+    let routes = warp::method()
+        .and(warp::path::full())
+        .and(
+            warp::query::raw()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and_then(
+            move |method: Method,
+                  path: FullPath,
+                  query: Option<String>,
+                  headers: HeaderMap,
+                  body: Bytes| {
+                let faults = if method == Method::POST && path.as_str() == "/aggregate" {
+                    let request = aggregate_requests.fetch_add(1, Ordering::SeqCst);
+                    script.faults.get(&request).cloned().unwrap_or_default()
+                } else {
+                    vec![]
+                };
+                let mut url = helper.join(path.as_str().trim_start_matches('/')).unwrap();
+                url.set_query(query.as_deref());
+                let request = http_client.request(method, url).headers(headers).body(body);
+
+                async move {
+                    Ok(forward_with_faults(request, &faults)
+                        .await
+                        .unwrap_or_else(|error| {
+                            http::Response::builder()
+                                .status(StatusCode::BAD_GATEWAY)
+                                .body(error.to_string().into_bytes())
+                                .unwrap()
+                        })) as Result<_, Rejection>
+                }
+            },
+        );
+
+    serve(warp::service(routes), listener).await?;
+    Ok(())
+}
+
+async fn forward_with_faults(
+    request: RequestBuilder,
+    faults: &[Fault],
+) -> Result<http::Response<Vec<u8>>> {
+    for fault in faults {
+        match fault {
+            Fault::Delay(delay) => tokio::time::sleep(*delay).await,
+            Fault::Status(status) => {
+                return Ok(http::Response::builder().status(*status).body(vec![])?)
+            }
+            _ => {}
+        }
+    }
+
+    let helper_response = request.send().await?;
+    let mut response = http::Response::builder().status(helper_response.status());
+    if let Some(content_type) = helper_response.headers().get(CONTENT_TYPE) {
+        response = response.header(CONTENT_TYPE, content_type);
+    }
+    let mut body = helper_response.bytes().await?.to_vec();
+
+    if !faults.is_empty() {
+        // Error responses from the helper are passed on untouched
+        if let Ok(AggregateMessage {
+            aggregate: Aggregate::Response(mut aggregate_response),
+            tag,
+        }) = AggregateMessage::get_decoded(&body)
+        {
+            for fault in faults {
+                fault.apply(&mut aggregate_response);
+            }
+            body = AggregateMessage {
+                aggregate: Aggregate::Response(aggregate_response),
+                tag,
+            }
+            .get_encoded();
+        }
+        if faults.iter().any(|fault| matches!(fault, Fault::Malformed)) {
+            body.truncate(body.len() / 2);
+        }
+    }
+
+    Ok(response.body(body)?)
+}
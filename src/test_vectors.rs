@@ -0,0 +1,435 @@
+//! Test vectors pinning the encoding of protocol messages and the strings
+//! mixed into HPKE encryption, so that changes to either, which would break
+//! interoperation with other PPM implementations, don't go unnoticed.
+//!
+//! The vectors checked in under `test-vectors` are produced by the
+//! `generate-test-vectors` binary from the messages constructed here. Tests
+//! check that this module still produces exactly the checked-in vectors and
+//! that each checked-in encoding decodes and re-encodes to the same bytes.
+
+use crate::{
+    aggregate::{
+        Aggregate, AggregateInitReq, AggregateMessage, AggregateReq, AggregateResp,
+        AggregateShareReq, PartialBatchSelector, ReportShare, Transition, TransitionError,
+        TransitionMessage,
+    },
+    collect::{CollectRequest, CollectResponse, Query},
+    hpke::{
+        AuthenticatedEncryptionWithAssociatedData, Ciphertext, Config, ConfigId,
+        KeyDerivationFunction, KeyEncapsulationMechanism, Label, PublicKey,
+    },
+    parameters::TaskId,
+    report::{DeviceClass, Extension, ExtensionType, Report},
+    BatchId, BatchSelector, Duration, Interval, Nonce, Role, Time,
+};
+use prio::{
+    codec::{CodecError, Decode, Encode},
+    vdaf::prio3::Prio3Aes128Count,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Name of the file containing [`MessageVector`]s
+pub const MESSAGES_FILE: &str = "messages.json";
+/// Name of the file containing [`HpkeVectors`]
+pub const HPKE_FILE: &str = "hpke.json";
+
+/// The encoding of a protocol message
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MessageVector {
+    /// Name of the message's type, as in [`reencode_message`]
+    pub message: String,
+    /// What is being encoded
+    pub description: String,
+    #[serde(with = "hex")]
+    pub encoding: Vec<u8>,
+}
+
+/// The application info string with which an HPKE context is set up
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ApplicationInfoVector {
+    #[serde(with = "hex")]
+    pub task_id: Vec<u8>,
+    pub label: String,
+    pub sender_role: String,
+    pub recipient_role: String,
+    #[serde(with = "hex")]
+    pub application_info: Vec<u8>,
+}
+
+/// The associated data with which a message is sealed
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct AssociatedDataVector {
+    /// What is being sealed, and the data it is bound to
+    pub description: String,
+    #[serde(with = "hex")]
+    pub associated_data: Vec<u8>,
+}
+
+/// Vectors for the inputs to HPKE encryption besides the plaintext
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct HpkeVectors {
+    pub application_info: Vec<ApplicationInfoVector>,
+    pub associated_data: Vec<AssociatedDataVector>,
+}
+
+fn task_id() -> TaskId {
+    TaskId::try_from((0..32).collect::<Vec<u8>>()).unwrap()
+}
+
+fn batch_id() -> BatchId {
+    BatchId([0xbb; 32])
+}
+
+fn nonce() -> Nonce {
+    Nonce {
+        time: Time(1631907500),
+        rand: 0x0102030405060708,
+    }
+}
+
+fn interval() -> Interval {
+    Interval {
+        start: Time(1631907500),
+        duration: Duration(100),
+    }
+}
+
+fn ciphertext(config_id: u8) -> Ciphertext {
+    Ciphertext {
+        config_id: ConfigId(config_id),
+        encapsulated_context: vec![0x0e; 32],
+        payload: vec![0x0f; 16],
+    }
+}
+
+fn extensions() -> Vec<Extension> {
+    vec![
+        Extension::from_typed(&DeviceClass("mobile".to_string())),
+        Extension::new(ExtensionType::from(0x8abc), vec![1, 2, 3]),
+    ]
+}
+
+fn transition_messages() -> Vec<TransitionMessage> {
+    vec![
+        TransitionMessage {
+            nonce: nonce(),
+            transition: Transition::Continued {
+                payload: vec![0xcc; 8],
+            },
+        },
+        TransitionMessage {
+            nonce: Nonce {
+                time: Time(1631907501),
+                rand: 2,
+            },
+            transition: Transition::Finished,
+        },
+        TransitionMessage {
+            nonce: Nonce {
+                time: Time(1631907502),
+                rand: 3,
+            },
+            transition: Transition::Failed {
+                error: TransitionError::VdafPrepError,
+            },
+        },
+    ]
+}
+
+fn aggregate_init_req() -> AggregateInitReq {
+    AggregateInitReq {
+        task_id: task_id(),
+        partial_batch_selector: PartialBatchSelector::FixedSize(batch_id()),
+        aggregation_parameter: vec![],
+        report_shares: vec![ReportShare {
+            nonce: nonce(),
+            extensions: extensions(),
+            encrypted_input_share: ciphertext(2),
+        }],
+    }
+}
+
+fn aggregate_req() -> AggregateReq {
+    AggregateReq {
+        task_id: task_id(),
+        helper_state: vec![0xaa; 4],
+        transitions: transition_messages(),
+    }
+}
+
+fn aggregate_resp() -> AggregateResp {
+    AggregateResp {
+        helper_state: vec![0xaa; 4],
+        transitions: transition_messages(),
+    }
+}
+
+fn aggregate_share_req() -> AggregateShareReq {
+    AggregateShareReq {
+        task_id: task_id(),
+        batch_selector: BatchSelector::TimeInterval(interval()),
+    }
+}
+
+fn vector<T: Encode>(message: &str, description: &str, value: &T) -> MessageVector {
+    MessageVector {
+        message: message.to_string(),
+        description: description.to_string(),
+        encoding: value.get_encoded(),
+    }
+}
+
+/// Encodings of examples of every protocol message
+pub fn message_vectors() -> Vec<MessageVector> {
+    let aggregate_message = |aggregate| AggregateMessage {
+        aggregate,
+        tag: [0x7a; 32],
+    };
+
+    vec![
+        vector("Time", "time", &Time(1631907500)),
+        vector("Duration", "duration", &Duration(100)),
+        vector("Nonce", "nonce", &nonce()),
+        vector("Interval", "interval", &interval()),
+        vector("BatchId", "batch ID", &batch_id()),
+        vector(
+            "BatchSelector",
+            "time interval batch",
+            &BatchSelector::TimeInterval(interval()),
+        ),
+        vector(
+            "BatchSelector",
+            "fixed size batch",
+            &BatchSelector::FixedSize(batch_id()),
+        ),
+        vector("TaskId", "task ID", &task_id()),
+        vector("Ciphertext", "HPKE ciphertext", &ciphertext(1)),
+        vector(
+            "Config",
+            "HPKE config",
+            &Config {
+                id: ConfigId(7),
+                kem_id: KeyEncapsulationMechanism::X25519HkdfSha256,
+                kdf_id: KeyDerivationFunction::HkdfSha256,
+                aead_id: AuthenticatedEncryptionWithAssociatedData::ChaCha20Poly1305,
+                public_key: PublicKey::new(vec![0x9b; 32]),
+                private_key: None,
+            },
+        ),
+        vector("Extension", "device class extension", &extensions()[0]),
+        vector("Extension", "unknown critical extension", &extensions()[1]),
+        vector(
+            "DeviceClass",
+            "device class",
+            &DeviceClass("mobile".to_string()),
+        ),
+        vector(
+            "Report",
+            "report with extensions",
+            &Report {
+                task_id: task_id(),
+                nonce: nonce(),
+                extensions: extensions(),
+                encrypted_input_shares: vec![ciphertext(1), ciphertext(2)],
+            },
+        ),
+        vector(
+            "ReportShare",
+            "report share",
+            &aggregate_init_req().report_shares[0],
+        ),
+        vector(
+            "Transition",
+            "continued",
+            &transition_messages()[0].transition,
+        ),
+        vector(
+            "Transition",
+            "finished",
+            &transition_messages()[1].transition,
+        ),
+        vector("Transition", "failed", &transition_messages()[2].transition),
+        vector(
+            "TransitionMessage",
+            "transition message",
+            &transition_messages()[0],
+        ),
+        vector(
+            "PartialBatchSelector",
+            "time interval",
+            &PartialBatchSelector::TimeInterval,
+        ),
+        vector(
+            "PartialBatchSelector",
+            "fixed size",
+            &PartialBatchSelector::FixedSize(batch_id()),
+        ),
+        vector(
+            "AggregateInitReq",
+            "aggregate initialization request",
+            &aggregate_init_req(),
+        ),
+        vector("AggregateReq", "aggregate request", &aggregate_req()),
+        vector("AggregateResp", "aggregate response", &aggregate_resp()),
+        vector(
+            "AggregateShareReq",
+            "aggregate share request",
+            &aggregate_share_req(),
+        ),
+        vector(
+            "Aggregate",
+            "aggregate share response",
+            &Aggregate::ShareResponse(ciphertext(1)),
+        ),
+        vector(
+            "AggregateMessage",
+            "aggregate initialization request",
+            &aggregate_message(Aggregate::Initialize(aggregate_init_req())),
+        ),
+        vector(
+            "AggregateMessage",
+            "aggregate request",
+            &aggregate_message(Aggregate::Request(aggregate_req())),
+        ),
+        vector(
+            "AggregateMessage",
+            "aggregate response",
+            &aggregate_message(Aggregate::Response(aggregate_resp())),
+        ),
+        vector(
+            "AggregateMessage",
+            "aggregate share request",
+            &aggregate_message(Aggregate::ShareRequest(aggregate_share_req())),
+        ),
+        vector(
+            "AggregateMessage",
+            "aggregate share response",
+            &aggregate_message(Aggregate::ShareResponse(ciphertext(1))),
+        ),
+        vector(
+            "Query",
+            "time interval query",
+            &Query::TimeInterval {
+                batch_interval: interval(),
+            },
+        ),
+        vector(
+            "Query",
+            "fixed size query by batch ID",
+            &Query::FixedSize {
+                batch_id: Some(batch_id()),
+            },
+        ),
+        vector(
+            "Query",
+            "fixed size query for current batch",
+            &Query::FixedSize { batch_id: None },
+        ),
+        vector(
+            "CollectRequest",
+            "collect request with empty aggregation parameter",
+            &CollectRequest::<Prio3Aes128Count> {
+                task_id: task_id(),
+                query: Query::TimeInterval {
+                    batch_interval: interval(),
+                },
+                aggregation_parameter: (),
+            },
+        ),
+        vector(
+            "CollectResponse",
+            "collect response",
+            &CollectResponse {
+                batch_selector: BatchSelector::FixedSize(batch_id()),
+                report_count: 100,
+                encrypted_agg_shares: vec![ciphertext(1), ciphertext(2)],
+            },
+        ),
+    ]
+}
+
+fn reencode<T: Decode + Encode>(encoding: &[u8]) -> Result<Vec<u8>, CodecError> {
+    Ok(T::get_decoded(encoding)?.get_encoded())
+}
+
+/// Decode `encoding` as the named message type and encode it again
+pub fn reencode_message(message: &str, encoding: &[u8]) -> Result<Vec<u8>, CodecError> {
+    match message {
+        "Time" => reencode::<Time>(encoding),
+        "Duration" => reencode::<Duration>(encoding),
+        "Nonce" => reencode::<Nonce>(encoding),
+        "Interval" => reencode::<Interval>(encoding),
+        "BatchId" => reencode::<BatchId>(encoding),
+        "BatchSelector" => reencode::<BatchSelector>(encoding),
+        "TaskId" => reencode::<TaskId>(encoding),
+        "Ciphertext" => reencode::<Ciphertext>(encoding),
+        "Config" => reencode::<Config>(encoding),
+        "Extension" => reencode::<Extension>(encoding),
+        "DeviceClass" => reencode::<DeviceClass>(encoding),
+        "Report" => reencode::<Report>(encoding),
+        "ReportShare" => reencode::<ReportShare>(encoding),
+        "Transition" => reencode::<Transition>(encoding),
+        "TransitionMessage" => reencode::<TransitionMessage>(encoding),
+        "PartialBatchSelector" => reencode::<PartialBatchSelector>(encoding),
+        "AggregateInitReq" => reencode::<AggregateInitReq>(encoding),
+        "AggregateReq" => reencode::<AggregateReq>(encoding),
+        "AggregateResp" => reencode::<AggregateResp>(encoding),
+        "AggregateShareReq" => reencode::<AggregateShareReq>(encoding),
+        "Aggregate" => reencode::<Aggregate>(encoding),
+        "AggregateMessage" => reencode::<AggregateMessage>(encoding),
+        "Query" => reencode::<Query>(encoding),
+        "CollectRequest" => reencode::<CollectRequest<Prio3Aes128Count>>(encoding),
+        "CollectResponse" => reencode::<CollectResponse>(encoding),
+        _ => Err(CodecError::UnexpectedValue),
+    }
+}
+
+fn application_info_vector(
+    label: Label,
+    sender_role: Role,
+    recipient_role: Role,
+) -> ApplicationInfoVector {
+    ApplicationInfoVector {
+        task_id: task_id().as_bytes().to_vec(),
+        label: String::from_utf8_lossy(label.as_bytes()).into_owned(),
+        sender_role: sender_role.name().to_string(),
+        recipient_role: recipient_role.name().to_string(),
+        application_info: Config::application_info(&task_id(), label, sender_role, recipient_role),
+    }
+}
+
+/// Application info strings and associated data for every message that is
+/// sealed with HPKE
+pub fn hpke_vectors() -> HpkeVectors {
+    HpkeVectors {
+        application_info: vec![
+            application_info_vector(Label::InputShare, Role::Client, Role::Leader),
+            application_info_vector(Label::InputShare, Role::Client, Role::Helper),
+            application_info_vector(Label::AggregateShare, Role::Leader, Role::Collector),
+            application_info_vector(Label::AggregateShare, Role::Helper, Role::Collector),
+        ],
+        associated_data: vec![
+            AssociatedDataVector {
+                description: "input share for a report without extensions".to_string(),
+                associated_data: Report::associated_data(nonce(), &[]),
+            },
+            AssociatedDataVector {
+                description: "input share for a report with extensions".to_string(),
+                associated_data: Report::associated_data(nonce(), &extensions()),
+            },
+            AssociatedDataVector {
+                description: "interval".to_string(),
+                associated_data: interval().associated_data(),
+            },
+            AssociatedDataVector {
+                description: "aggregate share for a time interval batch".to_string(),
+                associated_data: BatchSelector::TimeInterval(interval()).associated_data(),
+            },
+            AssociatedDataVector {
+                description: "aggregate share for a fixed size batch".to_string(),
+                associated_data: BatchSelector::FixedSize(batch_id()).associated_data(),
+            },
+        ],
+    }
+}
@@ -1,24 +1,274 @@
+//! Configuration and installation of the tracing subscriber used by all the
+//! binaries.
+//!
+//! The subscriber is configured by `trace.json` in the config directory, if it
+//! exists, and then by environment variables, which take precedence:
+//!
+//!   - `PPM_LOG_FORMAT`: one of `pretty`, `full`, `compact` or `json`
+//!   - `RUST_LOG`: filter directives, as described at
+//!     https://docs.rs/tracing-subscriber/0.2.20/tracing_subscriber/filter/struct.EnvFilter.html
+//!   - `PPM_LOG_FILE`: path of a file to which logs are appended instead of
+//!     being written to stdout
+//!   - `PPM_OTLP_ENDPOINT`: address of an OpenTelemetry collector to which
+//!     spans are exported over OTLP, e.g. `http://localhost:4317`
+
+use crate::config_path;
+use opentelemetry::{
+    sdk::{trace, Resource},
+    KeyValue,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind},
+    path::PathBuf,
+    str::FromStr,
+};
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
 use tracing_error::ErrorLayer;
-use tracing_subscriber::{fmt, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::{
+    filter::ParseError,
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    EnvFilter, Layer, Registry,
+};
+
+/// Environment variable selecting the log format
+pub const LOG_FORMAT_ENV: &str = "PPM_LOG_FORMAT";
+/// Environment variable containing filter directives
+pub const LOG_FILTER_ENV: &str = "RUST_LOG";
+/// Environment variable naming a file to write logs to
+pub const LOG_FILE_ENV: &str = "PPM_LOG_FILE";
+/// Environment variable containing the address of an OTLP collector
+pub const OTLP_ENDPOINT_ENV: &str = "PPM_OTLP_ENDPOINT";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown log format {0}")]
+    UnknownFormat(String),
+    #[error("invalid log filter: {0}")]
+    Filter(#[from] ParseError),
+    #[error("file error: {1}")]
+    File(#[source] io::Error, PathBuf),
+    #[error("JSON parse error")]
+    JsonParse(#[from] serde_json::Error),
+    #[error("failed to install OTLP exporter: {0}")]
+    Otlp(#[from] opentelemetry::trace::TraceError),
+    #[error("failed to install subscriber: {0}")]
+    Install(#[from] tracing::subscriber::SetGlobalDefaultError),
+}
+
+/// Format in which events are logged
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Multi-line, human readable output
+    #[default]
+    Pretty,
+    /// Single line output including the fields of enclosing spans
+    Full,
+    /// Single line output, omitting the fields of enclosing spans
+    Compact,
+    /// Newline delimited JSON objects
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "full" => Ok(Self::Full),
+            "compact" => Ok(Self::Compact),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// Configuration of the tracing subscriber
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TraceConfiguration {
+    /// Format of log output
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Filter directives selecting which events and spans are logged. If
+    /// unset, only errors are logged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    /// File to which logs are appended. If unset, logs go to stdout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
+    /// Address of an OpenTelemetry collector to which spans are exported over
+    /// OTLP. If unset, spans are not exported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+}
+
+impl TraceConfiguration {
+    /// Load the configuration from `trace.json` in the config directory, if it
+    /// exists, applying any overrides from environment variables.
+    pub fn load() -> Result<Self, Error> {
+        let path = config_path().join("trace.json");
+        let config = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(Error::File(e, path)),
+        };
+
+        config.with_env_overrides()
+    }
+
+    /// Apply overrides from environment variables to the configuration
+    pub fn with_env_overrides(mut self) -> Result<Self, Error> {
+        if let Ok(format) = env::var(LOG_FORMAT_ENV) {
+            self.format = format.parse()?;
+        }
+        if let Ok(filter) = env::var(LOG_FILTER_ENV) {
+            self.filter = Some(filter);
+        }
+        if let Ok(log_file) = env::var(LOG_FILE_ENV) {
+            self.log_file = Some(PathBuf::from(log_file));
+        }
+        if let Ok(otlp_endpoint) = env::var(OTLP_ENDPOINT_ENV) {
+            self.otlp_endpoint = Some(otlp_endpoint);
+        }
+
+        Ok(self)
+    }
+}
 
-/// Configures and installs a tracing subscriber
-pub fn install_subscriber() {
-    // Configure a tracing subscriber. The crate emits events using `info!`,
-    // `err!`, etc. macros from crate `tracing`.
-    let fmt_layer = fmt::layer()
+/// Keeps the subscriber's background workers running. Dropping it flushes
+/// buffered logs and exported spans, so it should be held until the program
+/// exits.
+#[must_use]
+#[derive(Debug)]
+pub struct TraceGuard {
+    _log_writer: WorkerGuard,
+    otlp: bool,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        if self.otlp {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+fn fmt_layer<S>(
+    format: LogFormat,
+    writer: BoxMakeWriter,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span> + Send + Sync,
+{
+    let layer = fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi)
         .with_thread_ids(true)
-        // TODO(timg): take an argument for pretty vs. full vs. compact vs. JSON
-        // output
-        .pretty()
         .with_level(true)
         .with_target(true);
 
+    match format {
+        LogFormat::Pretty => Box::new(layer.pretty()),
+        LogFormat::Full => Box::new(layer),
+        LogFormat::Compact => Box::new(layer.compact()),
+        LogFormat::Json => Box::new(layer.json()),
+    }
+}
+
+/// Configures and installs a tracing subscriber. Exporting spans over OTLP
+/// requires a Tokio runtime.
+pub fn install_subscriber(config: &TraceConfiguration) -> Result<TraceGuard, Error> {
+    // The crate emits events using `info!`, `err!`, etc. macros from crate
+    // `tracing`.
+    let filter = match &config.filter {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => EnvFilter::default(),
+    };
+
+    let (writer, log_writer_guard, ansi) = match &config.log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| Error::File(e, path.clone()))?;
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            (BoxMakeWriter::new(writer), guard, false)
+        }
+        None => {
+            let (writer, guard) = tracing_appender::non_blocking(io::stdout());
+            (BoxMakeWriter::new(writer), guard, true)
+        }
+    };
+
+    let otlp_layer = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .with_endpoint(endpoint)
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", "ppm-prototype"),
+                ])))
+                .with_tonic()
+                .install_batch(opentelemetry::runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
     let subscriber = Registry::default()
-        .with(fmt_layer)
-        // Configure filters with RUST_LOG env var. Format discussed at
-        // https://docs.rs/tracing-subscriber/0.2.20/tracing_subscriber/filter/struct.EnvFilter.html
-        .with(EnvFilter::from_default_env())
+        .with(filter)
+        .with(fmt_layer(config.format, writer, ansi))
+        .with(otlp_layer)
         .with(ErrorLayer::default());
 
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    Ok(TraceGuard {
+        _log_writer: log_writer_guard,
+        otlp: config.otlp_endpoint.is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_format() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("compact".parse::<LogFormat>().unwrap(), LogFormat::Compact);
+        assert!(matches!(
+            "yaml".parse::<LogFormat>(),
+            Err(Error::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn configuration_json() {
+        let config: TraceConfiguration = serde_json::from_str(
+            r#"{ "format": "json", "filter": "ppm_prototype=debug", "log_file": "/tmp/ppm.log" }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            TraceConfiguration {
+                format: LogFormat::Json,
+                filter: Some("ppm_prototype=debug".to_string()),
+                log_file: Some(PathBuf::from("/tmp/ppm.log")),
+                otlp_endpoint: None,
+            }
+        );
+
+        let config: TraceConfiguration = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, TraceConfiguration::default());
+    }
 }
@@ -0,0 +1,56 @@
+//! Checks that message encodings and HPKE inputs match the checked-in test
+//! vectors. If an encoding changes deliberately, regenerate the vectors with
+//! `cargo run --features test-util --bin generate-test-vectors`.
+
+use ppm_prototype::test_vectors::{
+    hpke_vectors, message_vectors, reencode_message, HpkeVectors, MessageVector,
+};
+
+fn checked_in_message_vectors() -> Vec<MessageVector> {
+    serde_json::from_str(include_str!("../test-vectors/messages.json")).unwrap()
+}
+
+fn checked_in_hpke_vectors() -> HpkeVectors {
+    serde_json::from_str(include_str!("../test-vectors/hpke.json")).unwrap()
+}
+
+#[test]
+fn message_encodings_match_vectors() {
+    let checked_in = checked_in_message_vectors();
+    let generated = message_vectors();
+    assert_eq!(checked_in.len(), generated.len());
+
+    for (checked_in, generated) in checked_in.iter().zip(generated.iter()) {
+        assert_eq!(
+            checked_in, generated,
+            "encoding of {} ({}) changed",
+            checked_in.message, checked_in.description
+        );
+    }
+}
+
+#[test]
+fn message_vectors_round_trip() {
+    for vector in checked_in_message_vectors() {
+        let reencoded = reencode_message(&vector.message, &vector.encoding).unwrap_or_else(|e| {
+            panic!(
+                "failed to decode {} ({}): {}",
+                vector.message, vector.description, e
+            )
+        });
+        assert_eq!(
+            reencoded, vector.encoding,
+            "{} ({}) re-encoded differently",
+            vector.message, vector.description
+        );
+    }
+}
+
+#[test]
+fn hpke_inputs_match_vectors() {
+    let checked_in = checked_in_hpke_vectors();
+    let generated = hpke_vectors();
+
+    assert_eq!(checked_in.application_info, generated.application_info);
+    assert_eq!(checked_in.associated_data, generated.associated_data);
+}
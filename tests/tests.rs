@@ -1,25 +1,38 @@
 use assert_matches::assert_matches;
-use color_eyre::Result;
-use http::StatusCode;
+use http::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    StatusCode,
+};
+use http_api_problem::HttpApiProblem;
 use ppm_prototype::{
-    client::{self, PpmClient},
-    collect::{self, run_collect},
-    helper::run_helper,
+    auth::{AdminAuth, ClientCredential, UploadAuth},
+    client::{self, PpmClient, Tamper},
+    collect::{self, PpmCollector},
+    dp::{DifferentialPrivacy, Mechanism, Rational},
     hpke,
-    leader::run_leader,
-    parameters::Parameters,
-    trace, Duration, Interval, Time,
+    parameters::{Parameters, QueryType, RetentionPolicy, TaskId, VdafLabel},
+    rate_limit::{RateLimit, UploadLimits},
+    report::{DeviceClass, Extension, ExtensionType, Report},
+    status::{
+        BatchFailures, CollectAuditResponse, FailuresResponse, StatusResponse, TaskCollectAudit,
+        TaskFailures, TaskStatus,
+    },
+    task::{TaskList, TaskView},
+    test_util::{
+        sample_hpke_configs, sample_parameters, Fault, FaultScript, RunningAggregators,
+        TestAggregators,
+    },
+    trace::{self, TraceConfiguration},
+    BatchSelector, Duration, Interval, Nonce, Role, Time,
 };
 use prio::{
-    field::Field128,
+    codec::Encode,
     vdaf::{
-        prio3::{Prio3Aes128Sum, Prio3InputShare},
-        Vdaf,
+        prio3::{Prio3Aes128Histogram, Prio3Aes128Sum},
+        Client, Vdaf,
     },
 };
-use serial_test::serial;
-use std::{io::Cursor, sync::Once};
-use tokio::task::JoinHandle;
+use std::sync::Once;
 
 const INTERVAL_START: u64 = 1631907500;
 
@@ -27,129 +40,107 @@ const INTERVAL_START: u64 = 1631907500;
 static INSTALL_TRACE_SUBSCRIBER: Once = Once::new();
 
 struct TestCase {
-    parameters: Parameters,
-    hpke_config: hpke::ConfigFile,
     client: PpmClient<Prio3Aes128Sum>,
-    vdaf: Prio3Aes128Sum,
-    leader_handle: JoinHandle<Result<()>>,
-    helper_handle: JoinHandle<Result<()>>,
+    collector: PpmCollector<Prio3Aes128Sum>,
+    /// The parameters the test case was created with, naming the aggregators'
+    /// actual endpoints
+    parameters: Parameters,
+    aggregators: RunningAggregators,
 }
 
 impl TestCase {
-    async fn new_tamper(tamper_leader_proof: bool, tamper_helper_proof: bool) -> Self {
-        INSTALL_TRACE_SUBSCRIBER.call_once(trace::install_subscriber);
+    /// The test case from [`TestCase::new`], along with 10 more reports whose
+    /// input shares are tampered with as requested
+    async fn new_tamper(
+        tamper_leader_share: Option<Tamper>,
+        tamper_helper_share: Option<Tamper>,
+    ) -> Self {
+        let mut parameters = sample_parameters();
+        parameters.admin_auth = Some(AdminAuth {
+            tokens: vec!["admin token".to_string()],
+        });
+        let test_case =
+            Self::new_with_reports(parameters, (0..100).map(|count| INTERVAL_START + count)).await;
 
-        let parameters = Parameters::from_json_reader(Cursor::new(include_bytes!(
-            "../sample-config/parameters.json"
-        )))
-        .unwrap();
-        let hpke_config = hpke::ConfigFile::from_json_reader(Cursor::new(include_bytes!(
-            "../sample-config/hpke.json"
-        )))
-        .unwrap();
+        for count in 0..10 {
+            test_case
+                .client
+                .do_upload_tamper(
+                    INTERVAL_START + count,
+                    &1,
+                    tamper_leader_share,
+                    tamper_helper_share,
+                )
+                .await
+                .unwrap();
+        }
+        test_case.client.run_aggregate().await.unwrap();
+
+        test_case
+    }
 
-        let leader_parameters = parameters.clone();
-        let helper_parameters = parameters.clone();
+    /// Upload and aggregate a report with a measurement of 1 for each of
+    /// `report_times`
+    async fn new_with_reports(
+        parameters: Parameters,
+        report_times: impl IntoIterator<Item = u64>,
+    ) -> Self {
+        INSTALL_TRACE_SUBSCRIBER.call_once(|| {
+            let trace_guard = trace::install_subscriber(
+                &TraceConfiguration::default().with_env_overrides().unwrap(),
+            )
+            .unwrap();
+            // Keep the subscriber running for the rest of the tests
+            std::mem::forget(trace_guard);
+        });
 
         let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
-        let leader_vdaf = vdaf.clone();
-        let helper_vdaf = leader_vdaf.clone();
-        let client_vdaf = leader_vdaf.clone();
-
-        let leader_hpke_config = hpke_config.leader.clone();
-        let helper_hpke_config = hpke_config.helper.clone();
 
         // Simulate negotation of verify parameter
         let (_, verify_parameters) = vdaf.setup().unwrap();
 
-        let leader_verify_parameter = verify_parameters[0].clone();
-        let helper_verify_parameter = verify_parameters[1].clone();
-
-        // Spawn leader and helper tasks
-        let leader_handle = tokio::spawn(async move {
-            run_leader(
-                &leader_parameters,
-                &leader_vdaf,
-                &leader_verify_parameter,
-                &(),
-                &leader_hpke_config,
-            )
-            .await
-        });
-        let helper_handle = tokio::spawn(async move {
-            run_helper(
-                &helper_parameters,
-                &helper_vdaf,
-                &helper_verify_parameter,
-                &(),
-                &helper_hpke_config,
-            )
+        let aggregators = TestAggregators::new(parameters, vdaf.clone(), ())
+            .with_verify_parameters(verify_parameters[0].clone(), verify_parameters[1].clone())
+            .start()
             .await
-        });
-
-        // Generate and upload 100 reports, with timestamps one second apart
-        let client = PpmClient::new(&parameters, &client_vdaf, ()).await.unwrap();
-
-        // libprio doesn't currently expose a way to tamper with input shares
-        // (all fields of [`Prio3InputShare`] are private) so we neuter this
-        // feature and the test cases that depend on it
-        let tamper_leader_proof_func = if tamper_leader_proof {
-            |input_share: &Prio3InputShare<Field128, 16>| input_share.clone()
-        } else {
-            |s: &Prio3InputShare<Field128, 16>| s.clone()
-        };
-
-        let tamper_helper_proof_func = if tamper_helper_proof {
-            |input_share: &Prio3InputShare<Field128, 16>| input_share.clone()
-        } else {
-            |s: &Prio3InputShare<Field128, 16>| s.clone()
-        };
+            .unwrap();
+        let parameters = aggregators.parameters().clone();
 
-        for count in 0..100 {
-            client
-                .do_upload_tamper(
-                    INTERVAL_START + count,
-                    &1,
-                    &tamper_leader_proof_func
-                        as &dyn Fn(&Prio3InputShare<Field128, 16>) -> Prio3InputShare<Field128, 16>,
-                    &tamper_helper_proof_func
-                        as &dyn Fn(&Prio3InputShare<Field128, 16>) -> Prio3InputShare<Field128, 16>,
-                )
-                .await
-                .unwrap();
+        // Generate and upload the reports
+        let client = PpmClient::new(&parameters, &vdaf, ()).await.unwrap();
+        for report_time in report_times {
+            client.do_upload(report_time, &1).await.unwrap();
         }
 
         client.run_aggregate().await.unwrap();
 
+        let collector =
+            PpmCollector::new(&parameters, &vdaf, &aggregators.hpke_configs().collector).unwrap();
+
         Self {
-            parameters,
-            hpke_config,
             client,
-            vdaf,
-            leader_handle,
-            helper_handle,
+            collector,
+            parameters,
+            aggregators,
         }
     }
 
     async fn new() -> Self {
-        Self::new_tamper(false, false).await
+        Self::new_with_reports(
+            sample_parameters(),
+            (0..100).map(|count| INTERVAL_START + count),
+        )
+        .await
     }
 
     async fn teardown(self) {
-        // Kill leader and helper tasks
-        self.leader_handle.abort();
-        self.helper_handle.abort();
-
-        assert!(self.leader_handle.await.unwrap_err().is_cancelled());
-        assert!(self.helper_handle.await.unwrap_err().is_cancelled());
+        self.aggregators.shutdown().await.unwrap();
     }
 }
 
 #[tokio::test]
-#[serial]
 async fn successful_aggregate() {
     let test_case = TestCase::new().await;
-    let aggregate_share_len = test_case.vdaf.output_len();
 
     // The interval should capture all inputs send by client
     let collect_interval = Interval {
@@ -158,42 +149,34 @@ async fn successful_aggregate() {
     };
 
     // Successful collect
-    let sum = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        collect_interval,
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap();
+    let sum = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap();
 
-    assert_eq!(sum.0, 100);
+    assert_eq!(sum.aggregate_result.0, 100);
+    assert_eq!(sum.report_count, 100);
 
     test_case.teardown().await;
 }
 
 #[tokio::test]
-#[serial]
 async fn insufficient_batch_size() {
     let test_case = TestCase::new().await;
-    let aggregate_share_len = test_case.vdaf.output_len();
 
     // Not enough inputs in the interval to meet min batch size
-    let error_document = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        Interval {
-            start: Time(INTERVAL_START),
-            duration: Duration(50),
-        },
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap_err();
+    let error_document = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(50),
+            },
+            &(),
+        )
+        .await
+        .unwrap_err();
 
     assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
         assert_eq!(problem_document.instance, Some("collect".to_string()));
@@ -205,10 +188,62 @@ async fn insufficient_batch_size() {
 }
 
 #[tokio::test]
-#[serial]
+async fn poll_collect_retries_insufficient_batch_size() {
+    let mut parameters = sample_parameters();
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    // Only half of min_batch_size has been uploaded when collection starts
+    let test_case =
+        TestCase::new_with_reports(parameters, (0..50).map(|count| INTERVAL_START + count)).await;
+    let leader_endpoint = test_case
+        .parameters
+        .aggregator_endpoint(Role::Leader)
+        .unwrap();
+
+    let collect = test_case.collector.poll_collect(
+        collect::Query::TimeInterval {
+            batch_interval: Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(50),
+            },
+        },
+        &(),
+        std::time::Duration::from_millis(100),
+        100,
+    );
+    // Upload the rest of the reports once the first collect request has been
+    // refused
+    let upload = async {
+        while collect_audit(leader_endpoint).await.queries.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        for count in 0..50 {
+            test_case
+                .client
+                .do_upload(INTERVAL_START + count, &1)
+                .await
+                .unwrap();
+        }
+        test_case.client.run_aggregate().await.unwrap();
+    };
+    let (sum, ()) = tokio::join!(collect, upload);
+
+    let sum = sum.unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+    assert_eq!(sum.report_count, 100);
+
+    let leader_audit = collect_audit(leader_endpoint).await;
+    assert!(leader_audit.queries.len() >= 2);
+    assert!(!leader_audit.queries[0].collected);
+    assert!(leader_audit.queries[leader_audit.queries.len() - 1].collected);
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
 async fn exceed_privacy_budget() {
     let test_case = TestCase::new().await;
-    let aggregate_share_len = test_case.vdaf.output_len();
 
     // The interval should capture all inputs send by client
     let collect_interval = Interval {
@@ -217,31 +252,21 @@ async fn exceed_privacy_budget() {
     };
 
     // Successful collect
-    let sum = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        collect_interval,
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap();
+    let sum = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap();
 
-    assert_eq!(sum.0, 100);
+    assert_eq!(sum.aggregate_result.0, 100);
 
     // Collect again over same interval. Should fail because privacy budget is
     // exceeded.
-    let error_document = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        collect_interval,
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap_err();
+    let error_document = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap_err();
 
     assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
         assert_eq!(problem_document.instance, Some("collect".to_string()));
@@ -253,24 +278,20 @@ async fn exceed_privacy_budget() {
 }
 
 #[tokio::test]
-#[serial]
 async fn unaligned_batch_interval() {
     let test_case = TestCase::new().await;
-    let aggregate_share_len = test_case.vdaf.output_len();
-
-    let error_document = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        Interval {
-            start: Time(INTERVAL_START),
-            duration: Duration(99),
-        },
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap_err();
+
+    let error_document = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(99),
+            },
+            &(),
+        )
+        .await
+        .unwrap_err();
 
     assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
         assert_eq!(problem_document.instance, Some("collect".to_string()));
@@ -282,24 +303,20 @@ async fn unaligned_batch_interval() {
 }
 
 #[tokio::test]
-#[serial]
 async fn batch_interval_too_short() {
     let test_case = TestCase::new().await;
-    let aggregate_share_len = test_case.vdaf.output_len();
-
-    let error_document = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        Interval {
-            start: Time(INTERVAL_START),
-            duration: Duration(25),
-        },
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap_err();
+
+    let error_document = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(25),
+            },
+            &(),
+        )
+        .await
+        .unwrap_err();
 
     assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
         assert_eq!(problem_document.instance, Some("collect".to_string()));
@@ -310,78 +327,112 @@ async fn batch_interval_too_short() {
     test_case.teardown().await;
 }
 
-#[tokio::test]
-#[serial]
-#[ignore]
-async fn invalid_helper_proof() {
-    let test_case = TestCase::new_tamper(false, true).await;
-    let aggregate_share_len = test_case.vdaf.output_len();
-
-    let error_document = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        Interval {
-            start: Time(INTERVAL_START),
-            duration: Duration(100),
-        },
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap_err();
+/// Check that the test case's tampered reports were left out of the aggregate,
+/// and that each of `roles` counted them as failed with `outcome`
+async fn check_tampered_reports_excluded(test_case: TestCase, roles: &[Role], outcome: &str) {
+    // Failed reports are never aggregated again, nor do they count as pending
+    test_case.client.run_aggregate().await.unwrap();
+    for role in [Role::Leader, Role::Helper].iter() {
+        let endpoint = test_case.parameters.aggregator_endpoint(*role).unwrap();
+        let status = task_status(endpoint).await;
+        let failed_reports = if roles.contains(role) { 10 } else { 0 };
+        assert_eq!(status.failed_reports, failed_reports, "{:?}", role);
+        assert_eq!(status.pending_reports, 0, "{:?}", role);
+        assert_eq!(status.aggregated_reports, 100, "{:?}", role);
 
-    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
-        assert_eq!(problem_document.instance, Some("collect".to_string()));
-        assert_eq!(problem_document.status, Some(StatusCode::BAD_REQUEST));
-        // There's no explicit error from proof rejection. Rather, those inputs
-        // whose proofs were bad will simply be not have been aggregated, so
-        // the collect request fails with insufficient batch size.
-        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:insufficientBatchSize".to_string()));
-    });
+        // All the tampered reports are in the first interval
+        let expected_failures = if roles.contains(role) {
+            vec![BatchFailures {
+                batch: Some(
+                    BatchSelector::TimeInterval(Interval {
+                        start: Time(INTERVAL_START),
+                        duration: test_case.parameters.min_batch_duration,
+                    })
+                    .to_string(),
+                ),
+                reasons: [(outcome.to_string(), 10)].iter().cloned().collect(),
+            }]
+        } else {
+            vec![]
+        };
+        assert_eq!(
+            task_failures(endpoint).await.batches,
+            expected_failures,
+            "{:?}",
+            role
+        );
+    }
+
+    let collect_interval = Interval {
+        start: Time(INTERVAL_START),
+        duration: Duration(100),
+    };
+    let sum = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+    assert_eq!(sum.report_count, 100);
+
+    let http_client = reqwest::Client::new();
+    for &role in roles {
+        let endpoint = test_case.parameters.aggregator_endpoint(role).unwrap();
+        let metrics = http_client
+            .get(endpoint.join("metrics").unwrap())
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let failed = format!(
+            "ppm_reports_prepared_total{{outcome=\"{}\",role=\"{}\",task=\"{}\"}} 10\n",
+            outcome,
+            role.name(),
+            test_case.parameters.task_id
+        );
+        assert!(metrics.contains(&failed), "{} not in {}", failed, metrics);
+    }
 
     test_case.teardown().await;
 }
 
 #[tokio::test]
-#[serial]
-#[ignore]
+async fn invalid_helper_proof() {
+    // The leader checks the proof first and tells the helper to give up on
+    // the report
+    let test_case = TestCase::new_tamper(None, Some(Tamper::EncodedShare)).await;
+    check_tampered_reports_excluded(test_case, &[Role::Leader, Role::Helper], "VdafPrepError")
+        .await;
+}
+
+#[tokio::test]
 async fn invalid_leader_proof() {
-    let test_case = TestCase::new_tamper(true, false).await;
-    let aggregate_share_len = test_case.vdaf.output_len();
-
-    let error_document = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        Interval {
-            start: Time(INTERVAL_START),
-            duration: Duration(100),
-        },
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap_err();
+    let test_case = TestCase::new_tamper(Some(Tamper::EncodedShare), None).await;
+    check_tampered_reports_excluded(test_case, &[Role::Leader, Role::Helper], "VdafPrepError")
+        .await;
+}
 
-    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
-        assert_eq!(problem_document.instance, Some("collect".to_string()));
-        assert_eq!(problem_document.status, Some(StatusCode::BAD_REQUEST));
-        // There's no explicit error from proof rejection. Rather, those inputs
-        // whose proofs were bad will simply be not have been aggregated, so
-        // the collect request fails with insufficient batch size.
-        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:insufficientBatchSize".to_string()));
-    });
+#[tokio::test]
+async fn undecryptable_helper_share() {
+    // The helper rejects the report and the leader counts the helper's error
+    let test_case = TestCase::new_tamper(None, Some(Tamper::Ciphertext)).await;
+    check_tampered_reports_excluded(test_case, &[Role::Leader, Role::Helper], "HpkeDecryptError")
+        .await;
+}
 
-    test_case.teardown().await;
+#[tokio::test]
+async fn undecryptable_leader_share() {
+    // The leader never sends the report to the helper
+    let test_case = TestCase::new_tamper(Some(Tamper::Ciphertext), None).await;
+    check_tampered_reports_excluded(test_case, &[Role::Leader], "HpkeDecryptError").await;
 }
 
 #[tokio::test]
-#[serial]
 async fn report_uploaded_after_interval_collected() {
     // Successfully run aggregation over an interval
     let test_case = TestCase::new().await;
-    let aggregate_share_len = test_case.vdaf.output_len();
 
     // The interval should capture all inputs send by client
     let collect_interval = Interval {
@@ -390,18 +441,13 @@ async fn report_uploaded_after_interval_collected() {
     };
 
     // Successful collect
-    let sum = run_collect(
-        &test_case.parameters,
-        &test_case.hpke_config.collector,
-        collect_interval,
-        test_case.vdaf.clone(),
-        &(),
-        aggregate_share_len,
-    )
-    .await
-    .unwrap();
+    let sum = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap();
 
-    assert_eq!(sum.0, 100);
+    assert_eq!(sum.aggregate_result.0, 100);
 
     // Upload one more share, within the collected interval.
     let error_document = test_case
@@ -418,3 +464,1540 @@ async fn report_uploaded_after_interval_collected() {
 
     test_case.teardown().await;
 }
+
+#[tokio::test]
+async fn collect_series() {
+    // The first two intervals each hold 50 reports, which is less than the min
+    // batch size, and the third holds enough to be collected
+    let test_case = TestCase::new_with_reports(
+        sample_parameters(),
+        (0..100)
+            .map(|count| INTERVAL_START + count)
+            .chain((0..100).map(|count| INTERVAL_START + 100 + count % 50)),
+    )
+    .await;
+
+    // Failing to collect the first two intervals shouldn't prevent collecting
+    // the third
+    let mut collections = test_case
+        .collector
+        .collect_series(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(50),
+            },
+            3,
+            &(),
+        )
+        .await;
+
+    assert_eq!(collections.len(), 3);
+    let last_collection = collections.pop().unwrap();
+    for collection in collections {
+        assert_matches!(collection, Err(collect::Error::ProblemDocument(problem_document)) => {
+            assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:insufficientBatchSize".to_string()));
+        });
+    }
+    assert_eq!(last_collection.unwrap().aggregate_result.0, 100);
+
+    // A series running past the end of time stops at the first interval that
+    // can't be represented rather than panicking
+    let collections = test_case
+        .collector
+        .collect_series(
+            Interval {
+                start: Time(u64::MAX - 15),
+                duration: Duration(50),
+            },
+            3,
+            &(),
+        )
+        .await;
+    assert_eq!(collections.len(), 2);
+    assert_matches!(collections[1], Err(collect::Error::SeriesOutOfRange(1)));
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn refused_collect_consumes_no_privacy_budget() {
+    let mut parameters = sample_parameters();
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    // Put 50 reports in each of the first two intervals of min_batch_duration,
+    // and enough in the third for it to be collected on its own
+    let test_case = TestCase::new_with_reports(
+        parameters,
+        (0..100)
+            .map(|count| INTERVAL_START + count)
+            .chain((0..100).map(|count| INTERVAL_START + 100 + count % 50)),
+    )
+    .await;
+
+    let sum = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START + 100),
+                duration: Duration(50),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+
+    // The second interval has budget remaining but the third does not, so this
+    // is refused...
+    let error_document = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START + 50),
+                duration: Duration(100),
+            },
+            &(),
+        )
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:privacyBudgetExceeded".to_string()));
+    });
+
+    // ...without consuming the second interval's budget
+    let sum = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(100),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+
+    // Operators can see both the released and the refused queries. The
+    // leader refused the second query without asking the helper.
+    let outcomes = |audit: TaskCollectAudit| -> Vec<_> {
+        audit
+            .queries
+            .into_iter()
+            .map(|query| (query.collected, query.report_count))
+            .collect()
+    };
+    let leader_audit = collect_audit(
+        test_case
+            .parameters
+            .aggregator_endpoint(Role::Leader)
+            .unwrap(),
+    )
+    .await;
+    assert!(leader_audit.queries[1]
+        .rejection_reason
+        .as_ref()
+        .unwrap()
+        .contains("privacy budget"));
+    assert_eq!(
+        outcomes(leader_audit),
+        vec![(true, Some(100)), (false, None), (true, Some(100))]
+    );
+    let helper_audit = collect_audit(
+        test_case
+            .parameters
+            .aggregator_endpoint(Role::Helper)
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(
+        outcomes(helper_audit),
+        vec![(true, Some(100)), (true, Some(100))]
+    );
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn differential_privacy() {
+    let mut parameters = sample_parameters();
+    parameters.differential_privacy = Some(DifferentialPrivacy {
+        mechanism: Mechanism::DiscreteLaplace {
+            epsilon: Rational {
+                numerator: 1,
+                denominator: 1,
+            },
+        },
+        sensitivity: 1,
+    });
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    let test_case =
+        TestCase::new_with_reports(parameters, (0..100).map(|count| INTERVAL_START + count)).await;
+
+    let sum = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(100),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+
+    // Both the aggregate and the report count are noised, by an amount that is
+    // vanishingly unlikely to be this large with a scale of 1
+    assert!((50..150).contains(&sum.aggregate_result.0), "{:?}", sum);
+    assert!((50..150).contains(&sum.report_count), "{:?}", sum);
+
+    // Exact per-batch counts aren't published
+    for role in [Role::Leader, Role::Helper].iter() {
+        let endpoint = test_case.parameters.aggregator_endpoint(*role).unwrap();
+        let status = task_status(endpoint).await;
+        assert!(!status.batches.is_empty(), "{:?}", role);
+        assert!(
+            status
+                .batches
+                .iter()
+                .all(|batch| batch.contributions.is_none()),
+            "{:?}",
+            role
+        );
+    }
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn differential_privacy_histogram() {
+    let buckets = vec![10, 20];
+    let mut parameters = sample_parameters();
+    parameters.vdaf = VdafLabel::Prio3Histogram64 {
+        buckets: buckets.clone(),
+    };
+    parameters.differential_privacy = Some(DifferentialPrivacy {
+        mechanism: Mechanism::DiscreteLaplace {
+            epsilon: Rational {
+                numerator: 1,
+                denominator: 1,
+            },
+        },
+        sensitivity: 1,
+    });
+
+    let vdaf = Prio3Aes128Histogram::new(2, &buckets).unwrap();
+    let (_, verify_parameters) = vdaf.setup().unwrap();
+    let aggregators = TestAggregators::new(parameters, vdaf.clone(), ())
+        .with_verify_parameters(verify_parameters[0].clone(), verify_parameters[1].clone())
+        .start()
+        .await
+        .unwrap();
+    let parameters = aggregators.parameters().clone();
+
+    // Every report lands in the first bucket, leaving the other two empty
+    let client = PpmClient::new(&parameters, &vdaf, ()).await.unwrap();
+    for count in 0..100 {
+        client.do_upload(INTERVAL_START + count, &5).await.unwrap();
+    }
+    client.run_aggregate().await.unwrap();
+
+    // Noise takes each empty bucket below zero about half the time, which the
+    // collector clamps to zero rather than failing to unshard
+    let collector =
+        PpmCollector::new(&parameters, &vdaf, &aggregators.hpke_configs().collector).unwrap();
+    let histogram = collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(100),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+
+    let counts = histogram.aggregate_result.0;
+    assert_eq!(counts.len(), 3);
+    assert!((50..150).contains(&counts[0]), "{:?}", counts);
+    assert!(counts[1..].iter().all(|count| *count < 50), "{:?}", counts);
+
+    aggregators.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn fixed_size_batches() {
+    let mut parameters = sample_parameters();
+    parameters.query_type = QueryType::FixedSize { batch_size: 100 };
+
+    // Enough reports for two full batches and part of a third
+    let test_case =
+        TestCase::new_with_reports(parameters, (0..250).map(|count| INTERVAL_START + count)).await;
+
+    let first_batch = test_case
+        .collector
+        .collect_fixed_size(None, &())
+        .await
+        .unwrap();
+    assert_eq!(first_batch.aggregate_result.0, 100);
+    assert_eq!(first_batch.report_count, 100);
+
+    let second_batch = test_case
+        .collector
+        .collect_fixed_size(None, &())
+        .await
+        .unwrap();
+    assert_eq!(second_batch.aggregate_result.0, 100);
+    assert_ne!(first_batch.batch, second_batch.batch);
+
+    // The third batch isn't full yet
+    let error_document = test_case
+        .collector
+        .collect_fixed_size(None, &())
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:insufficientBatchSize".to_string()));
+    });
+
+    // Batches can also be collected by ID, subject to the privacy budget
+    let first_batch_id =
+        assert_matches!(first_batch.batch, BatchSelector::FixedSize(batch_id) => batch_id);
+    let error_document = test_case
+        .collector
+        .collect_fixed_size(Some(first_batch_id), &())
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:privacyBudgetExceeded".to_string()));
+    });
+
+    // Time interval queries make no sense for this task
+    let error_document = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(100),
+            },
+            &(),
+        )
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:queryMismatch".to_string()));
+    });
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn garbage_collection() {
+    let mut parameters = sample_parameters();
+    // Keep the test reports around, but expire anything much older
+    parameters.retention = Some(RetentionPolicy {
+        gc_interval: Duration(1),
+        report_max_age: Some(Duration(Time::now().0 - INTERVAL_START + 1000)),
+        accumulator_max_age: None,
+    });
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+
+    let test_case =
+        TestCase::new_with_reports(parameters, (0..100).map(|count| INTERVAL_START + count)).await;
+
+    // A report of zero in the collected interval, which is deleted once it
+    // has been accumulated
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+    let hpke_config = sample_hpke_configs();
+    let nonce = Nonce {
+        time: Time(INTERVAL_START + 50),
+        rand: 0,
+    };
+    let input_shares = vdaf.shard(&(), &0).unwrap();
+    let seal = |config: &hpke::Config, role: Role| {
+        config
+            .sender(
+                &test_case.parameters.task_id,
+                hpke::Label::InputShare,
+                Role::Client,
+                role,
+            )
+            .unwrap()
+            .seal(
+                &role.entry(&input_shares).unwrap().get_encoded(),
+                &Report::associated_data(nonce, &[]),
+            )
+            .unwrap()
+    };
+    let accumulated_report = Report {
+        task_id: test_case.parameters.task_id,
+        nonce,
+        extensions: vec![],
+        encrypted_input_shares: vec![
+            seal(&hpke_config.leader, Role::Leader),
+            seal(&hpke_config.helper, Role::Helper),
+        ],
+    };
+    let upload_accumulated_report = || {
+        reqwest::Client::new()
+            .post(test_case.parameters.upload_endpoint().unwrap())
+            .body(accumulated_report.get_encoded())
+            .send()
+    };
+    assert_eq!(
+        upload_accumulated_report().await.unwrap().status(),
+        StatusCode::OK
+    );
+
+    // A report that fails aggregation, in an interval that isn't collected
+    let garbage_share = |config: &hpke::Config| hpke::Ciphertext {
+        config_id: config.id,
+        encapsulated_context: vec![0; 32],
+        payload: vec![0; 64],
+    };
+    let failed_report = Report {
+        task_id: test_case.parameters.task_id,
+        nonce: Nonce {
+            time: Time(INTERVAL_START + 150),
+            rand: 0,
+        },
+        extensions: vec![],
+        encrypted_input_shares: vec![
+            garbage_share(&hpke_config.leader),
+            garbage_share(&hpke_config.helper),
+        ],
+    };
+    let upload_failed_report = || {
+        reqwest::Client::new()
+            .post(test_case.parameters.upload_endpoint().unwrap())
+            .body(failed_report.get_encoded())
+            .send()
+    };
+    assert_eq!(
+        upload_failed_report().await.unwrap().status(),
+        StatusCode::OK
+    );
+    test_case.client.run_aggregate().await.unwrap();
+
+    let collect_interval = Interval {
+        start: Time(INTERVAL_START),
+        duration: Duration(100),
+    };
+
+    let sum = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+
+    // Give the aggregators a chance to delete the collected reports and
+    // exhausted accumulators
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    for role in [Role::Leader, Role::Helper].iter() {
+        let endpoint = test_case.parameters.aggregator_endpoint(*role).unwrap();
+        let status = task_status(endpoint).await;
+        assert_eq!(status.pending_reports, 0, "{:?}", role);
+        assert_eq!(status.aggregated_reports, 0, "{:?}", role);
+        assert_eq!(status.batches, vec![], "{:?}", role);
+    }
+
+    // Replays of deleted reports are still rejected, whether they were
+    // accumulated or failed
+    assert_eq!(
+        upload_accumulated_report().await.unwrap().status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        upload_failed_report().await.unwrap().status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    // Deleting the accumulators must not make their privacy budget available
+    // again
+    let error_document = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:privacyBudgetExceeded".to_string()));
+    });
+
+    // Reports older than the maximum age are rejected
+    let error_document = test_case
+        .client
+        .do_upload(INTERVAL_START - 2000, &1)
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.instance, Some("upload".to_string()));
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:staleReport".to_string()));
+    });
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn garbage_collected_batch_gets_no_new_budget() {
+    let mut parameters = sample_parameters();
+    parameters.min_batch_size = 10;
+    parameters.retention = Some(RetentionPolicy {
+        gc_interval: Duration(1),
+        report_max_age: Some(Duration(Time::now().0 - INTERVAL_START + 1000)),
+        accumulator_max_age: None,
+    });
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+    let (_, verify_parameters) = vdaf.setup().unwrap();
+    // The helper is unavailable when the second job continues preparation of
+    // its reports, so that they are still in flight when the batch is collected
+    let aggregators = TestAggregators::new(parameters, vdaf.clone(), ())
+        .with_verify_parameters(verify_parameters[0].clone(), verify_parameters[1].clone())
+        .with_faults(FaultScript::new().inject(3, Fault::Status(StatusCode::SERVICE_UNAVAILABLE)))
+        .start()
+        .await
+        .unwrap();
+    let parameters = aggregators.parameters().clone();
+    let client = PpmClient::new(&parameters, &vdaf, ()).await.unwrap();
+    let collector =
+        PpmCollector::new(&parameters, &vdaf, &aggregators.hpke_configs().collector).unwrap();
+
+    for count in 0..20 {
+        client.do_upload(INTERVAL_START + count, &1).await.unwrap();
+    }
+    client.run_aggregate().await.unwrap();
+    for count in 0..10 {
+        client.do_upload(INTERVAL_START + count, &1).await.unwrap();
+    }
+    client.run_aggregate().await.unwrap_err();
+
+    let collect_interval = Interval {
+        start: Time(INTERVAL_START),
+        duration: Duration(50),
+    };
+    let sum = collector.collect(collect_interval, &()).await.unwrap();
+    assert_eq!(sum.aggregate_result.0, 20);
+
+    // Give the aggregators a chance to delete the exhausted accumulators, then
+    // retry the job that was in flight
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    client.run_aggregate().await.unwrap();
+
+    // The retried reports must not have started a new accumulator, with a
+    // fresh privacy budget, for the collected interval
+    let error_document = collector.collect(collect_interval, &()).await.unwrap_err();
+    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:privacyBudgetExceeded".to_string()));
+    });
+
+    aggregators.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn report_extensions() {
+    let test_case = TestCase::new().await;
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+
+    // Recognized extensions are accepted, and so are unrecognized ones that
+    // aren't critical
+    let client = PpmClient::new(&test_case.parameters, &vdaf, ())
+        .await
+        .unwrap()
+        .with_extension(Extension::from_typed(&DeviceClass("mobile".to_string())))
+        .with_extension(Extension::new(ExtensionType::Unknown(0x0100), vec![1]));
+    client.do_upload(INTERVAL_START + 100, &1).await.unwrap();
+
+    let client = PpmClient::new(&test_case.parameters, &vdaf, ())
+        .await
+        .unwrap()
+        .with_extension(Extension::new(ExtensionType::Unknown(0x8100), vec![1]));
+    let error_document = client
+        .do_upload(INTERVAL_START + 100, &1)
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.instance, Some("upload".to_string()));
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:unrecognizedMessage".to_string()));
+    });
+
+    // The helper accepts the report with extensions too
+    client.run_aggregate().await.unwrap();
+    let sum = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(150),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(sum.aggregate_result.0, 101);
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn upload_authentication() {
+    let credential = ClientCredential::ed25519_from_seed(&[1u8; 32]).unwrap();
+    let mut parameters = sample_parameters();
+    parameters.upload_auth = Some(UploadAuth::Ed25519 {
+        public_keys: vec![credential.ed25519_public_key().unwrap()],
+    });
+    // Room for a single upload, which unauthenticated clients can't take
+    parameters.upload_limits = Some(UploadLimits {
+        per_task: Some(RateLimit {
+            burst: 1,
+            per_second: 0,
+        }),
+        ..Default::default()
+    });
+
+    let test_case = TestCase::new_with_reports(parameters.clone(), std::iter::empty()).await;
+    let parameters = test_case.parameters.clone();
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+
+    // The test case's client has no credentials
+    let error_document = test_case
+        .client
+        .do_upload(INTERVAL_START, &1)
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.instance, Some("upload".to_string()));
+        assert_eq!(problem_document.status, Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:unauthorizedRequest".to_string()));
+    });
+
+    // Signed with a key the leader doesn't know
+    let client = PpmClient::new(&parameters, &vdaf, ())
+        .await
+        .unwrap()
+        .with_credential(ClientCredential::ed25519_from_seed(&[2u8; 32]).unwrap());
+    let error_document = client.do_upload(INTERVAL_START, &1).await.unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.status, Some(StatusCode::FORBIDDEN));
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:unauthorizedRequest".to_string()));
+    });
+
+    let client = PpmClient::new(&parameters, &vdaf, ())
+        .await
+        .unwrap()
+        .with_credential(credential);
+    client.do_upload(INTERVAL_START, &1).await.unwrap();
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn upload_rate_limit() {
+    let mut parameters = sample_parameters();
+    parameters.upload_limits = Some(UploadLimits {
+        per_task: Some(RateLimit {
+            burst: 3,
+            per_second: 1,
+        }),
+        ..Default::default()
+    });
+
+    let test_case = TestCase::new_with_reports(parameters.clone(), std::iter::empty()).await;
+    let parameters = test_case.parameters.clone();
+
+    for count in 0..3 {
+        test_case
+            .client
+            .do_upload(INTERVAL_START + count, &1)
+            .await
+            .unwrap();
+    }
+
+    let error_document = test_case
+        .client
+        .do_upload(INTERVAL_START + 3, &1)
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.status, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:rateLimited".to_string()));
+    });
+
+    // The leader refuses the upload after reading only the task ID it begins
+    // with
+    let response = reqwest::Client::new()
+        .post(parameters.upload_endpoint().unwrap())
+        .body(parameters.task_id.get_encoded())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn max_pending_reports() {
+    let mut parameters = sample_parameters();
+    parameters.upload_limits = Some(UploadLimits {
+        max_pending_reports_per_task: Some(2),
+        ..Default::default()
+    });
+
+    let test_case =
+        TestCase::new_with_reports(parameters, [INTERVAL_START, INTERVAL_START + 1]).await;
+
+    // The test case ran aggregation, so there's room for two more reports
+    for count in 2..4 {
+        test_case
+            .client
+            .do_upload(INTERVAL_START + count, &1)
+            .await
+            .unwrap();
+    }
+
+    let error_document = test_case
+        .client
+        .do_upload(INTERVAL_START + 4, &1)
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.status, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:rateLimited".to_string()));
+    });
+
+    test_case.client.run_aggregate().await.unwrap();
+    test_case
+        .client
+        .do_upload(INTERVAL_START + 4, &1)
+        .await
+        .unwrap();
+
+    // Reports that fail aggregation free up room too
+    let hpke_config = sample_hpke_configs();
+    let garbage_share = |config: &hpke::Config| hpke::Ciphertext {
+        config_id: config.id,
+        encapsulated_context: vec![0; 32],
+        payload: vec![0; 64],
+    };
+    let failing_report = Report {
+        task_id: test_case.parameters.task_id,
+        nonce: Nonce {
+            time: Time(INTERVAL_START + 5),
+            rand: 0,
+        },
+        extensions: vec![],
+        encrypted_input_shares: vec![
+            garbage_share(&hpke_config.leader),
+            garbage_share(&hpke_config.helper),
+        ],
+    };
+    let response = reqwest::Client::new()
+        .post(test_case.parameters.upload_endpoint().unwrap())
+        .body(failing_report.get_encoded())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    test_case
+        .client
+        .do_upload(INTERVAL_START + 6, &1)
+        .await
+        .unwrap_err();
+
+    test_case.client.run_aggregate().await.unwrap();
+    for count in 6..8 {
+        test_case
+            .client
+            .do_upload(INTERVAL_START + count, &1)
+            .await
+            .unwrap();
+    }
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn max_pending_reports_across_tasks() {
+    let mut parameters = sample_parameters();
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+    let aggregators = TestAggregators::new(parameters, vdaf.clone(), ())
+        .with_max_pending_reports(3)
+        .start()
+        .await
+        .unwrap();
+    let parameters = aggregators.parameters().clone();
+
+    let mut second_task = parameters.clone();
+    second_task.task_id = TaskId::random();
+    let response = reqwest::Client::new()
+        .post(
+            parameters.aggregator_endpoints[0]
+                .join("tasks?push_to_helper=true")
+                .unwrap(),
+        )
+        .bearer_auth("admin token")
+        .json(&second_task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let first_client = PpmClient::new(&parameters, &vdaf, ()).await.unwrap();
+    let second_client = PpmClient::new(&second_task, &vdaf, ()).await.unwrap();
+    for count in 0..2 {
+        first_client
+            .do_upload(INTERVAL_START + count, &1)
+            .await
+            .unwrap();
+    }
+    second_client.do_upload(INTERVAL_START, &1).await.unwrap();
+
+    // The leader holds as many pending reports as it may, so neither task
+    // accepts more
+    for client in [&first_client, &second_client] {
+        let error_document = client.do_upload(INTERVAL_START + 10, &1).await.unwrap_err();
+        assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+            assert_eq!(problem_document.status, Some(StatusCode::TOO_MANY_REQUESTS));
+            assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:rateLimited".to_string()));
+        });
+    }
+
+    // Aggregation makes room again, which one task can take all of
+    first_client.run_aggregate().await.unwrap();
+    for count in 1..4 {
+        second_client
+            .do_upload(INTERVAL_START + count, &1)
+            .await
+            .unwrap();
+    }
+    first_client
+        .do_upload(INTERVAL_START + 2, &1)
+        .await
+        .unwrap_err();
+
+    // So does deleting a task along with its pending reports
+    let response = reqwest::Client::new()
+        .delete(
+            parameters.aggregator_endpoints[0]
+                .join(&format!("tasks/{}", second_task.task_id))
+                .unwrap(),
+        )
+        .bearer_auth("admin token")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    first_client
+        .do_upload(INTERVAL_START + 2, &1)
+        .await
+        .unwrap();
+
+    aggregators.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn upload_defers_decryption() {
+    let test_case = TestCase::new().await;
+    let parameters = test_case.parameters.clone();
+    let hpke_config = sample_hpke_configs();
+
+    // A report whose shares can't be decrypted passes the leader's checks on
+    // upload, which don't involve decryption
+    let garbage_share = |config: &hpke::Config| hpke::Ciphertext {
+        config_id: config.id,
+        encapsulated_context: vec![0; 32],
+        payload: vec![0; 64],
+    };
+    let report = Report {
+        task_id: parameters.task_id,
+        nonce: Nonce {
+            time: Time(INTERVAL_START + 100),
+            rand: 0,
+        },
+        extensions: vec![],
+        encrypted_input_shares: vec![
+            garbage_share(&hpke_config.leader),
+            garbage_share(&hpke_config.helper),
+        ],
+    };
+
+    let http_client = reqwest::Client::new();
+    let response = http_client
+        .post(parameters.upload_endpoint().unwrap())
+        .body(report.get_encoded())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // A leader share encrypted to a config the leader doesn't have is caught
+    // on upload, though
+    let mut unknown_config_report = report.clone();
+    unknown_config_report.nonce.rand = 1;
+    unknown_config_report.encrypted_input_shares[0].config_id =
+        hpke::ConfigId(hpke_config.leader.id.0.wrapping_add(1));
+    let response = http_client
+        .post(parameters.upload_endpoint().unwrap())
+        .body(unknown_config_report.get_encoded())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem_document: HttpApiProblem = response.json().await.unwrap();
+    assert_eq!(
+        problem_document.type_url.unwrap(),
+        "urn:ietf:params:ppm:error:outdatedConfig"
+    );
+
+    // Replays are still caught on upload
+    let response = http_client
+        .post(parameters.upload_endpoint().unwrap())
+        .body(report.get_encoded())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // The report is dropped during aggregation without disturbing the others,
+    // and it still can't be replayed afterwards
+    test_case.client.run_aggregate().await.unwrap();
+    let response = http_client
+        .post(parameters.upload_endpoint().unwrap())
+        .body(report.get_encoded())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let sum = test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(150),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn malformed_requests() {
+    let test_case = TestCase::new().await;
+    let parameters = test_case.parameters.clone();
+    let hpke_config = sample_hpke_configs();
+    let http_client = reqwest::Client::new();
+
+    // A report missing the helper's input share is refused
+    let report = Report {
+        task_id: parameters.task_id,
+        nonce: Nonce {
+            time: Time(INTERVAL_START + 100),
+            rand: 0,
+        },
+        extensions: vec![],
+        encrypted_input_shares: vec![hpke::Ciphertext {
+            config_id: hpke_config.leader.id,
+            encapsulated_context: vec![0; 32],
+            payload: vec![0; 64],
+        }],
+    };
+    let response = http_client
+        .post(parameters.upload_endpoint().unwrap())
+        .body(report.get_encoded())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem_document: HttpApiProblem = response.json().await.unwrap();
+    assert_eq!(
+        problem_document.type_url.unwrap(),
+        "urn:ietf:params:ppm:error:unrecognizedMessage"
+    );
+
+    // Requests warp itself rejects get problem documents too
+    let leader = parameters.aggregator_endpoint(Role::Leader).unwrap();
+    let response = http_client
+        .get(leader.join("no_such_endpoint").unwrap())
+        .send()
+        .await
+        .unwrap();
+    // warp prefers to report that the method isn't allowed for some other
+    // route over reporting that no route matches the path
+    assert!(response.status().is_client_error());
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+    let response = http_client
+        .get(parameters.upload_endpoint().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    // The leader is still serving
+    test_case
+        .client
+        .do_upload(INTERVAL_START + 4, &1)
+        .await
+        .unwrap();
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn metrics() {
+    let test_case = TestCase::new().await;
+    let parameters = test_case.parameters.clone();
+
+    test_case
+        .collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(100),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+
+    let http_client = reqwest::Client::new();
+    let get_metrics = |endpoint: &url::Url| {
+        let request = http_client.get(endpoint.join("metrics").unwrap()).send();
+        async move { request.await.unwrap().text().await.unwrap() }
+    };
+
+    let task = parameters.task_id;
+
+    let leader_metrics = get_metrics(&parameters.aggregator_endpoints[0]).await;
+    assert!(leader_metrics.contains(&format!(
+        r#"ppm_uploads_total{{outcome="accepted",role="leader",task="{}"}} 100"#,
+        task
+    )));
+    assert!(leader_metrics.contains(&format!(
+        r#"ppm_reports_prepared_total{{outcome="finished",role="leader",task="{}"}} 100"#,
+        task
+    )));
+    assert!(leader_metrics.contains(&format!(
+        r#"ppm_collect_requests_total{{outcome="collected",role="leader",task="{}"}} 1"#,
+        task
+    )));
+    assert!(leader_metrics.contains("ppm_aggregation_job_duration_seconds_count"));
+
+    let helper_metrics = get_metrics(&parameters.aggregator_endpoints[1]).await;
+    assert!(helper_metrics.contains(&format!(
+        r#"ppm_reports_prepared_total{{outcome="finished",role="helper",task="{}"}} 100"#,
+        task
+    )));
+    assert!(helper_metrics.contains(&format!(
+        r#"ppm_collect_requests_total{{outcome="collected",role="helper",task="{}"}} 1"#,
+        task
+    )));
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn health_and_status() {
+    let mut parameters = sample_parameters();
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    let test_case = TestCase::new_with_reports(
+        parameters.clone(),
+        (0..100).map(|count| INTERVAL_START + count),
+    )
+    .await;
+    let parameters = test_case.parameters.clone();
+
+    let http_client = reqwest::Client::new();
+    for endpoint in &parameters.aggregator_endpoints {
+        for path in ["healthz", "readyz"] {
+            let response = http_client
+                .get(endpoint.join(path).unwrap())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}{}", endpoint, path);
+        }
+
+        // Status requires the admin token
+        let response = http_client
+            .get(endpoint.join("status").unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = http_client
+            .get(endpoint.join("status").unwrap())
+            .bearer_auth("wrong token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let status: StatusResponse = http_client
+            .get(endpoint.join("status").unwrap())
+            .bearer_auth("admin token")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status.tasks.len(), 1);
+        let task = &status.tasks[0];
+        assert_eq!(task.task_id, parameters.task_id);
+        assert_eq!(task.pending_reports, 0);
+        assert_eq!(task.aggregated_reports, 100);
+        assert_eq!(task.failed_reports, 0);
+        // Reports span two intervals of min_batch_duration
+        assert_eq!(task.batches.len(), 2);
+        assert!(task
+            .batches
+            .iter()
+            .all(|batch| batch.contributions == Some(50) && !batch.collected));
+    }
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn task_management() {
+    let mut parameters = sample_parameters();
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    let test_case = TestCase::new_with_reports(parameters.clone(), []).await;
+    let parameters = test_case.parameters.clone();
+
+    let http_client = reqwest::Client::new();
+    let leader_tasks = parameters.aggregator_endpoints[0].join("tasks").unwrap();
+    let helper_tasks = parameters.aggregator_endpoints[1].join("tasks").unwrap();
+
+    let mut new_task = parameters.clone();
+    new_task.task_id = TaskId::random();
+    let new_task_endpoint =
+        |tasks: &url::Url| tasks.join(&format!("tasks/{}", new_task.task_id)).unwrap();
+    // Responses describe tasks without echoing their secrets back
+    let assert_no_secrets = |body: &str, task: &Parameters| {
+        assert!(!body.contains(&base64::encode(&task.aggregator_auth_key)));
+        for verification_parameter in &task.vdaf_verification_parameter {
+            assert!(!body.contains(&base64::encode(verification_parameter)));
+        }
+        assert!(!body.contains("admin token"));
+    };
+
+    // Creating a task requires the admin token
+    let response = http_client
+        .post(leader_tasks.clone())
+        .json(&new_task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Create the task on the leader, which pushes it to the helper
+    let response = http_client
+        .post(leader_tasks.join("tasks?push_to_helper=true").unwrap())
+        .bearer_auth("admin token")
+        .json(&new_task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.text().await.unwrap();
+    assert_no_secrets(&body, &new_task);
+    assert_eq!(
+        serde_json::from_str::<TaskView>(&body).unwrap(),
+        TaskView::from(&new_task)
+    );
+
+    // Creating it again conflicts
+    let response = http_client
+        .post(leader_tasks.clone())
+        .bearer_auth("admin token")
+        .json(&new_task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    for tasks in [&leader_tasks, &helper_tasks] {
+        let task_list: TaskList = http_client
+            .get(tasks.clone())
+            .bearer_auth("admin token")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(task_list.task_ids.len(), 2);
+        assert!(task_list.task_ids.contains(&new_task.task_id.to_string()));
+
+        let body = http_client
+            .get(new_task_endpoint(tasks))
+            .bearer_auth("admin token")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_no_secrets(&body, &new_task);
+        assert_eq!(
+            serde_json::from_str::<TaskView>(&body).unwrap(),
+            TaskView::from(&new_task)
+        );
+    }
+
+    // The new task takes effect without a restart
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+    let hpke_config = sample_hpke_configs();
+    let client = PpmClient::new(&new_task, &vdaf, ()).await.unwrap();
+    for count in 0..100 {
+        client.do_upload(INTERVAL_START + count, &1).await.unwrap();
+    }
+    client.run_aggregate().await.unwrap();
+
+    let collect_interval = Interval {
+        start: Time(INTERVAL_START),
+        duration: Duration(100),
+    };
+    let collector = PpmCollector::new(&new_task, &vdaf, &hpke_config.collector).unwrap();
+    let sum = collector.collect(collect_interval, &()).await.unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+
+    // Reports for the new task don't land in the original one
+    let error_document = test_case
+        .collector
+        .collect(collect_interval, &())
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, collect::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:insufficientBatchSize".to_string()));
+    });
+
+    // Rotate the aggregator auth key
+    let mut updated_task = new_task.clone();
+    updated_task.aggregator_auth_key = vec![7; 32];
+    let response = http_client
+        .put(new_task_endpoint(&leader_tasks).as_str().to_owned() + "?push_to_helper=true")
+        .bearer_auth("admin token")
+        .json(&updated_task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_no_secrets(&response.text().await.unwrap(), &updated_task);
+    for tasks in [&leader_tasks, &helper_tasks] {
+        let body = http_client
+            .get(new_task_endpoint(tasks))
+            .bearer_auth("admin token")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_no_secrets(&body, &updated_task);
+    }
+
+    // Aggregation still works with the rotated key
+    client.do_upload(INTERVAL_START + 200, &1).await.unwrap();
+    client.run_aggregate().await.unwrap();
+
+    // Parameters that identify the task's reports can't be changed
+    let mut immutable_change = updated_task.clone();
+    immutable_change.min_batch_duration = Duration(immutable_change.min_batch_duration.0 * 2);
+    let response = http_client
+        .put(new_task_endpoint(&leader_tasks))
+        .bearer_auth("admin token")
+        .json(&immutable_change)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Neither can the administrative credentials, which are the aggregator's
+    let mut credentials_change = updated_task.clone();
+    credentials_change.admin_auth = Some(AdminAuth {
+        tokens: vec!["rotated token".to_string()],
+    });
+    let response = http_client
+        .put(new_task_endpoint(&leader_tasks))
+        .bearer_auth("admin token")
+        .json(&credentials_change)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Retire the task, after which its reports are rejected
+    let response = http_client
+        .delete(new_task_endpoint(&leader_tasks).as_str().to_owned() + "?push_to_helper=true")
+        .bearer_auth("admin token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    for tasks in [&leader_tasks, &helper_tasks] {
+        let response = http_client
+            .get(new_task_endpoint(tasks))
+            .bearer_auth("admin token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let error_document = client
+        .do_upload(INTERVAL_START + 300, &1)
+        .await
+        .unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(problem_document.type_url, Some("urn:ietf:params:ppm:error:unrecognizedTask".to_string()));
+    });
+
+    // The retired task's ID can't be used again
+    let response = http_client
+        .post(leader_tasks.join("tasks?push_to_helper=true").unwrap())
+        .bearer_auth("admin token")
+        .json(&new_task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    test_case.teardown().await;
+}
+
+#[tokio::test]
+async fn aggregation_continues_past_failed_task() {
+    let mut parameters = sample_parameters();
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    let test_case = TestCase::new_with_reports(parameters, []).await;
+    let parameters = test_case.parameters.clone();
+    let http_client = reqwest::Client::new();
+    let leader_tasks = parameters.aggregator_endpoints[0].join("tasks").unwrap();
+
+    // The helper doesn't know about the failing task, which sorts before the
+    // healthy one so that it is aggregated first
+    let (first_id, second_id) = (TaskId::random(), TaskId::random());
+    let mut failing_task = parameters.clone();
+    failing_task.task_id = first_id.min(second_id);
+    let mut healthy_task = parameters.clone();
+    healthy_task.task_id = first_id.max(second_id);
+    for (task, tasks) in [
+        (&failing_task, leader_tasks.clone()),
+        (
+            &healthy_task,
+            leader_tasks.join("tasks?push_to_helper=true").unwrap(),
+        ),
+    ] {
+        let response = http_client
+            .post(tasks)
+            .bearer_auth("admin token")
+            .json(task)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+    let failing_client = PpmClient::new(&failing_task, &vdaf, ()).await.unwrap();
+    let healthy_client = PpmClient::new(&healthy_task, &vdaf, ()).await.unwrap();
+    for count in 0..100 {
+        failing_client
+            .do_upload(INTERVAL_START + count, &1)
+            .await
+            .unwrap();
+        healthy_client
+            .do_upload(INTERVAL_START + count, &1)
+            .await
+            .unwrap();
+    }
+
+    // The failure is reported once every task has been tried
+    let error_document = healthy_client.run_aggregate().await.unwrap_err();
+    assert_matches!(error_document, client::Error::ProblemDocument(problem_document) => {
+        assert_eq!(
+            problem_document.get_value::<&str, Vec<String>>("failedtasks"),
+            Some(vec![failing_task.task_id.to_string()])
+        );
+    });
+
+    let collector = PpmCollector::new(
+        &healthy_task,
+        &vdaf,
+        &test_case.aggregators.hpke_configs().collector,
+    )
+    .unwrap();
+    let sum = collector
+        .collect(
+            Interval {
+                start: Time(INTERVAL_START),
+                duration: Duration(100),
+            },
+            &(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(sum.aggregate_result.0, 100);
+
+    test_case.teardown().await;
+}
+
+/// Describe the task served by the aggregator at `endpoint`
+async fn task_status(endpoint: &url::Url) -> TaskStatus {
+    let mut status: StatusResponse = reqwest::Client::new()
+        .get(endpoint.join("status").unwrap())
+        .bearer_auth("admin token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    status.tasks.remove(0)
+}
+
+async fn task_failures(endpoint: &url::Url) -> TaskFailures {
+    let mut failures: FailuresResponse = reqwest::Client::new()
+        .get(endpoint.join("failures").unwrap())
+        .bearer_auth("admin token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    failures.tasks.remove(0)
+}
+
+async fn collect_audit(endpoint: &url::Url) -> TaskCollectAudit {
+    let mut audit: CollectAuditResponse = reqwest::Client::new()
+        .get(endpoint.join("collect_audit").unwrap())
+        .bearer_auth("admin token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    audit.tasks.remove(0)
+}
+
+/// Upload 10 reports and aggregate them while the helper's responses suffer
+/// the scripted faults, then aggregate again without faults. Returns whether
+/// the first aggregation succeeded, after checking that the second one left no
+/// report accumulated by only one of the aggregators.
+async fn aggregate_with_faults(faults: FaultScript) -> bool {
+    let mut parameters = sample_parameters();
+    parameters.admin_auth = Some(AdminAuth {
+        tokens: vec!["admin token".to_string()],
+    });
+    let vdaf = Prio3Aes128Sum::new(2, 63).unwrap();
+    let (_, verify_parameters) = vdaf.setup().unwrap();
+    let aggregators = TestAggregators::new(parameters, vdaf.clone(), ())
+        .with_verify_parameters(verify_parameters[0].clone(), verify_parameters[1].clone())
+        .with_faults(faults)
+        .start()
+        .await
+        .unwrap();
+
+    let client = PpmClient::new(aggregators.parameters(), &vdaf, ())
+        .await
+        .unwrap();
+    for count in 0..10 {
+        client.do_upload(INTERVAL_START + count, &1).await.unwrap();
+    }
+    let succeeded = client.run_aggregate().await.is_ok();
+    // The faults are scripted for the first aggregation's requests only
+    client.run_aggregate().await.unwrap();
+
+    let leader = task_status(aggregators.leader().url()).await;
+    let helper = task_status(aggregators.helper().url()).await;
+    for status in [&leader, &helper].iter() {
+        assert_eq!(status.aggregated_reports, 10);
+        assert_eq!(status.pending_reports, 0);
+        assert_eq!(status.failed_reports, 0);
+    }
+    let contributions = |status: &TaskStatus| {
+        let mut contributions: Vec<_> = status
+            .batches
+            .iter()
+            .filter(|batch| batch.contributions > Some(0))
+            .map(|batch| (batch.batch.clone(), batch.contributions))
+            .collect();
+        contributions.sort();
+        contributions
+    };
+    assert_eq!(contributions(&leader), contributions(&helper));
+
+    aggregators.shutdown().await.unwrap();
+    succeeded
+}
+
+/// Faults that make the leader reject any response from the helper
+fn failing_faults() -> Vec<Fault> {
+    vec![
+        Fault::Reorder,
+        Fault::Drop(3),
+        Fault::Duplicate(3),
+        Fault::WrongNonce(3),
+        Fault::Malformed,
+        Fault::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    ]
+}
+
+#[tokio::test]
+async fn helper_faults_during_initialization() {
+    // The helper's response to the aggregate initialization request is
+    // rejected as a whole, and the retried job picks up where the helper left
+    // off
+    for fault in failing_faults().into_iter().chain(Some(Fault::Finish(3))) {
+        assert!(
+            !aggregate_with_faults(FaultScript::new().inject(0, fault.clone())).await,
+            "{:?}",
+            fault
+        );
+    }
+}
+
+#[tokio::test]
+async fn helper_faults_during_continuation() {
+    // Apart from the error status, the helper has already accumulated the
+    // reports by the time its response is lost, so the retried job has the
+    // leader catch up
+    for fault in failing_faults() {
+        assert!(
+            !aggregate_with_faults(FaultScript::new().inject(1, fault.clone())).await,
+            "{:?}",
+            fault
+        );
+    }
+
+    // The helper finishes every report in its response anyway
+    assert!(aggregate_with_faults(FaultScript::new().inject(1, Fault::Finish(3))).await);
+
+    // Slow responses are waited for
+    let delay = Fault::Delay(std::time::Duration::from_millis(200));
+    assert!(
+        aggregate_with_faults(FaultScript::new().inject(0, delay.clone()).inject(1, delay)).await
+    );
+}